@@ -0,0 +1,474 @@
+use bevy::prelude::*;
+
+use crate::filter::{self, field_value};
+use crate::height::BuildingHeight;
+use crate::properties::FeatureProperties;
+use crate::{BottomRightPanels, arg_value};
+
+/// Attributes the `C` key cycles through. Any numeric attribute works from the
+/// command line.
+const FIELDS: [&str; 4] = ["height", "building:levels", "start_date", "area"];
+
+/// Jenks is quadratic in the number of values, so it runs on an even sample.
+const JENKS_SAMPLE: usize = 1000;
+
+/// Colour ramps, as sRGB stops from low to high.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Ramp {
+    Viridis,
+    Magma,
+    Blues,
+    Heat,
+    Spectral,
+}
+
+impl Ramp {
+    const ALL: [Ramp; 5] = [
+        Self::Viridis,
+        Self::Magma,
+        Self::Blues,
+        Self::Heat,
+        Self::Spectral,
+    ];
+
+    fn stops(self) -> &'static [u32] {
+        match self {
+            Self::Viridis => &[0x440154, 0x3b528b, 0x21918c, 0x5ec962, 0xfde725],
+            Self::Magma => &[0x000004, 0x51127c, 0xb73779, 0xfc8961, 0xfcfdbf],
+            Self::Blues => &[0xdeebf7, 0x9ecae1, 0x4292c6, 0x08519c, 0x08306b],
+            Self::Heat => &[0xffffb2, 0xfecc5c, 0xfd8d3c, 0xf03b20, 0xbd0026],
+            Self::Spectral => &[0x3288bd, 0x99d594, 0xffffbf, 0xfc8d59, 0xd53e4f],
+        }
+    }
+
+    /// Colour at `t` in 0..=1, blended in Oklab so steps look even.
+    pub fn sample(self, t: f32) -> Color {
+        let stops = self.stops();
+        let scaled = t.clamp(0.0, 1.0) * (stops.len() - 1) as f32;
+        let i = (scaled as usize).min(stops.len() - 2);
+        let hex = |rgb: u32| {
+            Oklaba::from(Srgba::rgb_u8(
+                (rgb >> 16) as u8,
+                (rgb >> 8) as u8,
+                rgb as u8,
+            ))
+        };
+        hex(stops[i])
+            .mix(&hex(stops[i + 1]), scaled - i as f32)
+            .into()
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Viridis => "viridis",
+            Self::Magma => "magma",
+            Self::Blues => "blues",
+            Self::Heat => "heat",
+            Self::Spectral => "spectral",
+        }
+    }
+}
+
+/// How values are split into classes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BreakMethod {
+    /// The same number of features in each class.
+    Quantile,
+    /// Classes of the same width.
+    EqualInterval,
+    /// Jenks natural breaks: classes that keep similar values together.
+    Jenks,
+}
+
+impl BreakMethod {
+    fn next(self) -> Self {
+        match self {
+            Self::Quantile => Self::EqualInterval,
+            Self::EqualInterval => Self::Jenks,
+            Self::Jenks => Self::Quantile,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Quantile => "quantile",
+            Self::EqualInterval => "equal interval",
+            Self::Jenks => "jenks",
+        }
+    }
+
+    /// Upper bounds of every class but the last, from values sorted ascending.
+    pub fn breaks(self, sorted: &[f64], classes: usize) -> Vec<f64> {
+        let (Some(&min), Some(&max)) = (sorted.first(), sorted.last()) else {
+            return Vec::new();
+        };
+        match self {
+            Self::Quantile => (1..classes)
+                .map(|i| sorted[(i * sorted.len() / classes).saturating_sub(1)])
+                .collect(),
+            Self::EqualInterval => (1..classes)
+                .map(|i| min + (max - min) * i as f64 / classes as f64)
+                .collect(),
+            Self::Jenks => {
+                let step = sorted.len().div_ceil(JENKS_SAMPLE);
+                let sample: Vec<f64> = sorted.iter().step_by(step).copied().collect();
+                jenks(&sample, classes)
+            }
+        }
+    }
+}
+
+/// Fisher–Jenks natural breaks: the split into `classes` runs that minimises the
+/// total within-class variance.
+fn jenks(sorted: &[f64], classes: usize) -> Vec<f64> {
+    let n = sorted.len();
+    let k = classes.min(n);
+    if k < 2 {
+        return Vec::new();
+    }
+    // lower[l][j]: 1-based index of the first value in the last class when the
+    // first `l` values are split into `j` classes; variance[l][j]: its cost.
+    let mut lower = vec![vec![0usize; k + 1]; n + 1];
+    let mut variance = vec![vec![f64::INFINITY; k + 1]; n + 1];
+    for j in 1..=k {
+        lower[1][j] = 1;
+        variance[1][j] = 0.0;
+    }
+    for l in 2..=n {
+        let (mut sum, mut sum_sq) = (0.0, 0.0);
+        let mut v = 0.0;
+        for m in 1..=l {
+            let first = l - m + 1;
+            let value = sorted[first - 1];
+            sum += value;
+            sum_sq += value * value;
+            v = sum_sq - sum * sum / m as f64;
+            // The values before `first` need at least one per earlier class.
+            for j in 2..=k.min(first) {
+                let cost = v + variance[first - 1][j - 1];
+                if variance[l][j] >= cost {
+                    lower[l][j] = first;
+                    variance[l][j] = cost;
+                }
+            }
+        }
+        lower[l][1] = 1;
+        variance[l][1] = v;
+    }
+    let mut breaks = vec![0.0; k - 1];
+    let mut end = n;
+    for j in (2..=k).rev() {
+        let first = lower[end][j];
+        breaks[j - 2] = sorted[first - 2];
+        end = first - 1;
+    }
+    breaks
+}
+
+/// The attribute a value is read from for colouring. `start_date` is reduced to
+/// its year, so `1923-05` and `~1923` both count as 1923.
+pub fn thematic_value(
+    feature: &FeatureProperties,
+    height: Option<&BuildingHeight>,
+    field: &str,
+) -> Option<f64> {
+    let value = field_value(feature, height, field)?;
+    if field == "start_date" {
+        let text = value.to_string();
+        let digits: Vec<char> = text.chars().collect();
+        return digits
+            .windows(4)
+            .find(|w| w.iter().all(char::is_ascii_digit))
+            .and_then(|w| w.iter().collect::<String>().parse().ok());
+    }
+    value.as_f64().filter(|v| v.is_finite())
+}
+
+// ---------------------------------------------------------------------------
+// Thematic mode
+// ---------------------------------------------------------------------------
+
+/// Colour buildings by a numeric attribute instead of their style colour.
+#[derive(Resource, Debug, Clone)]
+pub struct Choropleth {
+    /// The attribute to colour by; `None` leaves buildings as styled.
+    pub field: Option<String>,
+    pub ramp: Ramp,
+    pub method: BreakMethod,
+    pub classes: usize,
+}
+
+impl Default for Choropleth {
+    fn default() -> Self {
+        Self {
+            field: None,
+            ramp: Ramp::Viridis,
+            method: BreakMethod::Quantile,
+            classes: 5,
+        }
+    }
+}
+
+impl Choropleth {
+    /// `--color-by <attribute>` turns thematic colouring on at startup, with
+    /// `--ramp viridis|magma|blues|heat|spectral`, `--breaks quantile|equal|jenks`
+    /// and `--classes <n>`.
+    pub fn from_args(args: &[String]) -> Self {
+        let value = |flag| arg_value(args, flag);
+        let defaults = Self::default();
+        Self {
+            field: value("--color-by").map(str::to_string),
+            ramp: value("--ramp")
+                .and_then(|name| Ramp::ALL.into_iter().find(|r| r.name() == name))
+                .unwrap_or(defaults.ramp),
+            method: match value("--breaks") {
+                Some("equal") | Some("equal-interval") => BreakMethod::EqualInterval,
+                Some("jenks") => BreakMethod::Jenks,
+                _ => defaults.method,
+            },
+            classes: value("--classes")
+                .and_then(|n| n.parse().ok())
+                .unwrap_or(defaults.classes)
+                .clamp(2, 9),
+        }
+    }
+}
+
+/// Class breaks over the buildings loaded so far, for the legend.
+#[derive(Resource, Debug, Default)]
+pub struct Classes {
+    pub min: f64,
+    pub max: f64,
+    pub breaks: Vec<f64>,
+    pub counts: Vec<usize>,
+}
+
+impl Classes {
+    fn class_of(&self, value: f64) -> usize {
+        self.breaks.iter().filter(|b| value > **b).count()
+    }
+
+    fn color(&self, class: usize, ramp: Ramp) -> Color {
+        ramp.sample(class as f32 / self.breaks.len().max(1) as f32)
+    }
+}
+
+/// A building's thematic colour, used in place of its style colour. `None` while
+/// thematic colouring is off or the building has no value.
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct Thematic(pub Option<Color>);
+
+#[derive(Component)]
+struct Legend;
+
+pub struct ChoroplethPlugin;
+
+impl Plugin for ChoroplethPlugin {
+    fn build(&self, app: &mut App) {
+        let args: Vec<String> = std::env::args().collect();
+        app.insert_resource(Choropleth::from_args(&args))
+            .init_resource::<Classes>()
+            .add_systems(Startup, spawn_legend.after(crate::spawn_ui_text))
+            .add_systems(
+                Update,
+                (
+                    cycle_choropleth.run_if(not(filter::editing_filter)),
+                    classify.before(filter::apply_filter),
+                    show_legend.run_if(resource_changed::<Classes>),
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// `C` steps through the attributes (and off), `B` the break methods and `R`
+/// the colour ramps.
+fn cycle_choropleth(keys: Res<ButtonInput<KeyCode>>, mut choropleth: ResMut<Choropleth>) {
+    if keys.just_pressed(KeyCode::KeyC) {
+        let current = choropleth
+            .field
+            .as_deref()
+            .and_then(|f| FIELDS.iter().position(|x| *x == f));
+        choropleth.field = match current {
+            None if choropleth.field.is_none() => Some(FIELDS[0].to_string()),
+            Some(i) if i + 1 < FIELDS.len() => Some(FIELDS[i + 1].to_string()),
+            _ => None,
+        };
+    }
+    if keys.just_pressed(KeyCode::KeyB) {
+        choropleth.method = choropleth.method.next();
+    }
+    if keys.just_pressed(KeyCode::KeyR) {
+        let i = Ramp::ALL
+            .iter()
+            .position(|r| *r == choropleth.ramp)
+            .unwrap_or(0);
+        choropleth.ramp = Ramp::ALL[(i + 1) % Ramp::ALL.len()];
+    }
+}
+
+/// Recompute the class breaks over every loaded building when the settings
+/// change, tiles arrive or data is joined to them, and give each building its class colour.
+fn classify(
+    mut commands: Commands,
+    choropleth: Res<Choropleth>,
+    mut classes: ResMut<Classes>,
+    changed: Query<(), (Changed<FeatureProperties>, With<BuildingHeight>)>,
+    buildings: Query<(
+        Entity,
+        &FeatureProperties,
+        &BuildingHeight,
+        Option<&Thematic>,
+    )>,
+) {
+    if !choropleth.is_changed() && changed.is_empty() {
+        return;
+    }
+    let Some(field) = &choropleth.field else {
+        if choropleth.is_changed() {
+            *classes = Classes::default();
+        }
+        for (entity, _, _, thematic) in &buildings {
+            if thematic.is_some_and(|t| t.0.is_some()) {
+                commands.entity(entity).insert(Thematic(None));
+            }
+        }
+        return;
+    };
+
+    let values: Vec<(Entity, Option<f64>, Option<&Thematic>)> = buildings
+        .iter()
+        .map(|(entity, feature, height, thematic)| {
+            (
+                entity,
+                thematic_value(feature, Some(height), field),
+                thematic,
+            )
+        })
+        .collect();
+    let mut sorted: Vec<f64> = values.iter().filter_map(|(_, v, _)| *v).collect();
+    sorted.sort_by(f64::total_cmp);
+
+    let breaks = choropleth.method.breaks(&sorted, choropleth.classes);
+    let mut next = Classes {
+        min: sorted.first().copied().unwrap_or(0.0),
+        max: sorted.last().copied().unwrap_or(0.0),
+        counts: vec![0; breaks.len() + 1],
+        breaks,
+    };
+    for (entity, value, thematic) in values {
+        let color = value.map(|v| {
+            let class = next.class_of(v);
+            next.counts[class] += 1;
+            next.color(class, choropleth.ramp)
+        });
+        if thematic.map(|t| t.0) != Some(color) {
+            commands.entity(entity).insert(Thematic(color));
+        }
+    }
+    *classes = next;
+}
+
+/// The legend goes first in the bottom-right column, above the attribution.
+fn spawn_legend(mut commands: Commands, column: Single<Entity, With<BottomRightPanels>>) {
+    let legend = commands
+        .spawn((
+            Legend,
+            Node {
+                display: Display::None,
+                flex_direction: FlexDirection::Column,
+                row_gap: px(4),
+                padding: UiRect::all(px(8)),
+                ..default()
+            },
+            BackgroundColor(Color::srgba(0.12, 0.08, 0.16, 0.85)),
+        ))
+        .id();
+    commands.entity(*column).insert_children(0, &[legend]);
+}
+
+fn show_legend(
+    mut commands: Commands,
+    choropleth: Res<Choropleth>,
+    classes: Res<Classes>,
+    legend: Single<(Entity, &mut Node), With<Legend>>,
+) {
+    let (legend, mut node) = legend.into_inner();
+    commands.entity(legend).despawn_related::<Children>();
+    let Some(field) = &choropleth.field else {
+        node.display = Display::None;
+        return;
+    };
+    node.display = Display::Flex;
+
+    let font = TextFont {
+        font_size: 13.0,
+        ..default()
+    };
+    let title = format!(
+        "{field} ({}, {})   C/B/R to change",
+        choropleth.method.name(),
+        choropleth.ramp.name()
+    );
+    commands
+        .entity(legend)
+        .with_child((Text(title), font.clone()));
+    if classes.counts.iter().all(|c| *c == 0) {
+        commands
+            .entity(legend)
+            .with_child((Text("no values loaded".to_string()), font));
+        return;
+    }
+    for (class, count) in classes.counts.iter().enumerate() {
+        let low = class
+            .checked_sub(1)
+            .map_or(classes.min, |i| classes.breaks[i]);
+        let high = classes.breaks.get(class).copied().unwrap_or(classes.max);
+        commands.entity(legend).with_child((
+            Node {
+                column_gap: px(6),
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            children![
+                (
+                    Node {
+                        width: px(14),
+                        height: px(14),
+                        ..default()
+                    },
+                    BackgroundColor(classes.color(class, choropleth.ramp)),
+                ),
+                (
+                    Text(format!("{low:.0} – {high:.0}   ({count})")),
+                    font.clone(),
+                ),
+            ],
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jenks_breaks_between_clusters() {
+        let values = [1.0, 2.0, 3.0, 10.0, 11.0, 12.0, 20.0, 21.0, 22.0];
+        assert_eq!(BreakMethod::Jenks.breaks(&values, 3), [3.0, 12.0]);
+        assert_eq!(BreakMethod::Jenks.breaks(&values, 2), [12.0]);
+        // No more classes than values, and none from a single value.
+        assert_eq!(BreakMethod::Jenks.breaks(&[5.0, 7.0], 4), [5.0]);
+        assert!(BreakMethod::Jenks.breaks(&[5.0], 3).is_empty());
+        assert!(BreakMethod::Jenks.breaks(&[], 3).is_empty());
+    }
+
+    /// Large layers are sampled down to `JENKS_SAMPLE` values first.
+    #[test]
+    fn jenks_samples_large_layers() {
+        let values: Vec<f64> = (0..5000)
+            .map(|i| i as f64 * 0.001 + if i < 2500 { 0.0 } else { 100.0 })
+            .collect();
+        assert_eq!(BreakMethod::Jenks.breaks(&values, 2), [2.495]);
+    }
+}
@@ -0,0 +1,559 @@
+use std::{borrow::Cow, cmp::Ordering};
+
+use bevy::{
+    input::{
+        ButtonState,
+        keyboard::{Key, KeyboardInput},
+    },
+    prelude::*,
+};
+
+use crate::arg_value;
+use crate::choropleth::Thematic;
+use crate::height::BuildingHeight;
+use crate::isochrone::TravelTime;
+use crate::properties::{FeatureProperties, PropertyValue};
+use crate::style::{self, ActiveStyle, HexColor, Paint, StyleMaterials, Styled};
+
+/// Opacity of dimmed features.
+const DIM_OPACITY: f32 = 0.15;
+
+// ---------------------------------------------------------------------------
+// Expressions
+// ---------------------------------------------------------------------------
+
+/// A parsed filter such as `height > 50 and kind == "commercial"`.
+///
+/// Fields are tile attributes, plus `layer`, `id`, and for buildings the resolved
+/// `height`, `min_height`, `estimated` and footprint `area`. A bare field tests that it's present
+/// and not `no`/`false`/0. Combine with `and`, `or`, `not` and parentheses.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(String, CompareOp, Literal),
+    Has(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl CompareOp {
+    fn holds(self, ordering: Ordering) -> bool {
+        match self {
+            Self::Eq => ordering == Ordering::Equal,
+            Self::Ne => ordering != Ordering::Equal,
+            Self::Lt => ordering == Ordering::Less,
+            Self::Le => ordering != Ordering::Greater,
+            Self::Gt => ordering == Ordering::Greater,
+            Self::Ge => ordering != Ordering::Less,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    Number(f64),
+    String(String),
+    Bool(bool),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(f64),
+    Str(String),
+    Op(CompareOp),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '(' || c == ')' {
+            chars.next();
+            tokens.push(if c == '(' { Token::Open } else { Token::Close });
+        } else if c == '"' || c == '\'' {
+            chars.next();
+            let mut text = String::new();
+            loop {
+                match chars.next() {
+                    Some(q) if q == c => break,
+                    Some(ch) => text.push(ch),
+                    None => return Err("unterminated string".to_string()),
+                }
+            }
+            tokens.push(Token::Str(text));
+        } else if "=!<>&|".contains(c) {
+            let mut op = String::new();
+            while let Some(&ch) = chars.peek().filter(|ch| "=!<>&|".contains(**ch)) {
+                op.push(ch);
+                chars.next();
+            }
+            tokens.push(match op.as_str() {
+                "=" | "==" => Token::Op(CompareOp::Eq),
+                "!=" => Token::Op(CompareOp::Ne),
+                "<" => Token::Op(CompareOp::Lt),
+                "<=" => Token::Op(CompareOp::Le),
+                ">" => Token::Op(CompareOp::Gt),
+                ">=" => Token::Op(CompareOp::Ge),
+                "&&" => Token::And,
+                "||" => Token::Or,
+                "!" => Token::Not,
+                _ => return Err(format!("unknown operator {op:?}")),
+            });
+        } else {
+            let mut word = String::new();
+            while let Some(&ch) = chars.peek() {
+                if ch.is_whitespace() || "()\"'=!<>&|".contains(ch) {
+                    break;
+                }
+                word.push(ch);
+                chars.next();
+            }
+            tokens.push(match word.to_lowercase().as_str() {
+                "and" => Token::And,
+                "or" => Token::Or,
+                "not" => Token::Not,
+                _ => match word.parse() {
+                    Ok(n) => Token::Number(n),
+                    Err(_) => Token::Ident(word),
+                },
+            });
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Not) => Ok(Expr::Not(Box::new(self.unary()?))),
+            Some(Token::Open) => {
+                let expr = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => Err("missing )".to_string()),
+                }
+            }
+            Some(Token::Ident(field)) => match self.peek() {
+                Some(Token::Op(op)) => {
+                    let op = *op;
+                    self.next();
+                    Ok(Expr::Compare(field, op, self.literal()?))
+                }
+                _ => Ok(Expr::Has(field)),
+            },
+            Some(token) => Err(format!("expected a field, found {token:?}")),
+            None => Err("expected a field".to_string()),
+        }
+    }
+
+    fn literal(&mut self) -> Result<Literal, String> {
+        match self.next() {
+            Some(Token::Number(n)) => Ok(Literal::Number(n)),
+            Some(Token::Str(s)) => Ok(Literal::String(s)),
+            Some(Token::Ident(word)) => Ok(match word.as_str() {
+                "true" => Literal::Bool(true),
+                "false" => Literal::Bool(false),
+                // Unquoted words read as strings: `kind == park`.
+                _ => Literal::String(word),
+            }),
+            Some(token) => Err(format!("expected a value, found {token:?}")),
+            None => Err("expected a value".to_string()),
+        }
+    }
+}
+
+impl Expr {
+    pub fn parse(input: &str) -> Result<Expr, String> {
+        let mut parser = Parser {
+            tokens: tokenize(input)?,
+            pos: 0,
+        };
+        let expr = parser.or()?;
+        match parser.peek() {
+            None => Ok(expr),
+            Some(token) => Err(format!("unexpected {token:?}")),
+        }
+    }
+
+    pub fn matches(&self, feature: &FeatureProperties, height: Option<&BuildingHeight>) -> bool {
+        match self {
+            Self::And(a, b) => a.matches(feature, height) && b.matches(feature, height),
+            Self::Or(a, b) => a.matches(feature, height) || b.matches(feature, height),
+            Self::Not(e) => !e.matches(feature, height),
+            Self::Has(field) => field_value(feature, height, field).is_some_and(|v| v.is_truthy()),
+            Self::Compare(field, op, literal) => {
+                let Some(value) = field_value(feature, height, field) else {
+                    return false;
+                };
+                let ordering = match literal {
+                    Literal::Number(n) => value.as_f64().and_then(|v| v.partial_cmp(n)),
+                    Literal::Bool(b) => Some(value.is_truthy().cmp(b)),
+                    Literal::String(s) => Some(value.to_string().as_str().cmp(s.as_str())),
+                };
+                ordering.is_some_and(|o| op.holds(o))
+            }
+        }
+    }
+}
+
+/// A field of a feature: its resolved building height where it has one, so
+/// estimates count, then the built-ins, then its attributes.
+pub fn field_value(
+    feature: &FeatureProperties,
+    height: Option<&BuildingHeight>,
+    field: &str,
+) -> Option<PropertyValue> {
+    match (field, height) {
+        ("height", Some(h)) => Some(PropertyValue::Float(h.height)),
+        ("min_height", Some(h)) => Some(PropertyValue::Float(h.min_height)),
+        ("estimated", Some(h)) => Some(PropertyValue::Bool(h.estimated)),
+        ("area", Some(h)) => Some(PropertyValue::Float(h.area)),
+        ("layer", _) => Some(PropertyValue::String(feature.layer.clone())),
+        ("id", _) => feature.id.map(PropertyValue::Uint),
+        _ => feature.field(field).map(Cow::into_owned),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Applying filters
+// ---------------------------------------------------------------------------
+
+/// What happens to features matching the filter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterMode {
+    Recolour,
+    Dim,
+    Hide,
+}
+
+impl FilterMode {
+    fn next(self) -> Self {
+        match self {
+            Self::Recolour => Self::Dim,
+            Self::Dim => Self::Hide,
+            Self::Hide => Self::Recolour,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::Recolour => "recolour",
+            Self::Dim => "dim",
+            Self::Hide => "hide",
+        }
+    }
+}
+
+#[derive(Resource, Debug, Clone)]
+pub struct ActiveFilter {
+    pub expr: Option<Expr>,
+    /// The text the expression was parsed from.
+    pub source: String,
+    pub mode: FilterMode,
+    /// Colour of recoloured features.
+    pub color: Color,
+}
+
+impl Default for ActiveFilter {
+    fn default() -> Self {
+        Self {
+            expr: None,
+            source: String::new(),
+            mode: FilterMode::Recolour,
+            color: Color::srgb(1.0, 0.85, 0.2),
+        }
+    }
+}
+
+impl ActiveFilter {
+    /// `--filter <expression>` sets a filter at startup, and
+    /// `--filter-mode recolour|dim|hide` what it does.
+    pub fn from_args(args: &[String]) -> Self {
+        let value = |flag| arg_value(args, flag);
+        let mut filter = Self::default();
+        if let Some(source) = value("--filter") {
+            match Expr::parse(source) {
+                Ok(expr) => {
+                    filter.expr = Some(expr);
+                    filter.source = source.to_string();
+                }
+                Err(e) => warn!("Ignoring --filter {:?}: {}", source, e),
+            }
+        }
+        filter.mode = match value("--filter-mode") {
+            Some("dim") => FilterMode::Dim,
+            Some("hide") => FilterMode::Hide,
+            _ => FilterMode::Recolour,
+        };
+        filter
+    }
+}
+
+/// Text typed into the filter bar.
+#[derive(Resource, Debug, Default)]
+pub struct FilterInput {
+    pub editing: bool,
+    text: String,
+    error: Option<String>,
+}
+
+/// Run condition: true while the filter bar has the keyboard.
+pub fn editing_filter(input: Res<FilterInput>) -> bool {
+    input.editing
+}
+
+#[derive(Component)]
+struct FilterBar;
+
+pub struct FilterPlugin;
+
+impl Plugin for FilterPlugin {
+    fn build(&self, app: &mut App) {
+        let args: Vec<String> = std::env::args().collect();
+        app.insert_resource(ActiveFilter::from_args(&args))
+            .init_resource::<FilterInput>()
+            .add_systems(Startup, spawn_filter_bar)
+            .add_systems(
+                Update,
+                (
+                    edit_filter,
+                    show_filter_bar,
+                    apply_filter.after(style::restyle),
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// Match every feature against the filter when it or the style changes, and
+/// new, joined or re-themed features as they come. Dimmed and recoloured features use
+/// variants of their style paint (or travel time or thematic colour), so they share
+/// materials like everything else. Points can only be hidden.
+pub fn apply_filter(
+    mut commands: Commands,
+    filter: Res<ActiveFilter>,
+    style: Option<Res<ActiveStyle>>,
+    mut style_materials: ResMut<StyleMaterials>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut features: Query<FilteredFeature>,
+) {
+    let all = filter.is_changed() || style.is_some_and(|s| s.is_changed());
+    for (entity, feature, height, styled, thematic, travel, material, visibility) in &mut features {
+        let recoloured = thematic.as_ref().is_some_and(|t| t.is_changed())
+            || travel.as_ref().is_some_and(|t| t.is_changed());
+        if !all && !feature.is_changed() && !recoloured {
+            continue;
+        }
+        let matched = filter
+            .expr
+            .as_ref()
+            .is_some_and(|expr| expr.matches(&feature, height));
+
+        if let (Some(styled), Some(mut material)) = (styled, material) {
+            let color = travel.and_then(|t| t.0).or(thematic.and_then(|t| t.0));
+            let base = match color {
+                Some(color) => Paint {
+                    color: Some(HexColor(color)),
+                    ..styled.paint
+                },
+                None => styled.paint,
+            };
+            let paint = match filter.mode {
+                FilterMode::Recolour if matched => Paint {
+                    color: Some(HexColor(filter.color)),
+                    ..base
+                },
+                FilterMode::Dim if matched => Paint {
+                    opacity: Some(DIM_OPACITY),
+                    ..base
+                },
+                _ => base,
+            };
+            let handle = style_materials.get(&paint, &mut materials);
+            if material.0 != handle {
+                material.0 = handle;
+            }
+        }
+
+        let wanted = if matched && filter.mode == FilterMode::Hide {
+            Visibility::Hidden
+        } else {
+            Visibility::Inherited
+        };
+        match visibility {
+            Some(mut visibility) => {
+                visibility.set_if_neq(wanted);
+            }
+            None => {
+                commands.entity(entity).insert(wanted);
+            }
+        }
+    }
+}
+
+type FilteredFeature<'a> = (
+    Entity,
+    Ref<'a, FeatureProperties>,
+    Option<&'a BuildingHeight>,
+    Option<&'a Styled>,
+    Option<Ref<'a, Thematic>>,
+    Option<Ref<'a, TravelTime>>,
+    Option<&'a mut MeshMaterial3d<StandardMaterial>>,
+    Option<&'a mut Visibility>,
+);
+
+// ---------------------------------------------------------------------------
+// Filter bar
+// ---------------------------------------------------------------------------
+
+fn spawn_filter_bar(mut commands: Commands) {
+    commands.spawn((
+        FilterBar,
+        Text::default(),
+        TextFont {
+            font_size: 14.0,
+            ..default()
+        },
+        Node {
+            position_type: PositionType::Absolute,
+            left: px(12),
+            bottom: px(12),
+            padding: UiRect::all(px(6)),
+            ..default()
+        },
+        BackgroundColor(Color::srgba(0.12, 0.08, 0.16, 0.85)),
+        Visibility::Hidden,
+    ));
+}
+
+/// `/` opens the filter bar. While it's open, Enter applies the filter (an empty
+/// one clears it), Tab switches between recolouring, dimming and hiding matches,
+/// and Escape closes the bar.
+fn edit_filter(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut typed: MessageReader<KeyboardInput>,
+    mut input: ResMut<FilterInput>,
+    mut filter: ResMut<ActiveFilter>,
+) {
+    if !input.editing {
+        typed.clear();
+        if keys.just_pressed(KeyCode::Slash) {
+            input.editing = true;
+            input.text = filter.source.clone();
+            input.error = None;
+        }
+        return;
+    }
+    for event in typed.read() {
+        if event.state != ButtonState::Pressed {
+            continue;
+        }
+        match &event.logical_key {
+            Key::Enter => {
+                let text = input.text.trim().to_string();
+                if text.is_empty() {
+                    filter.expr = None;
+                    filter.source.clear();
+                    input.editing = false;
+                } else {
+                    match Expr::parse(&text) {
+                        Ok(expr) => {
+                            info!("Filter: {} ({})", text, filter.mode.label());
+                            filter.expr = Some(expr);
+                            filter.source = text;
+                            input.editing = false;
+                        }
+                        Err(e) => input.error = Some(e),
+                    }
+                }
+            }
+            Key::Escape => input.editing = false,
+            Key::Tab => filter.mode = filter.mode.next(),
+            Key::Backspace => {
+                input.text.pop();
+            }
+            _ => {
+                if let Some(text) = &event.text {
+                    input.text.extend(text.chars().filter(|c| !c.is_control()));
+                }
+            }
+        }
+    }
+}
+
+fn show_filter_bar(
+    input: Res<FilterInput>,
+    filter: Res<ActiveFilter>,
+    bar: Single<(&mut Text, &mut Visibility), With<FilterBar>>,
+) {
+    if !input.is_changed() && !filter.is_changed() {
+        return;
+    }
+    let (mut text, mut visibility) = bar.into_inner();
+    let mode = filter.mode.label();
+    text.0 = if input.editing {
+        let mut line = format!("filter ({mode}, tab to change): {}_", input.text);
+        if let Some(error) = &input.error {
+            line.push_str(&format!("\n{error}"));
+        }
+        line
+    } else {
+        format!("filter ({mode}): {}   / to edit", filter.source)
+    };
+    visibility.set_if_neq(if input.editing || filter.expr.is_some() {
+        Visibility::Inherited
+    } else {
+        Visibility::Hidden
+    });
+}
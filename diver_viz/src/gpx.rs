@@ -0,0 +1,665 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use bevy::{
+    ecs::system::SystemParam,
+    prelude::*,
+    tasks::{AsyncComputeTaskPool, Task, block_on, poll_once},
+};
+
+use crate::filter;
+use crate::properties::{FeatureProperties, PropertyValue};
+use crate::road_mesh::{RibbonStyle, RoadCap, RoadJoin};
+use crate::style::{ActiveStyle, StyleMaterials, StyledShape};
+use crate::sun::days_from_civil;
+use crate::terrain::{Heightmap, OnTerrain};
+use crate::{WorldCenter, lon_lat_to_world};
+
+/// Tile layer name tracks are styled under.
+pub const TRACK_LAYER: &str = "track";
+/// Ribbon width when the style doesn't set one.
+const TRACK_WIDTH: f32 = 2.5;
+/// Lift over roads and overlays, so the track is drawn on top.
+const TRACK_LIFT: f32 = 0.25;
+/// Pace assumed for tracks without timestamps, in metres per second.
+const UNTIMED_PACE: f64 = 1.4;
+const MARKER_RADIUS: f32 = 2.0;
+/// The marker floats this far above the ground.
+const MARKER_LIFT: f32 = 3.0;
+/// Playback starts this many times faster than real time.
+const DEFAULT_SPEED: f64 = 30.0;
+/// The follow camera sits this far behind and above the marker.
+const FOLLOW_DISTANCE: f32 = 60.0;
+const FOLLOW_HEIGHT: f32 = 30.0;
+/// How quickly the follow camera catches up, per second.
+const FOLLOW_STIFFNESS: f32 = 3.0;
+
+#[derive(Debug, Clone, Copy)]
+pub struct TrackPoint {
+    pub position: Vec2,
+    /// Metres above sea level.
+    pub elevation: Option<f32>,
+    /// Seconds since the Unix epoch.
+    pub time: Option<f64>,
+}
+
+/// A `<trk>` or `<rte>`, projected into the world. Each `<trkseg>` is a segment.
+#[derive(Debug, Clone, Default)]
+pub struct Track {
+    pub name: String,
+    pub segments: Vec<Vec<TrackPoint>>,
+}
+
+impl Track {
+    pub fn points(&self) -> impl Iterator<Item = &TrackPoint> {
+        self.segments.iter().flatten()
+    }
+
+    /// Seconds from the first point to each point. Uses the timestamps when every
+    /// point has one, otherwise assumes a steady walking pace.
+    pub fn clock(&self) -> Vec<f64> {
+        let points: Vec<&TrackPoint> = self.points().collect();
+        let times: Option<Vec<f64>> = points.iter().map(|p| p.time).collect();
+        match times {
+            Some(times) if times.len() >= 2 && times[times.len() - 1] > times[0] => {
+                // GPS loggers occasionally step back in time; hold the clock still instead.
+                let mut latest = 0.0;
+                times
+                    .iter()
+                    .map(|t| {
+                        latest = f64::max(latest, t - times[0]);
+                        latest
+                    })
+                    .collect()
+            }
+            _ => {
+                let mut elapsed = 0.0;
+                std::iter::once(0.0)
+                    .chain(points.windows(2).map(|w| {
+                        elapsed += w[0].position.distance(w[1].position) as f64 / UNTIMED_PACE;
+                        elapsed
+                    }))
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Read a GPX document's tracks and routes, projected with `lon_lat_to_world` so
+/// they line up with the tile features `RoadProcessor` puts through `tile_to_world`.
+/// Waypoints are skipped.
+pub fn read_gpx(name: &str, text: &str, center: Vec2) -> Result<Vec<Track>, String> {
+    let doc = roxmltree::Document::parse(text).map_err(|e| e.to_string())?;
+    let point = |node: roxmltree::Node| {
+        let lat: f64 = node.attribute("lat")?.trim().parse().ok()?;
+        let lon: f64 = node.attribute("lon")?.trim().parse().ok()?;
+        Some(TrackPoint {
+            position: lon_lat_to_world(lon, lat, center),
+            elevation: child(node, "ele").and_then(|e| e.parse().ok()),
+            time: child(node, "time").and_then(parse_time),
+        })
+    };
+
+    let mut tracks = Vec::new();
+    for node in doc.descendants() {
+        let segments: Vec<Vec<TrackPoint>> = match node.tag_name().name() {
+            "trk" => node
+                .children()
+                .filter(|c| c.tag_name().name() == "trkseg")
+                .map(|seg| {
+                    seg.children()
+                        .filter(|c| c.tag_name().name() == "trkpt")
+                        .filter_map(point)
+                        .collect()
+                })
+                .collect(),
+            "rte" => vec![
+                node.children()
+                    .filter(|c| c.tag_name().name() == "rtept")
+                    .filter_map(point)
+                    .collect(),
+            ],
+            _ => continue,
+        };
+        let segments: Vec<_> = segments.into_iter().filter(|s| s.len() >= 2).collect();
+        if segments.is_empty() {
+            continue;
+        }
+        tracks.push(Track {
+            name: child(node, "name").map_or_else(
+                || match tracks.len() {
+                    0 => name.to_string(),
+                    n => format!("{name} {}", n + 1),
+                },
+                str::to_string,
+            ),
+            segments,
+        });
+    }
+    if tracks.is_empty() {
+        return Err("no tracks or routes".to_string());
+    }
+    Ok(tracks)
+}
+
+/// Trimmed text of the first child element called `tag`.
+fn child<'a>(node: roxmltree::Node<'a, '_>, tag: &str) -> Option<&'a str> {
+    node.children()
+        .find(|c| c.tag_name().name() == tag)
+        .and_then(|c| c.text())
+        .map(str::trim)
+}
+
+/// RFC 3339 timestamps as GPX writes them, e.g. `2024-06-01T17:04:05Z` or
+/// `2024-06-01T11:04:05.250-06:00`, to seconds since the Unix epoch.
+fn parse_time(text: &str) -> Option<f64> {
+    let (date, rest) = text.split_once(['T', 't', ' '])?;
+    let mut date = date.splitn(3, '-').map(|p| p.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    let (time, zone) = rest.split_at(rest.find(['Z', 'z', '+', '-']).unwrap_or(rest.len()));
+    let mut time = time.splitn(3, ':').map(|p| p.parse::<f64>().ok());
+    let (hour, minute) = (time.next()??, time.next()??);
+    let second = time.next().unwrap_or(Some(0.0))?;
+    let offset = match zone.split_at_checked(1) {
+        Some((sign @ ("+" | "-"), hhmm)) => {
+            let digits = hhmm.replace(':', "");
+            let hours: f64 = digits.get(0..2)?.parse().ok()?;
+            let minutes: f64 = digits.get(2..4).map_or(Some(0.0), |m| m.parse().ok())?;
+            let offset = hours * 3600.0 + minutes * 60.0;
+            if sign == "-" { -offset } else { offset }
+        }
+        // UTC, or no zone at all, which GPX doesn't allow but some apps write anyway.
+        _ => 0.0,
+    };
+    Some(
+        days_from_civil(year, month, day) as f64 * 86_400.0
+            + hour * 3600.0
+            + minute * 60.0
+            + second
+            - offset,
+    )
+}
+
+/// `h:mm:ss`, or `m:ss` under an hour.
+fn format_duration(seconds: f64) -> String {
+    let s = seconds.max(0.0) as u64;
+    match s / 3600 {
+        0 => format!("{}:{:02}", s / 60, s % 60),
+        h => format!("{}:{:02}:{:02}", h, s / 60 % 60, s % 60),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Loading and spawning
+// ---------------------------------------------------------------------------
+
+/// A track's ribbon, tagged with the file it came from so loading that file again
+/// replaces it.
+#[derive(Component, Debug, Clone)]
+pub struct TrackRibbon {
+    pub file: String,
+}
+
+/// Where a GPX file comes from.
+#[derive(Debug, Clone)]
+pub enum GpxSource {
+    /// A document already in memory, as dropped on the web page.
+    Text(String),
+    File(PathBuf),
+}
+
+/// GPX files waiting for the world to exist, by name.
+#[derive(Resource, Debug, Default)]
+pub struct GpxQueue(pub Vec<(String, GpxSource)>);
+
+type ReadGpx = Task<Result<Vec<Track>, String>>;
+
+/// Files being read in the background.
+#[derive(Resource, Default)]
+struct GpxTasks(Vec<(String, ReadGpx)>);
+
+/// A loaded track, flattened for playback.
+#[derive(Debug, Clone)]
+pub struct LoadedTrack {
+    pub name: String,
+    pub file: String,
+    pub points: Vec<Vec2>,
+    /// Seconds from the start to each point.
+    pub clock: Vec<f64>,
+    /// When the first point was recorded, for tracks with timestamps.
+    pub start: Option<f64>,
+    /// Metres above sea level at each point, where the file has them.
+    pub elevations: Vec<Option<f32>>,
+}
+
+impl LoadedTrack {
+    fn new(file: &str, track: &Track) -> Self {
+        let points: Vec<&TrackPoint> = track.points().collect();
+        let clock = track.clock();
+        let timed = points.iter().all(|p| p.time.is_some()) && clock.last() > Some(&0.0);
+        Self {
+            name: track.name.clone(),
+            file: file.to_string(),
+            points: points.iter().map(|p| p.position).collect(),
+            start: points.first().and_then(|p| p.time).filter(|_| timed),
+            elevations: points.iter().map(|p| p.elevation).collect(),
+            clock,
+        }
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.clock.last().copied().unwrap_or_default()
+    }
+
+    /// Where the track was `seconds` after it started, the direction it was heading
+    /// and the elevation there, interpolated between the points either side.
+    pub fn at(&self, seconds: f64) -> (Vec2, Vec2, Option<f32>) {
+        let next = self
+            .clock
+            .partition_point(|c| *c <= seconds)
+            .clamp(1, self.points.len() - 1);
+        let prev = next - 1;
+        let span = self.clock[next] - self.clock[prev];
+        let f = if span > 0.0 {
+            ((seconds - self.clock[prev]) / span).clamp(0.0, 1.0) as f32
+        } else {
+            0.0
+        };
+        let (a, b) = (self.points[prev], self.points[next]);
+        let elevation = match (self.elevations[prev], self.elevations[next]) {
+            (Some(ea), Some(eb)) => Some(ea + (eb - ea) * f),
+            (ea, eb) => ea.or(eb),
+        };
+        (a.lerp(b, f), (b - a).normalize_or_zero(), elevation)
+    }
+}
+
+/// Tracks loaded so far, in load order.
+#[derive(Resource, Debug, Default)]
+pub struct Tracks(pub Vec<LoadedTrack>);
+
+/// Playing a track back: the marker moves along it by timestamp.
+#[derive(Resource, Debug)]
+pub struct Playback {
+    /// Index into [`Tracks`].
+    pub track: usize,
+    /// Seconds since the track started.
+    pub clock: f64,
+    /// Track seconds per real second.
+    pub speed: f64,
+    pub playing: bool,
+    /// Keep the camera behind the marker.
+    pub follow: bool,
+    /// Last direction of travel, kept through stops.
+    heading: Vec2,
+}
+
+impl Default for Playback {
+    fn default() -> Self {
+        Self {
+            track: 0,
+            clock: 0.0,
+            speed: DEFAULT_SPEED,
+            playing: false,
+            follow: false,
+            heading: Vec2::Y,
+        }
+    }
+}
+
+/// The sphere that moves along the track during playback.
+#[derive(Component)]
+struct TrackMarker;
+
+#[derive(Component)]
+struct PlaybackText;
+
+/// Everything that decides how track ribbons look.
+#[derive(SystemParam)]
+struct TrackStyle<'w> {
+    style: Res<'w, ActiveStyle>,
+    style_materials: ResMut<'w, StyleMaterials>,
+    materials: ResMut<'w, Assets<StandardMaterial>>,
+    meshes: ResMut<'w, Assets<Mesh>>,
+}
+
+pub struct GpxPlugin;
+
+impl Plugin for GpxPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GpxQueue>()
+            .init_resource::<GpxTasks>()
+            .init_resource::<Tracks>()
+            .init_resource::<Playback>()
+            .add_systems(Startup, (queue_gpx_args, spawn_marker, spawn_playback_text))
+            .add_systems(
+                Update,
+                (
+                    receive_dropped_files,
+                    read_gpx_files.run_if(resource_exists::<WorldCenter>),
+                    spawn_tracks.run_if(resource_exists::<ActiveStyle>),
+                    playback_controls.run_if(not(filter::editing_filter)),
+                    advance_playback,
+                    show_playback_text,
+                )
+                    .chain(),
+            )
+            .add_systems(
+                PostUpdate,
+                follow_marker.before(TransformSystems::Propagate),
+            );
+    }
+}
+
+pub fn is_gpx(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("gpx"))
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_stem()
+        .map_or_else(|| "track".to_string(), |s| s.to_string_lossy().to_string())
+}
+
+/// `--gpx <path>`, as many times as you like, loads tracks from disk at startup.
+fn queue_gpx_args(mut queue: ResMut<GpxQueue>) {
+    let args: Vec<String> = std::env::args().collect();
+    for pair in args.windows(2) {
+        if pair[0] == "--gpx" {
+            let path = PathBuf::from(&pair[1]);
+            queue.0.push((file_name(&path), GpxSource::File(path)));
+        }
+    }
+}
+
+/// `.gpx` files dropped on the window; everything else is left for the overlays.
+fn receive_dropped_files(mut drops: MessageReader<FileDragAndDrop>, mut queue: ResMut<GpxQueue>) {
+    for drop in drops.read() {
+        if let FileDragAndDrop::DroppedFile { path_buf, .. } = drop
+            && is_gpx(path_buf)
+        {
+            queue
+                .0
+                .push((file_name(path_buf), GpxSource::File(path_buf.clone())));
+        }
+    }
+    #[cfg(target_arch = "wasm32")]
+    queue.0.extend(
+        crate::overlay::web_drop::take(|name| is_gpx(Path::new(name)))
+            .into_iter()
+            .map(|(name, text)| (file_name(Path::new(&name)), GpxSource::Text(text))),
+    );
+}
+
+fn read_gpx_files(
+    center: Res<WorldCenter>,
+    mut queue: ResMut<GpxQueue>,
+    mut tasks: ResMut<GpxTasks>,
+) {
+    let center = center.0;
+    for (name, source) in queue.0.drain(..) {
+        info!("Reading GPX {}", name);
+        let task_name = name.clone();
+        let task = AsyncComputeTaskPool::get().spawn(async move {
+            let text = match source {
+                GpxSource::Text(text) => text,
+                GpxSource::File(path) => {
+                    std::fs::read_to_string(&path).map_err(|e| e.to_string())?
+                }
+            };
+            read_gpx(&task_name, &text, center)
+        });
+        tasks.0.push((name, task));
+    }
+}
+
+/// Draw finished tracks as ribbons draped over the ground, styled by rules for the
+/// `track` layer, and cue the first new one up for playback.
+fn spawn_tracks(
+    mut commands: Commands,
+    mut tasks: ResMut<GpxTasks>,
+    mut style: TrackStyle,
+    mut tracks: ResMut<Tracks>,
+    mut playback: ResMut<Playback>,
+    existing: Query<(Entity, &TrackRibbon)>,
+) {
+    let mut pending = Vec::new();
+    for (file, mut task) in tasks.0.drain(..) {
+        let Some(result) = block_on(poll_once(&mut task)) else {
+            pending.push((file, task));
+            continue;
+        };
+        let loaded = match result {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                warn!("Couldn't read GPX {}: {}", file, e);
+                continue;
+            }
+        };
+        for (entity, ribbon) in &existing {
+            if ribbon.file == file {
+                commands.entity(entity).despawn();
+            }
+        }
+        tracks.0.retain(|t| t.file != file);
+        playback.track = tracks.0.len();
+        playback.clock = 0.0;
+
+        for track in &loaded {
+            let mut tags = HashMap::new();
+            tags.insert(
+                "name".to_string(),
+                PropertyValue::String(track.name.clone()),
+            );
+            tags.insert(TRACK_LAYER.to_string(), PropertyValue::String(file.clone()));
+            let properties = FeatureProperties {
+                layer: TRACK_LAYER.to_string(),
+                tags,
+                ..default()
+            };
+            let styled = style.style.0.styled(&properties);
+            for segment in &track.segments {
+                let shape = StyledShape::Ribbon {
+                    points: segment.iter().map(|p| p.position).collect(),
+                    width: TRACK_WIDTH,
+                    style: RibbonStyle {
+                        join: RoadJoin::Round,
+                        start_cap: RoadCap::Round,
+                        end_cap: RoadCap::Round,
+                    },
+                    deck: None,
+                };
+                let Some(mesh) = shape.mesh(&styled.paint) else {
+                    continue;
+                };
+                commands.spawn((
+                    TrackRibbon { file: file.clone() },
+                    Mesh3d(style.meshes.add(mesh)),
+                    MeshMaterial3d(
+                        style
+                            .style_materials
+                            .get(&styled.paint, &mut style.materials),
+                    ),
+                    Transform::from_xyz(0.0, TRACK_LIFT, 0.0),
+                    OnTerrain::Drape,
+                    properties.clone(),
+                    styled.clone(),
+                    shape,
+                ));
+            }
+            let track = LoadedTrack::new(&file, track);
+            info!(
+                "✓ Loaded track {} from {}: {} points over {}{}",
+                track.name,
+                file,
+                track.points.len(),
+                format_duration(track.duration()),
+                if track.start.is_some() {
+                    ""
+                } else {
+                    " (no timestamps, at walking pace)"
+                }
+            );
+            tracks.0.push(track);
+        }
+    }
+    tasks.0 = pending;
+}
+
+fn spawn_marker(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.spawn((
+        TrackMarker,
+        Mesh3d(meshes.add(Sphere::new(MARKER_RADIUS))),
+        MeshMaterial3d(materials.add(StandardMaterial {
+            base_color: Color::srgb(1.0, 0.35, 0.1),
+            emissive: LinearRgba::rgb(4.0, 1.0, 0.2),
+            ..default()
+        })),
+        Transform::default(),
+        Visibility::Hidden,
+    ));
+}
+
+fn spawn_playback_text(mut commands: Commands) {
+    commands.spawn((
+        PlaybackText,
+        Text::default(),
+        TextFont {
+            font_size: 14.0,
+            ..default()
+        },
+        Node {
+            position_type: PositionType::Absolute,
+            left: px(12),
+            bottom: px(52),
+            padding: UiRect::all(px(6)),
+            ..default()
+        },
+        BackgroundColor(Color::srgba(0.12, 0.08, 0.16, 0.85)),
+        Visibility::Hidden,
+    ));
+}
+
+/// P plays and pauses, F follows the marker with the camera, - and = halve and
+/// double the speed, and N moves on to the next track.
+fn playback_controls(
+    keys: Res<ButtonInput<KeyCode>>,
+    tracks: Res<Tracks>,
+    mut playback: ResMut<Playback>,
+) {
+    let Some(track) = tracks.0.get(playback.track) else {
+        return;
+    };
+    if keys.just_pressed(KeyCode::KeyP) {
+        if !playback.playing && playback.clock >= track.duration() {
+            playback.clock = 0.0;
+        }
+        playback.playing = !playback.playing;
+    }
+    if keys.just_pressed(KeyCode::KeyF) {
+        playback.follow = !playback.follow;
+    }
+    if keys.just_pressed(KeyCode::Minus) {
+        playback.speed = (playback.speed / 2.0).max(1.0);
+    }
+    if keys.just_pressed(KeyCode::Equal) {
+        playback.speed = (playback.speed * 2.0).min(1024.0);
+    }
+    if keys.just_pressed(KeyCode::KeyN) {
+        playback.track = (playback.track + 1) % tracks.0.len();
+        playback.clock = 0.0;
+    }
+}
+
+/// Run the playback clock and put the marker where the track was at that time.
+fn advance_playback(
+    time: Res<Time>,
+    tracks: Res<Tracks>,
+    heightmap: Option<Res<Heightmap>>,
+    mut playback: ResMut<Playback>,
+    marker: Single<(&mut Transform, &mut Visibility), With<TrackMarker>>,
+) {
+    let (mut transform, mut visibility) = marker.into_inner();
+    let Some(track) = tracks.0.get(playback.track) else {
+        *visibility = Visibility::Hidden;
+        return;
+    };
+    *visibility = Visibility::Inherited;
+    if playback.playing {
+        playback.clock += time.delta_secs_f64() * playback.speed;
+        if playback.clock >= track.duration() {
+            playback.clock = track.duration();
+            playback.playing = false;
+        }
+    }
+    let (position, heading, _) = track.at(playback.clock);
+    if heading != Vec2::ZERO {
+        playback.heading = heading;
+    }
+    let ground = heightmap.map_or(0.0, |h| h.sample(position));
+    transform.translation = Vec3::new(position.x, ground + MARKER_LIFT, position.y);
+}
+
+fn show_playback_text(
+    tracks: Res<Tracks>,
+    playback: Res<Playback>,
+    text: Single<(&mut Text, &mut Visibility), With<PlaybackText>>,
+) {
+    let (mut text, mut visibility) = text.into_inner();
+    let Some(track) = tracks.0.get(playback.track) else {
+        *visibility = Visibility::Hidden;
+        return;
+    };
+    *visibility = Visibility::Inherited;
+    let (_, _, elevation) = track.at(playback.clock);
+    let mut line = format!(
+        "{} {}  {} / {}  ×{}",
+        if playback.playing { "▶" } else { "⏸" },
+        track.name,
+        format_duration(playback.clock),
+        format_duration(track.duration()),
+        playback.speed,
+    );
+    if let Some(start) = track.start {
+        let of_day = (start + playback.clock).rem_euclid(86_400.0);
+        line.push_str(&format!("  {} UTC", format_time_of_day(of_day)));
+    }
+    if let Some(elevation) = elevation {
+        line.push_str(&format!("  {elevation:.0} m"));
+    }
+    if playback.follow {
+        line.push_str("  (following)");
+    }
+    text.0 = line;
+}
+
+/// `hh:mm:ss`, for a time of day.
+fn format_time_of_day(seconds: f64) -> String {
+    let s = seconds as u64;
+    format!("{:02}:{:02}:{:02}", s / 3600, s / 60 % 60, s % 60)
+}
+
+/// Ease the camera to a spot behind and above the marker, looking at it. Runs after
+/// the free camera so it wins while following.
+fn follow_marker(
+    time: Res<Time>,
+    tracks: Res<Tracks>,
+    playback: Res<Playback>,
+    marker: Single<&Transform, (With<TrackMarker>, Without<Camera3d>)>,
+    mut camera: Query<&mut Transform, With<Camera3d>>,
+) {
+    if !playback.follow || tracks.0.get(playback.track).is_none() {
+        return;
+    }
+    let behind = Vec3::new(-playback.heading.x, 0.0, -playback.heading.y) * FOLLOW_DISTANCE;
+    let goal = marker.translation + behind + Vec3::Y * FOLLOW_HEIGHT;
+    let t = 1.0 - (-FOLLOW_STIFFNESS * time.delta_secs()).exp();
+    for mut cam in &mut camera {
+        cam.translation = cam.translation.lerp(goal, t);
+        cam.look_at(marker.translation, Vec3::Y);
+    }
+}
@@ -0,0 +1,257 @@
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::{Building, Footprint};
+
+/// Where a building's height can come from, tried in the order given by
+/// [`BuildingHeights::chain`].
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum HeightSource {
+    /// The `height` tag, in metres.
+    Tag,
+    /// `building:levels` times the floor height, plus any `roof:height`.
+    Levels,
+    /// A guess from the footprint, building type, landuse and neighbouring heights.
+    Estimate,
+    /// [`BuildingHeights::default_height`], so every building gets something.
+    Default,
+}
+
+/// How building heights and bases are resolved from tile attributes. Set in
+/// the style file's `heights`; anything left out keeps its default.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BuildingHeights {
+    pub chain: Vec<HeightSource>,
+    /// Metres per storey, for `building:levels` and `min_level`.
+    pub floor_height: f64,
+    pub default_height: f64,
+}
+
+impl Default for BuildingHeights {
+    fn default() -> Self {
+        Self {
+            chain: vec![
+                HeightSource::Tag,
+                HeightSource::Levels,
+                HeightSource::Estimate,
+                HeightSource::Default,
+            ],
+            floor_height: 3.0,
+            default_height: 10.0,
+        }
+    }
+}
+
+/// The height-related attributes of a building feature.
+#[derive(Debug, Clone, Default)]
+pub struct HeightTags {
+    pub height: Option<f64>,
+    pub min_height: Option<f64>,
+    pub levels: Option<f64>,
+    pub min_level: Option<f64>,
+    pub roof_height: Option<f64>,
+}
+
+impl BuildingHeights {
+    /// Top of the building (or building part) above ground, from the first source
+    /// in the chain that has a value, along with the source it came from. Estimated
+    /// and default heights stand on the part's base, so raised parts keep a height.
+    pub fn top(&self, tags: &HeightTags, estimate: Option<f64>) -> Option<(f64, HeightSource)> {
+        self.chain.iter().find_map(|&source| {
+            self.height_from(source, tags, estimate)
+                .map(|h| (h, source))
+        })
+    }
+
+    /// Height from the feature's own attributes only, ignoring estimates and the default.
+    fn tagged_top(&self, tags: &HeightTags) -> Option<f64> {
+        self.chain
+            .iter()
+            .filter(|s| matches!(s, HeightSource::Tag | HeightSource::Levels))
+            .find_map(|&source| self.height_from(source, tags, None))
+    }
+
+    fn height_from(
+        &self,
+        source: HeightSource,
+        tags: &HeightTags,
+        estimate: Option<f64>,
+    ) -> Option<f64> {
+        match source {
+            HeightSource::Tag => tags.height,
+            HeightSource::Levels => tags
+                .levels
+                .map(|l| l * self.floor_height + tags.roof_height.unwrap_or(0.0)),
+            HeightSource::Estimate => estimate.map(|h| self.base(tags) + h),
+            HeightSource::Default => Some(self.base(tags) + self.default_height),
+        }
+    }
+
+    /// Bottom of the building part: `min_height`, else `min_level` storeys up, else the ground.
+    pub fn base(&self, tags: &HeightTags) -> f64 {
+        tags.min_height
+            .or_else(|| tags.min_level.map(|l| l * self.floor_height))
+            .unwrap_or(0.0)
+    }
+}
+
+/// Resolved vertical extent and footprint of a spawned building, kept so tools
+/// can tell real heights from estimated ones.
+#[derive(Component, Reflect, Debug, Clone, Copy)]
+#[reflect(Component)]
+pub struct BuildingHeight {
+    pub height: f32,
+    pub min_height: f32,
+    pub estimated: bool,
+    /// Footprint area in m².
+    pub area: f32,
+}
+
+impl BuildingHeight {
+    /// `None` if no height was resolved, e.g. a chain without `Default`.
+    pub fn of(footprint: &Footprint) -> Option<Self> {
+        Some(Self {
+            height: footprint.height? as f32,
+            min_height: footprint.min_height.unwrap_or(0.0) as f32,
+            estimated: footprint.height_estimated,
+            area: footprint_area(&footprint.geometry) as f32,
+        })
+    }
+}
+
+/// Neighbours within this many metres inform an estimate.
+const NEIGHBOUR_RADIUS_M: f64 = 75.0;
+/// Estimates vary by up to this fraction either way, so blocks don't look stamped out.
+const ESTIMATE_JITTER: f64 = 0.15;
+
+/// Resolve the height of every building in a tile, estimating the ones whose tags
+/// don't say. Estimates only look at the tile's own buildings and landuse, and are
+/// seeded from the feature id so they come out the same on every load.
+pub fn resolve_heights(buildings: &mut [Building], landuse: &[Building], config: &BuildingHeights) {
+    let centroids: Vec<Vec2> = buildings.iter().map(footprint_centroid).collect();
+    let known: Vec<Option<f64>> = buildings
+        .iter()
+        .map(|b| config.tagged_top(&b.height_tags))
+        .collect();
+    let radius = NEIGHBOUR_RADIUS_M as f32;
+
+    for (i, building) in buildings.iter_mut().enumerate() {
+        let estimate =
+            (known[i].is_none() && config.chain.contains(&HeightSource::Estimate)).then(|| {
+                let mut neighbours: Vec<f64> = centroids
+                    .iter()
+                    .zip(&known)
+                    .filter(|(c, _)| c.distance(centroids[i]) <= radius)
+                    .filter_map(|(_, h)| *h)
+                    .collect();
+                neighbours.sort_by(f64::total_cmp);
+                let landuse_kind = landuse
+                    .iter()
+                    .find(|l| {
+                        l.geometry
+                            .first()
+                            .is_some_and(|ring| point_in_ring(centroids[i], ring))
+                    })
+                    .and_then(|l| l.kind.as_deref());
+                estimate_height(
+                    building,
+                    footprint_area(&building.geometry),
+                    landuse_kind,
+                    &neighbours,
+                    config.floor_height,
+                )
+            });
+
+        if let Some((height, source)) = config.top(&building.height_tags, estimate) {
+            building.height = Some(height);
+            building.height_estimated = source == HeightSource::Estimate;
+        }
+        building.min_height = Some(config.base(&building.height_tags));
+    }
+}
+
+fn estimate_height(
+    building: &Building,
+    area_m2: f64,
+    landuse_kind: Option<&str>,
+    neighbour_heights: &[f64],
+    floor_height: f64,
+) -> f64 {
+    let levels_by_type = building.kind.as_deref().and_then(|kind| match kind {
+        "garage" | "garages" | "shed" | "carport" | "hut" | "kiosk" => Some(1.0),
+        "house" | "detached" | "semidetached_house" | "terrace" | "bungalow" => Some(2.0),
+        "retail" | "supermarket" => Some(1.5),
+        "industrial" | "warehouse" => Some(2.5),
+        "school" | "university" | "hospital" | "civic" | "public" => Some(3.0),
+        "church" | "cathedral" => Some(4.0),
+        "apartments" | "residential" | "dormitory" | "hotel" => Some(4.0),
+        "commercial" | "office" => Some(5.0),
+        _ => None,
+    });
+    let levels_by_landuse = match landuse_kind {
+        Some("residential") => 2.0,
+        Some("commercial") | Some("retail") => 4.0,
+        Some("industrial") => 2.0,
+        _ => 2.5,
+    };
+    let mut levels = levels_by_type.unwrap_or(levels_by_landuse);
+
+    // Sheds are small and towers have big footprints; scale gently with area.
+    if area_m2 < 30.0 {
+        levels = 1.0;
+    } else {
+        levels *= (1.0 + 0.5 * (area_m2 / 250.0).log10()).clamp(0.6, 2.5);
+    }
+    let mut height = levels.max(1.0) * floor_height;
+
+    // Lean towards what the block around it actually looks like.
+    if !neighbour_heights.is_empty() {
+        let median = neighbour_heights[neighbour_heights.len() / 2];
+        let n = neighbour_heights.len() as f64;
+        let weight = (n / (n + 3.0)).min(0.7);
+        height = height * (1.0 - weight) + median * weight;
+    }
+
+    let seed = building
+        .id
+        .unwrap_or_else(|| footprint_centroid(building).to_array().map(f32::to_bits)[0] as u64);
+    let jitter = (splitmix64(seed) as f64 / u64::MAX as f64) * 2.0 - 1.0;
+    (height * (1.0 + jitter * ESTIMATE_JITTER)).max(floor_height)
+}
+
+fn footprint_centroid(building: &Building) -> Vec2 {
+    match building.geometry.first() {
+        Some(ring) if !ring.is_empty() => ring.iter().copied().sum::<Vec2>() / ring.len() as f32,
+        _ => Vec2::ZERO,
+    }
+}
+
+/// Area of the outer ring in m², with holes ignored.
+fn footprint_area(geometry: &[Vec<Vec2>]) -> f64 {
+    let Some(ring) = geometry.first() else {
+        return 0.0;
+    };
+    let twice: f64 = (0..ring.len())
+        .map(|i| ring[i].perp_dot(ring[(i + 1) % ring.len()]) as f64)
+        .sum();
+    twice.abs() / 2.0
+}
+
+pub fn point_in_ring(p: Vec2, ring: &[Vec2]) -> bool {
+    let mut inside = false;
+    for i in 0..ring.len() {
+        let (a, b) = (ring[i], ring[(i + 1) % ring.len()]);
+        if (a.y > p.y) != (b.y > p.y) && p.x < a.x + (p.y - a.y) / (b.y - a.y) * (b.x - a.x) {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+fn splitmix64(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
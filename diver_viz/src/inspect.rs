@@ -0,0 +1,206 @@
+use bevy::{
+    camera::primitives::Aabb,
+    picking::mesh_picking::ray_cast::{MeshRayCast, MeshRayCastSettings},
+    prelude::*,
+    window::{CursorGrabMode, CursorOptions, PrimaryWindow},
+};
+
+use crate::gpx::TRACK_LAYER;
+use crate::height::BuildingHeight;
+use crate::isochrone;
+use crate::overlay::OVERLAY_LAYER;
+use crate::properties::FeatureProperties;
+use crate::route::{self, ROUTE_LAYER};
+use crate::shadow;
+
+const HIGHLIGHT_COLOR: Color = Color::srgb(1.0, 0.92, 0.3);
+
+/// The feature last clicked on, if any.
+#[derive(Resource, Debug, Default)]
+pub struct Selection {
+    /// The mesh that was hit, outlined while selected.
+    pub mesh: Option<Entity>,
+    /// The entity holding the feature's properties: the mesh, or its parent for points.
+    pub feature: Option<Entity>,
+}
+
+#[derive(Component)]
+struct InspectPanel;
+
+#[derive(Component)]
+struct InspectText;
+
+pub struct InspectPlugin;
+
+impl Plugin for InspectPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Selection>()
+            .add_systems(Startup, spawn_inspect_panel)
+            .add_systems(
+                Update,
+                (
+                    pick_feature.run_if(
+                        not(route::routing)
+                            .and(not(isochrone::placing_origin))
+                            .and(not(shadow::placing_study)),
+                    ),
+                    show_selection.run_if(resource_changed::<Selection>),
+                    outline_selection,
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// Link to the feature on openstreetmap.org. Assumes the tiles carry plain OSM ids;
+/// points come from nodes and everything else from ways.
+pub fn osm_url(feature: &FeatureProperties) -> Option<String> {
+    let kind = match feature.layer.as_str() {
+        "pois" | "places" => "node",
+        _ => "way",
+    };
+    feature
+        .id
+        .map(|id| format!("https://www.openstreetmap.org/{kind}/{id}"))
+}
+
+fn spawn_inspect_panel(mut commands: Commands) {
+    commands.spawn((
+        InspectPanel,
+        Node {
+            display: Display::None,
+            position_type: PositionType::Absolute,
+            right: px(12),
+            top: px(12),
+            max_width: px(420),
+            padding: UiRect::all(px(10)),
+            ..default()
+        },
+        BackgroundColor(Color::srgba(0.12, 0.08, 0.16, 0.85)),
+        children![(
+            InspectText,
+            Text::default(),
+            TextFont {
+                font_size: 13.0,
+                ..default()
+            },
+        )],
+    ));
+}
+
+/// With the cursor free, a left click selects the feature under it, or clears the
+/// selection when there's nothing there.
+fn pick_feature(
+    mouse: Res<ButtonInput<MouseButton>>,
+    cursor_options: Single<&CursorOptions>,
+    window: Single<&Window, With<PrimaryWindow>>,
+    camera: Single<(&Camera, &GlobalTransform), With<Camera3d>>,
+    mut ray_cast: MeshRayCast,
+    entities: Query<(Option<&FeatureProperties>, Option<&ChildOf>)>,
+    mut selection: ResMut<Selection>,
+) {
+    if !mouse.just_pressed(MouseButton::Left) || cursor_options.grab_mode != CursorGrabMode::None {
+        return;
+    }
+    let Some(cursor) = window.cursor_position() else {
+        return;
+    };
+    let (camera, camera_tf) = *camera;
+    let Ok(ray) = camera.viewport_to_world(camera_tf, cursor) else {
+        return;
+    };
+    // Only the nearest hit counts; anything behind it can't be seen.
+    let hit = ray_cast
+        .cast_ray(ray, &MeshRayCastSettings::default())
+        .first()
+        .map(|(entity, _)| *entity);
+    let feature = hit.and_then(|mesh| {
+        let (properties, parent) = entities.get(mesh).ok()?;
+        match (properties, parent) {
+            (Some(_), _) => Some(mesh),
+            (None, Some(parent)) => entities
+                .get(parent.parent())
+                .ok()?
+                .0
+                .map(|_| parent.parent()),
+            (None, None) => None,
+        }
+    });
+    *selection = Selection {
+        mesh: feature.and(hit),
+        feature,
+    };
+}
+
+/// Fill the panel with the selected feature's properties.
+fn show_selection(
+    selection: Res<Selection>,
+    features: Query<(&FeatureProperties, Option<&BuildingHeight>)>,
+    mut panel: Single<&mut Node, With<InspectPanel>>,
+    mut text: Single<&mut Text, With<InspectText>>,
+) {
+    let Some((feature, height)) = selection.feature.and_then(|e| features.get(e).ok()) else {
+        panel.display = Display::None;
+        return;
+    };
+    let mut lines = vec![match feature.id {
+        Some(id) => format!("{} #{}", feature.layer, id),
+        None => feature.layer.clone(),
+    }];
+    if let Some(name) = feature.get("name") {
+        lines.push(format!("name: {name}"));
+    }
+    // The top above ground, as `height` means in tags and filters.
+    if let Some(height) = height {
+        lines.push(format!(
+            "height: {:.1} m{}",
+            height.height,
+            if height.estimated { " (estimated)" } else { "" }
+        ));
+        if height.min_height > 0.0 {
+            lines.push(format!("min_height: {:.1} m", height.min_height));
+        }
+    }
+    if ![OVERLAY_LAYER, TRACK_LAYER, ROUTE_LAYER].contains(&feature.layer.as_str()) {
+        lines.push(format!("tile: {}", feature.tile));
+    }
+    if let Some(url) = osm_url(feature) {
+        info!("Open on OpenStreetMap: {}", url);
+        lines.push(url);
+    }
+    lines.push(String::new());
+    let mut tags: Vec<_> = feature.tags.iter().collect();
+    tags.sort_by_key(|(key, _)| *key);
+    lines.extend(
+        tags.into_iter()
+            .map(|(key, value)| format!("{key}: {value}")),
+    );
+    if !feature.joined.is_empty() {
+        lines.push(String::new());
+        lines.push("joined:".to_string());
+        let mut joined: Vec<_> = feature.joined.iter().collect();
+        joined.sort_by_key(|(key, _)| *key);
+        lines.extend(
+            joined
+                .into_iter()
+                .map(|(key, value)| format!("{key}: {value}")),
+        );
+    }
+
+    text.0 = lines.join("\n");
+    panel.display = Display::Flex;
+}
+
+/// Outline the selected mesh's bounds.
+fn outline_selection(
+    selection: Res<Selection>,
+    meshes: Query<(&Aabb, &GlobalTransform)>,
+    mut gizmos: Gizmos,
+) {
+    let Some((aabb, global)) = selection.mesh.and_then(|e| meshes.get(e).ok()) else {
+        return;
+    };
+    let bounds = Transform::from_translation(aabb.center.into())
+        .with_scale(Vec3::from(aabb.half_extents) * 2.0);
+    gizmos.cube(*global * bounds, HIGHLIGHT_COLOR);
+}
@@ -0,0 +1,508 @@
+use std::collections::{BinaryHeap, HashMap};
+
+use bevy::{
+    ecs::system::SystemParam,
+    prelude::*,
+    window::{CursorGrabMode, CursorOptions, PrimaryWindow},
+};
+use geo::{ConcaveHull, MultiPoint, Point};
+
+use crate::choropleth::Ramp;
+use crate::filter;
+use crate::height::BuildingHeight;
+use crate::properties::FeatureProperties;
+use crate::road_graph::{EdgeHit, EdgeId, RoadGraph};
+use crate::road_mesh::{RibbonStyle, RoadCap, RoadJoin, road_ribbon_mesh};
+use crate::route::{self, Open, Profile, RouteRequest, format_length, point_along};
+use crate::shadow::{self, ShadowStudy};
+use crate::style::StyledShape;
+use crate::terrain::{Heightmap, OnTerrain, ground_point};
+
+/// Clicks further than this from any road don't place an origin.
+const SNAP_DISTANCE: f32 = 100.0;
+/// Buildings take their travel time from a street at most this far away.
+const ACCESS_DISTANCE: f32 = 80.0;
+/// Travel time is split into this many colours.
+const BANDS: usize = 5;
+/// Streets are coloured in pieces at most this long.
+const PIECE_LENGTH: f32 = 10.0;
+const STREET_WIDTH: f32 = 5.0;
+/// Lift over roads, so the coloured streets draw on top.
+const STREET_LIFT: f32 = 0.35;
+const HULL_WIDTH: f32 = 2.0;
+/// How tightly the hull hugs the reachable streets; higher is closer to convex.
+const HULL_CONCAVITY: f64 = 2.0;
+const MINUTES_STEP: f32 = 5.0;
+const MAX_MINUTES: f32 = 60.0;
+
+/// Travel times out from a point on the graph, up to a limit.
+pub struct Reach {
+    /// Seconds to each node; infinite past the limit.
+    cost: Vec<f32>,
+    start: EdgeHit,
+    profile: Profile,
+    limit: f32,
+}
+
+impl Reach {
+    /// Dijkstra out from `start` until everything within `limit` seconds is found.
+    pub fn new(graph: &RoadGraph, start: EdgeHit, profile: Profile, limit: f32) -> Self {
+        let oneway = profile.respects_oneway();
+        let mut cost = vec![f32::INFINITY; graph.nodes.len()];
+        let mut open = BinaryHeap::new();
+        let edge = &graph.edges[start.edge];
+        if let Some(speed) = profile.speed(edge) {
+            for (node, forward, distance) in [
+                (edge.to, true, edge.length - start.along),
+                (edge.from, false, start.along),
+            ] {
+                let seconds = distance / speed;
+                if (!oneway || edge.allows(forward)) && seconds < cost[node] && seconds <= limit {
+                    cost[node] = seconds;
+                    open.push(Open {
+                        estimate: seconds,
+                        node,
+                    });
+                }
+            }
+        }
+        while let Some(Open {
+            estimate: seconds,
+            node,
+        }) = open.pop()
+        {
+            if seconds > cost[node] {
+                continue;
+            }
+            for (edge, next) in graph.outgoing(node, oneway) {
+                let Some(speed) = profile.speed(&graph.edges[edge]) else {
+                    continue;
+                };
+                let seconds = seconds + graph.edges[edge].length / speed;
+                if seconds < cost[next] && seconds <= limit {
+                    cost[next] = seconds;
+                    open.push(Open {
+                        estimate: seconds,
+                        node: next,
+                    });
+                }
+            }
+        }
+        Self {
+            cost,
+            start,
+            profile,
+            limit,
+        }
+    }
+
+    /// Seconds to the point `along` metres down an edge, if it's within the limit.
+    pub fn time_at(&self, graph: &RoadGraph, edge: EdgeId, along: f32) -> Option<f32> {
+        let e = &graph.edges[edge];
+        let speed = self.profile.speed(e)?;
+        let oneway = self.profile.respects_oneway();
+        let allowed = |forward: bool| !oneway || e.allows(forward);
+        let mut best = f32::INFINITY;
+        if allowed(true) {
+            best = best.min(self.cost[e.from] + along / speed);
+        }
+        if allowed(false) {
+            best = best.min(self.cost[e.to] + (e.length - along) / speed);
+        }
+        if edge == self.start.edge && allowed(along >= self.start.along) {
+            best = best.min((along - self.start.along).abs() / speed);
+        }
+        (best <= self.limit).then_some(best)
+    }
+}
+
+/// A building's colour by travel time, used in place of its style and thematic
+/// colour. `None` while no walkshed is shown or the building is out of reach.
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct TravelTime(pub Option<Color>);
+
+/// The walkshed being asked for: while `active`, a click places its origin.
+#[derive(Resource, Debug)]
+pub struct IsochroneRequest {
+    pub active: bool,
+    pub profile: Profile,
+    pub minutes: f32,
+    pub origin: Option<Vec2>,
+    /// Draw a concave hull around the reachable streets.
+    pub hull: bool,
+}
+
+impl Default for IsochroneRequest {
+    fn default() -> Self {
+        Self {
+            active: false,
+            profile: Profile::Walking,
+            minutes: 10.0,
+            origin: None,
+            hull: true,
+        }
+    }
+}
+
+/// What the last walkshed reached.
+#[derive(Resource, Debug, Default)]
+pub struct Walkshed {
+    /// Street pieces in each travel time band, nearest first.
+    pub bands: Vec<Vec<Vec<Vec2>>>,
+    pub hull: Option<Vec<Vec2>>,
+    /// Metres of street reached.
+    pub street_length: f32,
+    /// Buildings reached, and their band.
+    pub buildings: Vec<(Entity, usize)>,
+    /// Why there's nothing to show.
+    pub error: Option<String>,
+}
+
+/// Leave walkshed mode when routing starts, so a click only does one thing.
+pub fn stop_placing(request: &mut IsochroneRequest) {
+    if request.active {
+        *request = IsochroneRequest {
+            active: false,
+            origin: None,
+            ..*request
+        };
+    }
+}
+
+/// Run condition: clicks place the walkshed's origin instead of inspecting features.
+pub fn placing_origin(request: Res<IsochroneRequest>) -> bool {
+    request.active
+}
+
+fn band_color(band: usize) -> Color {
+    // Nearest is brightest.
+    Ramp::Viridis.sample(1.0 - (band as f32 + 0.5) / BANDS as f32)
+}
+
+/// The coloured streets and hull outline.
+#[derive(Component)]
+struct WalkshedPart;
+
+#[derive(Component)]
+struct WalkshedText;
+
+/// What a walkshed is drawn with.
+#[derive(SystemParam)]
+struct WalkshedDraw<'w, 's> {
+    commands: Commands<'w, 's>,
+    meshes: ResMut<'w, Assets<Mesh>>,
+    materials: ResMut<'w, Assets<StandardMaterial>>,
+    parts: Query<'w, 's, Entity, With<WalkshedPart>>,
+    buildings: Query<'w, 's, (Entity, Option<&'static TravelTime>), With<BuildingHeight>>,
+}
+
+pub struct IsochronePlugin;
+
+impl Plugin for IsochronePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<IsochroneRequest>()
+            .init_resource::<Walkshed>()
+            .add_systems(Startup, spawn_walkshed_text)
+            .add_systems(
+                Update,
+                (
+                    isochrone_controls.run_if(not(filter::editing_filter)),
+                    pick_origin.run_if(placing_origin),
+                    find_walkshed.run_if(
+                        resource_changed::<IsochroneRequest>.or(resource_changed::<RoadGraph>),
+                    ),
+                    draw_walkshed.run_if(resource_changed::<Walkshed>),
+                    show_walkshed_text.run_if(
+                        resource_changed::<IsochroneRequest>.or(resource_changed::<Walkshed>),
+                    ),
+                )
+                    .chain()
+                    .before(filter::apply_filter),
+            );
+    }
+}
+
+/// I starts placing a walkshed, or stops and clears it; M switches between walking
+/// and cycling, , and . take 5 minutes off or add them, and H shows or hides the hull.
+fn isochrone_controls(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut request: ResMut<IsochroneRequest>,
+    mut route: ResMut<RouteRequest>,
+    mut shadow: ResMut<ShadowStudy>,
+) {
+    if keys.just_pressed(KeyCode::KeyI) {
+        *request = IsochroneRequest {
+            active: !request.active,
+            origin: None,
+            ..*request
+        };
+        if request.active {
+            route::stop_routing(&mut route);
+            shadow::stop_placing(&mut shadow);
+        }
+    }
+    if !request.active {
+        return;
+    }
+    if keys.just_pressed(KeyCode::KeyM) {
+        request.profile = match request.profile {
+            Profile::Walking => Profile::Cycling,
+            _ => Profile::Walking,
+        };
+    }
+    if keys.just_pressed(KeyCode::Comma) {
+        request.minutes = (request.minutes - MINUTES_STEP).max(MINUTES_STEP);
+    }
+    if keys.just_pressed(KeyCode::Period) {
+        request.minutes = (request.minutes + MINUTES_STEP).min(MAX_MINUTES);
+    }
+    if keys.just_pressed(KeyCode::KeyH) {
+        request.hull = !request.hull;
+    }
+}
+
+fn pick_origin(
+    mouse: Res<ButtonInput<MouseButton>>,
+    window: Single<(&Window, &CursorOptions), With<PrimaryWindow>>,
+    camera: Single<(&Camera, &GlobalTransform), With<Camera3d>>,
+    heightmap: Option<Res<Heightmap>>,
+    mut request: ResMut<IsochroneRequest>,
+) {
+    let (window, cursor_options) = *window;
+    if !mouse.just_pressed(MouseButton::Left) || cursor_options.grab_mode != CursorGrabMode::None {
+        return;
+    }
+    let (camera, camera_tf) = *camera;
+    let origin = window
+        .cursor_position()
+        .and_then(|cursor| camera.viewport_to_world(camera_tf, cursor).ok())
+        .and_then(|ray| ground_point(ray, heightmap.as_deref()));
+    if origin.is_some() {
+        request.origin = origin;
+    }
+}
+
+/// Work out which streets and buildings are within reach, cutting streets into
+/// short pieces so each piece gets the colour of its travel time.
+fn find_walkshed(
+    request: Res<IsochroneRequest>,
+    graph: Res<RoadGraph>,
+    buildings: Query<(Entity, &FeatureProperties, &StyledShape), With<BuildingHeight>>,
+    mut walkshed: ResMut<Walkshed>,
+) {
+    *walkshed = Walkshed::default();
+    let Some(origin) = request.origin.filter(|_| request.active) else {
+        return;
+    };
+    let Some(start) = graph.nearest_edge(origin, SNAP_DISTANCE) else {
+        walkshed.error = Some(format!("no road within {SNAP_DISTANCE:.0} m of that point"));
+        return;
+    };
+    let limit = request.minutes * 60.0;
+    let reach = Reach::new(&graph, start, request.profile, limit);
+    let band_of = |seconds: f32| ((seconds / limit * BANDS as f32) as usize).min(BANDS - 1);
+
+    walkshed.bands = vec![Vec::new(); BANDS];
+    for (id, edge) in graph.edges.iter().enumerate() {
+        // Cut at every vertex and every piece length, so pieces follow the road.
+        let mut cuts: Vec<f32> = (0..)
+            .map(|i| i as f32 * PIECE_LENGTH)
+            .take_while(|d| *d < edge.length)
+            .collect();
+        let mut along = 0.0;
+        for w in edge.points.windows(2) {
+            along += w[0].distance(w[1]);
+            cuts.push(along.min(edge.length));
+        }
+        cuts.sort_by(f32::total_cmp);
+        cuts.dedup();
+
+        let mut current: Option<(usize, Vec<Vec2>)> = None;
+        for w in cuts.windows(2) {
+            let (a, b) = (w[0], w[1]);
+            let band = reach.time_at(&graph, id, (a + b) / 2.0).map(band_of);
+            let continues = matches!((&current, band), (Some((c, _)), Some(band)) if *c == band);
+            if !continues && let Some((c, piece)) = current.take() {
+                walkshed.bands[c].push(piece);
+            }
+            let Some(band) = band else {
+                continue;
+            };
+            walkshed.street_length += b - a;
+            let end = point_along(&edge.points, b);
+            match &mut current {
+                Some((_, piece)) => piece.push(end),
+                None => current = Some((band, vec![point_along(&edge.points, a), end])),
+            }
+        }
+        if let Some((c, piece)) = current {
+            walkshed.bands[c].push(piece);
+        }
+    }
+
+    for (entity, feature, shape) in &buildings {
+        let StyledShape::Extrusion { footprint, .. } = shape else {
+            continue;
+        };
+        let Some(ring) = footprint.geometry.first().filter(|r| !r.is_empty()) else {
+            continue;
+        };
+        if feature.layer != "buildings" {
+            continue;
+        }
+        let center = ring.iter().copied().sum::<Vec2>() / ring.len() as f32;
+        let Some(hit) = graph.nearest_edge(center, ACCESS_DISTANCE) else {
+            continue;
+        };
+        let Some(seconds) = reach.time_at(&graph, hit.edge, hit.along) else {
+            continue;
+        };
+        // The last stretch from the street is on foot.
+        let seconds = seconds + hit.distance / Profile::Walking.max_speed();
+        if seconds <= limit {
+            walkshed.buildings.push((entity, band_of(seconds)));
+        }
+    }
+
+    if request.hull {
+        let points: MultiPoint<f64> = walkshed
+            .bands
+            .iter()
+            .flatten()
+            .flatten()
+            .map(|p| Point::new(p.x as f64, p.y as f64))
+            .collect();
+        if points.0.len() >= 3 {
+            let hull = points.concave_hull(HULL_CONCAVITY);
+            walkshed.hull = Some(
+                hull.exterior()
+                    .coords()
+                    .map(|c| Vec2::new(c.x as f32, c.y as f32))
+                    .collect(),
+            );
+        }
+    }
+}
+
+/// Replace the drawn walkshed: one ribbon mesh per band, the hull outline, and
+/// travel time colours on the buildings.
+fn draw_walkshed(walkshed: Res<Walkshed>, mut draw: WalkshedDraw) {
+    for entity in &draw.parts {
+        draw.commands.entity(entity).despawn();
+    }
+    let ribbon = |points: &[Vec2], width: f32| {
+        road_ribbon_mesh(
+            points,
+            width,
+            RibbonStyle {
+                join: RoadJoin::Round,
+                start_cap: RoadCap::Round,
+                end_cap: RoadCap::Round,
+            },
+        )
+    };
+    for (band, pieces) in walkshed.bands.iter().enumerate() {
+        let mut merged: Option<Mesh> = None;
+        for mesh in pieces.iter().filter_map(|p| ribbon(p, STREET_WIDTH)) {
+            match &mut merged {
+                Some(merged) => {
+                    if let Err(e) = merged.merge(&mesh) {
+                        warn!("Couldn't merge walkshed street: {}", e);
+                    }
+                }
+                None => merged = Some(mesh),
+            }
+        }
+        let Some(mesh) = merged else {
+            continue;
+        };
+        draw.commands.spawn((
+            WalkshedPart,
+            Mesh3d(draw.meshes.add(mesh)),
+            MeshMaterial3d(draw.materials.add(StandardMaterial {
+                base_color: band_color(band),
+                unlit: true,
+                ..default()
+            })),
+            Transform::from_xyz(0.0, STREET_LIFT, 0.0),
+            OnTerrain::Drape,
+        ));
+    }
+
+    // The hull's straight sides are cut short so the outline drapes over the ground.
+    if let Some(hull) = &walkshed.hull {
+        let length: f32 = hull.windows(2).map(|w| w[0].distance(w[1])).sum();
+        let outline: Vec<Vec2> = (0..=(length / PIECE_LENGTH).ceil() as usize)
+            .map(|i| point_along(hull, (i as f32 * PIECE_LENGTH).min(length)))
+            .collect();
+        if let Some(mesh) = ribbon(&outline, HULL_WIDTH) {
+            draw.commands.spawn((
+                WalkshedPart,
+                Mesh3d(draw.meshes.add(mesh)),
+                MeshMaterial3d(draw.materials.add(StandardMaterial {
+                    base_color: Color::WHITE,
+                    unlit: true,
+                    ..default()
+                })),
+                Transform::from_xyz(0.0, STREET_LIFT, 0.0),
+                OnTerrain::Drape,
+            ));
+        }
+    }
+
+    let reached: HashMap<Entity, usize> = walkshed.buildings.iter().copied().collect();
+    for (entity, travel) in &draw.buildings {
+        let color = reached.get(&entity).map(|band| band_color(*band));
+        if travel.and_then(|t| t.0) != color {
+            draw.commands.entity(entity).insert(TravelTime(color));
+        }
+    }
+}
+
+fn spawn_walkshed_text(mut commands: Commands) {
+    commands.spawn((
+        WalkshedText,
+        Text::default(),
+        TextFont {
+            font_size: 14.0,
+            ..default()
+        },
+        Node {
+            position_type: PositionType::Absolute,
+            left: px(12),
+            bottom: px(132),
+            padding: UiRect::all(px(6)),
+            ..default()
+        },
+        BackgroundColor(Color::srgba(0.12, 0.08, 0.16, 0.85)),
+        Visibility::Hidden,
+    ));
+}
+
+fn show_walkshed_text(
+    request: Res<IsochroneRequest>,
+    walkshed: Res<Walkshed>,
+    text: Single<(&mut Text, &mut Visibility), With<WalkshedText>>,
+) {
+    let (mut text, mut visibility) = text.into_inner();
+    if !request.active {
+        *visibility = Visibility::Hidden;
+        return;
+    }
+    *visibility = Visibility::Inherited;
+    let profile = request.profile.label();
+    let minutes = request.minutes;
+    text.0 = match (&walkshed.error, request.origin) {
+        (Some(e), _) => format!("{e}  (click somewhere else)"),
+        (None, None) => format!("{minutes:.0} min {profile}: click a starting point"),
+        (None, Some(_)) => format!(
+            "{:.0} min {}: {} of street, {} buildings, {:.0} min per colour  \
+             (M, , and . to change, H for the outline)",
+            minutes,
+            profile,
+            format_length(walkshed.street_length),
+            walkshed.buildings.len(),
+            minutes / BANDS as f32,
+        ),
+    };
+}
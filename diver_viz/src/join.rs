@@ -0,0 +1,357 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadState, io::Reader},
+    prelude::*,
+};
+use parquet::{
+    file::reader::{FileReader, SerializedFileReader},
+    record::Field,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::arg_value;
+use crate::properties::{FeatureProperties, PropertyValue};
+use crate::style;
+
+/// Key columns tried, in order, when none is given.
+const KEY_COLUMNS: [&str; 4] = ["osm_id", "way_id", "osm_way_id", "id"];
+
+/// A table of extra attributes keyed by OSM id, from a CSV or Parquet file.
+#[derive(Asset, TypePath, Debug, Default)]
+pub struct JoinTable {
+    /// The column the ids came from.
+    pub key: String,
+    pub rows: HashMap<u64, HashMap<String, PropertyValue>>,
+    /// Rows whose key wasn't an id.
+    pub skipped: usize,
+}
+
+impl JoinTable {
+    fn from_rows(
+        header: Vec<String>,
+        rows: impl Iterator<Item = Vec<Option<PropertyValue>>>,
+        key: Option<&str>,
+    ) -> Result<Self, String> {
+        let key_index = match key {
+            Some(key) => header.iter().position(|h| h == key),
+            None => KEY_COLUMNS
+                .iter()
+                .find_map(|k| header.iter().position(|h| h.eq_ignore_ascii_case(k))),
+        }
+        .ok_or_else(|| match key {
+            Some(key) => format!("no column named {key:?}"),
+            None => format!("no id column, expected one of {KEY_COLUMNS:?}"),
+        })?;
+
+        let mut table = Self {
+            key: header[key_index].clone(),
+            ..default()
+        };
+        for row in rows {
+            let Some(id) = row
+                .get(key_index)
+                .and_then(|v| v.as_ref())
+                .and_then(parse_id)
+            else {
+                table.skipped += 1;
+                continue;
+            };
+            let columns = header
+                .iter()
+                .zip(row)
+                .enumerate()
+                .filter(|(i, _)| *i != key_index)
+                .filter_map(|(_, (name, value))| Some((name.clone(), value?)))
+                .collect();
+            table.rows.insert(id, columns);
+        }
+        Ok(table)
+    }
+
+    fn parse_csv(bytes: &[u8], key: Option<&str>) -> Result<Self, String> {
+        let mut reader = csv::Reader::from_reader(bytes);
+        let header = reader
+            .headers()
+            .map_err(|e| e.to_string())?
+            .iter()
+            .map(|h| h.trim().to_string())
+            .collect();
+        let mut records = Vec::new();
+        for record in reader.records() {
+            let record = record.map_err(|e| e.to_string())?;
+            records.push(record.iter().map(csv_value).collect());
+        }
+        Self::from_rows(header, records.into_iter(), key)
+    }
+
+    fn parse_parquet(bytes: Vec<u8>, key: Option<&str>) -> Result<Self, String> {
+        let reader =
+            SerializedFileReader::new(bytes::Bytes::from(bytes)).map_err(|e| e.to_string())?;
+        let header = reader
+            .metadata()
+            .file_metadata()
+            .schema_descr()
+            .root_schema()
+            .get_fields()
+            .iter()
+            .map(|f| f.name().to_string())
+            .collect();
+        let mut records = Vec::new();
+        for row in reader.get_row_iter(None).map_err(|e| e.to_string())? {
+            let row = row.map_err(|e| e.to_string())?;
+            records.push(
+                row.get_column_iter()
+                    .map(|(_, f)| parquet_value(f))
+                    .collect(),
+            );
+        }
+        Self::from_rows(header, records.into_iter(), key)
+    }
+}
+
+/// OSM ids may be written as `123`, `123.0`, `w123` or `way/123`.
+fn parse_id(value: &PropertyValue) -> Option<u64> {
+    match value {
+        PropertyValue::Uint(v) => Some(*v),
+        PropertyValue::String(s) => s
+            .trim()
+            .trim_start_matches(|c: char| !c.is_ascii_digit())
+            .parse()
+            .ok(),
+        other => other
+            .as_f64()
+            .filter(|v| *v >= 0.0 && v.fract() == 0.0)
+            .map(|v| v as u64),
+    }
+}
+
+fn csv_value(field: &str) -> Option<PropertyValue> {
+    let field = field.trim();
+    if field.is_empty() {
+        return None;
+    }
+    Some(if let Ok(v) = field.parse() {
+        PropertyValue::Int(v)
+    } else if let Ok(v) = field.parse() {
+        PropertyValue::Double(v)
+    } else {
+        PropertyValue::String(field.to_string())
+    })
+}
+
+fn parquet_value(field: &Field) -> Option<PropertyValue> {
+    Some(match field {
+        Field::Null => return None,
+        Field::Bool(v) => PropertyValue::Bool(*v),
+        Field::Byte(v) => PropertyValue::Int(*v as i64),
+        Field::Short(v) => PropertyValue::Int(*v as i64),
+        Field::Int(v) => PropertyValue::Int(*v as i64),
+        Field::Long(v) => PropertyValue::Int(*v),
+        Field::UByte(v) => PropertyValue::Uint(*v as u64),
+        Field::UShort(v) => PropertyValue::Uint(*v as u64),
+        Field::UInt(v) => PropertyValue::Uint(*v as u64),
+        Field::ULong(v) => PropertyValue::Uint(*v),
+        Field::Float(v) => PropertyValue::Float(*v),
+        Field::Double(v) => PropertyValue::Double(*v),
+        Field::Str(v) => PropertyValue::String(v.clone()),
+        other => PropertyValue::String(other.to_string()),
+    })
+}
+
+/// Which column holds the ids; found by name when unset.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct JoinSettings {
+    pub key: Option<String>,
+}
+
+#[derive(Default, TypePath)]
+pub struct JoinTableLoader;
+
+impl AssetLoader for JoinTableLoader {
+    type Asset = JoinTable;
+    type Settings = JoinSettings;
+    type Error = std::io::Error;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        settings: &JoinSettings,
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<JoinTable, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let key = settings.key.as_deref();
+        let parquet = load_context
+            .path()
+            .path()
+            .extension()
+            .is_some_and(|e| e == "parquet");
+        if parquet {
+            JoinTable::parse_parquet(bytes, key)
+        } else {
+            JoinTable::parse_csv(&bytes, key)
+        }
+        .map_err(std::io::Error::other)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["csv", "parquet"]
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Joining
+// ---------------------------------------------------------------------------
+
+/// Where joined data comes from and what it's joined to.
+#[derive(Resource, Debug, Clone)]
+pub struct JoinSource {
+    /// Asset path of the table.
+    pub path: String,
+    pub key: Option<String>,
+    /// Only features in this tile layer are joined.
+    pub layer: String,
+}
+
+impl JoinSource {
+    /// `--join <file.csv|file.parquet>` (under `assets/`) joins a table at startup,
+    /// `--join-key <column>` names its id column and `--join-layer <layer>` picks
+    /// the features it's joined to, buildings by default.
+    pub fn from_args(args: &[String]) -> Option<Self> {
+        let value = |flag| arg_value(args, flag).map(str::to_string);
+        Some(Self {
+            path: value("--join")?,
+            key: value("--join-key"),
+            layer: value("--join-layer").unwrap_or_else(|| "buildings".to_string()),
+        })
+    }
+}
+
+#[derive(Resource)]
+struct JoinHandle(Handle<JoinTable>);
+
+/// How the join is going, over the tiles loaded so far.
+#[derive(Resource, Debug, Default)]
+pub struct JoinReport {
+    /// Ids of rows that found a feature.
+    pub matched_rows: HashSet<u64>,
+    pub matched_features: usize,
+    pub unmatched_features: usize,
+}
+
+pub struct JoinPlugin;
+
+impl Plugin for JoinPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<JoinTable>()
+            .init_asset_loader::<JoinTableLoader>()
+            .init_resource::<JoinReport>();
+        let args: Vec<String> = std::env::args().collect();
+        if let Some(source) = JoinSource::from_args(&args) {
+            app.insert_resource(source)
+                .add_systems(Startup, load_join_table)
+                .add_systems(
+                    Update,
+                    (report_join_failure, join_features.before(style::restyle)),
+                );
+        }
+    }
+}
+
+fn load_join_table(mut commands: Commands, source: Res<JoinSource>, assets: Res<AssetServer>) {
+    info!("Loading join table from {}", source.path);
+    let key = source.key.clone();
+    let handle = assets.load_with_settings(&source.path, move |s: &mut JoinSettings| {
+        s.key = key.clone();
+    });
+    commands.insert_resource(JoinHandle(handle));
+}
+
+fn report_join_failure(
+    source: Res<JoinSource>,
+    handle: Res<JoinHandle>,
+    assets: Res<AssetServer>,
+    mut reported: Local<bool>,
+) {
+    if let Some(LoadState::Failed(err)) = assets.get_load_state(&handle.0)
+        && !*reported
+    {
+        warn!("Couldn't load join table {}: {}", source.path, err);
+        *reported = true;
+    }
+}
+
+/// Copy table columns onto matching features: all of them when the table loads
+/// or changes, otherwise just the ones from newly loaded tiles. Logs how many
+/// features and rows went unmatched as it goes.
+fn join_features(
+    source: Res<JoinSource>,
+    handle: Res<JoinHandle>,
+    tables: Res<Assets<JoinTable>>,
+    mut events: MessageReader<AssetEvent<JoinTable>>,
+    mut report: ResMut<JoinReport>,
+    mut features: Query<&mut FeatureProperties>,
+) {
+    let reloaded = events.read().any(|event| match event {
+        AssetEvent::LoadedWithDependencies { id } | AssetEvent::Modified { id } => {
+            *id == handle.0.id()
+        }
+        _ => false,
+    });
+    let Some(table) = tables.get(&handle.0) else {
+        return;
+    };
+    if reloaded {
+        info!(
+            "✓ Loaded {} rows keyed by {} from {}{}",
+            table.rows.len(),
+            table.key,
+            source.path,
+            match table.skipped {
+                0 => String::new(),
+                n => format!(", skipped {n} without an id"),
+            }
+        );
+        *report = JoinReport::default();
+    }
+
+    let (mut matched, mut unmatched) = (0, 0);
+    for mut feature in &mut features {
+        if !reloaded && !feature.is_added() {
+            continue;
+        }
+        if feature.layer != source.layer {
+            continue;
+        }
+        let row = feature.id.and_then(|id| table.rows.get(&id));
+        match row {
+            Some(row) => {
+                feature.joined = row.clone();
+                report.matched_rows.insert(feature.id.unwrap_or_default());
+                matched += 1;
+            }
+            None => {
+                if !feature.joined.is_empty() {
+                    feature.joined.clear();
+                }
+                unmatched += 1;
+            }
+        }
+    }
+    if matched + unmatched == 0 {
+        return;
+    }
+    report.matched_features += matched;
+    report.unmatched_features += unmatched;
+    info!(
+        "✓ Joined {} of {} {} to {}; {} rows still unmatched, {} {} without a row so far",
+        matched,
+        matched + unmatched,
+        source.layer,
+        source.path,
+        table.rows.len() - report.matched_rows.len(),
+        report.unmatched_features,
+        source.layer,
+    );
+}
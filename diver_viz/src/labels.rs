@@ -0,0 +1,220 @@
+use std::cmp::Ordering;
+
+use bevy::prelude::*;
+
+use crate::Road;
+use crate::terrain::Heightmap;
+
+/// Road pieces shorter than this don't get a label of their own.
+const MIN_LABEL_ROAD_LENGTH: f32 = 40.0;
+/// The same name isn't repeated closer together than this.
+const MIN_REPEAT_DISTANCE: f32 = 250.0;
+/// Labels sit this far above the road surface.
+const LABEL_LIFT: f32 = 2.0;
+/// Screen-space gap kept between labels, in logical pixels.
+const LABEL_PADDING: f32 = 4.0;
+const LABEL_FONT_SIZE: f32 = 13.0;
+
+/// A street name pinned to a point along its road. Drawn as screen-space text
+/// turned to follow the road, and shown or hidden every frame by [`place_labels`].
+#[derive(Component, Debug, Clone)]
+pub struct RoadLabel {
+    /// World position on the ground (x, z).
+    pub anchor: Vec2,
+    /// Direction of the road at the anchor.
+    pub direction: Vec2,
+    /// Height of the road surface at the anchor.
+    pub height: f32,
+    /// Bigger roads win when labels collide and stay visible from further away.
+    pub rank: u8,
+}
+
+impl RoadLabel {
+    /// How far away the label is still drawn, in metres.
+    fn max_distance(&self) -> f32 {
+        match self.rank {
+            3 => 900.0,
+            2 => 600.0,
+            1 => 350.0,
+            _ => 150.0,
+        }
+    }
+}
+
+pub struct LabelsPlugin;
+
+impl Plugin for LabelsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(PostUpdate, place_labels.after(TransformSystems::Propagate));
+    }
+}
+
+fn road_rank(kind: &str) -> u8 {
+    match kind {
+        "highway" => 3,
+        "major_road" => 2,
+        "minor_road" => 1,
+        _ => 0,
+    }
+}
+
+/// Pick label positions for named roads: the middle of each long enough piece,
+/// longest first, skipping any too close to one already placed for the same name.
+pub fn road_labels(roads: &[Road], height: impl Fn(&Road) -> f32) -> Vec<(String, RoadLabel)> {
+    let mut candidates: Vec<(f32, &Road)> = roads
+        .iter()
+        .filter(|road| road.name.is_some() && !road.tunnel)
+        .map(|road| (polyline_length(&road.points), road))
+        .filter(|(length, _)| *length >= MIN_LABEL_ROAD_LENGTH)
+        .collect();
+    candidates.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(Ordering::Equal));
+
+    let mut labels: Vec<(String, RoadLabel)> = Vec::new();
+    for (length, road) in candidates {
+        let Some(name) = &road.name else {
+            continue;
+        };
+        let Some((anchor, direction)) = point_along(&road.points, length / 2.0) else {
+            continue;
+        };
+        let repeated = labels.iter().any(|(other, label)| {
+            other == name && label.anchor.distance(anchor) < MIN_REPEAT_DISTANCE
+        });
+        if repeated {
+            continue;
+        }
+        labels.push((
+            name.clone(),
+            RoadLabel {
+                anchor,
+                direction,
+                height: height(road),
+                rank: road_rank(&road.kind),
+            },
+        ));
+    }
+    labels
+}
+
+/// The text entity for a label, hidden until [`place_labels`] finds room for it.
+pub fn label_bundle(name: String, label: RoadLabel) -> impl Bundle {
+    (
+        Text::new(name),
+        TextFont {
+            font_size: LABEL_FONT_SIZE,
+            ..default()
+        },
+        TextColor(Color::WHITE),
+        TextShadow {
+            offset: Vec2::splat(1.0),
+            color: Color::srgba(0.1, 0.05, 0.15, 0.9),
+        },
+        Node {
+            position_type: PositionType::Absolute,
+            ..default()
+        },
+        UiTransform::default(),
+        Visibility::Hidden,
+        label,
+    )
+}
+
+fn polyline_length(points: &[Vec2]) -> f32 {
+    points.windows(2).map(|w| w[0].distance(w[1])).sum()
+}
+
+/// Position and direction `distance` metres along a polyline.
+fn point_along(points: &[Vec2], distance: f32) -> Option<(Vec2, Vec2)> {
+    let mut walked = 0.0;
+    for w in points.windows(2) {
+        let seg = w[0].distance(w[1]);
+        if seg > 0.0 && walked + seg >= distance {
+            let t = (distance - walked) / seg;
+            return Some((w[0].lerp(w[1], t), (w[1] - w[0]) / seg));
+        }
+        walked += seg;
+    }
+    None
+}
+
+/// Project labels into the viewport, drop those too far away or behind the camera,
+/// then place the rest biggest road and nearest first wherever they don't overlap
+/// one already placed.
+fn place_labels(
+    camera: Query<(&Camera, &GlobalTransform), With<Camera3d>>,
+    heightmap: Option<Res<Heightmap>>,
+    mut labels: Query<(
+        &RoadLabel,
+        &ComputedNode,
+        &mut Node,
+        &mut UiTransform,
+        &mut Visibility,
+    )>,
+) {
+    let Ok((camera, camera_tf)) = camera.single() else {
+        return;
+    };
+    let ground = |p: Vec2| heightmap.as_ref().map_or(0.0, |h| h.sample(p));
+
+    let mut candidates = Vec::new();
+    for (i, (label, computed, ..)) in labels.iter().enumerate() {
+        let world = Vec3::new(
+            label.anchor.x,
+            label.height + LABEL_LIFT + ground(label.anchor),
+            label.anchor.y,
+        );
+        let distance = world.distance(camera_tf.translation());
+        if distance > label.max_distance() {
+            continue;
+        }
+        let ahead = world + Vec3::new(label.direction.x, 0.0, label.direction.y) * 5.0;
+        let (Ok(screen), Ok(screen_ahead)) = (
+            camera.world_to_viewport(camera_tf, world),
+            camera.world_to_viewport(camera_tf, ahead),
+        ) else {
+            continue;
+        };
+        let mut angle = (screen_ahead - screen).to_angle();
+        // Keep the text upright.
+        if angle.abs() > std::f32::consts::FRAC_PI_2 {
+            angle -= std::f32::consts::PI.copysign(angle);
+        }
+        let size = computed.size() * computed.inverse_scale_factor;
+        candidates.push((i, label.rank, distance, screen, angle, size));
+    }
+    candidates.sort_by(|a, b| {
+        b.1.cmp(&a.1)
+            .then(a.2.partial_cmp(&b.2).unwrap_or(Ordering::Equal))
+    });
+
+    let mut placed: Vec<Rect> = Vec::new();
+    let mut shown = vec![None; labels.iter().len()];
+    for (i, _, _, screen, angle, size) in candidates {
+        // Bounds of the turned text, padded.
+        let (sin, cos) = angle.sin_cos();
+        let half = Vec2::new(
+            (size.x * cos.abs() + size.y * sin.abs()) / 2.0,
+            (size.x * sin.abs() + size.y * cos.abs()) / 2.0,
+        ) + LABEL_PADDING;
+        let rect = Rect::from_center_half_size(screen, half);
+        if placed.iter().any(|r| !r.intersect(rect).is_empty()) {
+            continue;
+        }
+        placed.push(rect);
+        shown[i] = Some((screen - size / 2.0, angle));
+    }
+
+    for ((_, _, mut node, mut transform, mut visibility), shown) in labels.iter_mut().zip(shown) {
+        match shown {
+            Some((corner, angle)) => {
+                node.left = px(corner.x);
+                node.top = px(corner.y);
+                transform.rotation = Rot2::radians(angle);
+                visibility.set_if_neq(Visibility::Inherited);
+            }
+            None => {
+                visibility.set_if_neq(Visibility::Hidden);
+            }
+        }
+    }
+}
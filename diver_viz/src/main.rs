@@ -1,24 +1,93 @@
 use bevy::{
     asset::RenderAssetUsages,
+    ecs::system::SystemParam,
     input::mouse::MouseMotion,
     mesh::{Indices, PrimitiveTopology},
     prelude::*,
     window::{CursorGrabMode, CursorOptions},
 };
 use bevy_http_client::prelude::*;
-use geo::{Buffer, Coord, LineString, MultiPolygon, coord};
 use geozero::GeomProcessor;
-use geozero::mvt::tile::Layer;
+use geozero::mvt::tile::{GeomType, Layer};
 use geozero::mvt::{Message, Tile};
 
+mod choropleth;
+mod filter;
+mod gpx;
+mod height;
+mod inspect;
+mod isochrone;
+mod join;
+mod labels;
+mod overlay;
+mod points;
+mod properties;
+mod road_graph;
+mod road_layers;
+mod road_mesh;
+mod road_width;
+mod roof;
+mod route;
+mod shadow;
+mod sources;
+mod style;
+mod sun;
+mod terrain;
+mod water;
+
+use choropleth::ChoroplethPlugin;
+use filter::FilterPlugin;
+use gpx::GpxPlugin;
+use height::{BuildingHeight, HeightTags};
+use inspect::InspectPlugin;
+use isochrone::IsochronePlugin;
+use join::JoinPlugin;
+use labels::LabelsPlugin;
+use overlay::OverlayPlugin;
+use points::{PointFeature, PointProcessor, PointsPlugin};
+use properties::{FeatureProperties, TileKey};
+use road_graph::{RoadCenterline, RoadGraphPlugin};
+use road_layers::{RoadLayers, TunnelDisplay};
+use road_mesh::{RoadJoin, RoadNodes};
+use road_width::{RoadWidthTags, RoadWidths};
+use roof::{Roof, RoofShape};
+use route::RoutePlugin;
+use shadow::ShadowPlugin;
+use style::{ActiveStyle, Paint, StyleMaterials, StylePlugin, Styled, StyledShape};
+use sun::SunPlugin;
+use terrain::{OnTerrain, TerrainPlugin};
+
 const MARTIN_MVT_ENDPOINT: &str =
     "https://denver.roboape.online/tiles/denver_blocks_all_zoom_15_up";
 
 #[derive(Debug, Clone)]
 struct Building {
     geometry: Vec<Vec<Vec2>>,
+    /// Top of the building above ground.
     height: Option<f64>,
-    color: Option<Color>,
+    /// Bottom of the building part, for parts that float above the ground.
+    min_height: Option<f64>,
+    properties: FeatureProperties,
+    /// How the style draws it.
+    styled: Styled,
+    roof: Option<Roof>,
+    /// Building type (`kind_detail`) for buildings, `kind` for landuse.
+    kind: Option<String>,
+    id: Option<u64>,
+    height_tags: HeightTags,
+    height_estimated: bool,
+}
+
+/// The part of a building its mesh is built from, kept on the entity so the
+/// mesh can be rebuilt when the style changes a height.
+#[derive(Debug, Clone)]
+pub struct Footprint {
+    pub geometry: Vec<Vec<Vec2>>,
+    /// Top above ground, as resolved from the tags or estimated.
+    pub height: Option<f64>,
+    pub min_height: Option<f64>,
+    pub height_estimated: bool,
+    pub roof: Option<Roof>,
 }
 
 #[derive(Debug, Clone)]
@@ -26,6 +95,14 @@ struct Road {
     points: Vec<Vec2>,
     width: f32,
     kind: String,
+    /// OSM `layer`, 0 at grade.
+    layer: i32,
+    bridge: bool,
+    tunnel: bool,
+    /// Street name, for labels.
+    name: Option<String>,
+    properties: FeatureProperties,
+    styled: Styled,
 }
 
 fn main() {
@@ -41,16 +118,35 @@ fn main() {
                 ..default()
             }),
         )
-        .add_plugins(HttpClientPlugin)
+        .add_plugins((
+            HttpClientPlugin,
+            StylePlugin,
+            TerrainPlugin,
+            PointsPlugin,
+            LabelsPlugin,
+            InspectPlugin,
+            FilterPlugin,
+            ChoroplethPlugin,
+            JoinPlugin,
+            OverlayPlugin,
+            GpxPlugin,
+            RoadGraphPlugin,
+            RoutePlugin,
+            IsochronePlugin,
+        ))
+        .add_plugins((SunPlugin, ShadowPlugin))
         .insert_resource(ClearColor(Color::srgb(0.82, 0.73, 0.86)))
-        .add_systems(Startup, (spawn_player_camera, spawn_ui_text, request_tiles))
+        .init_resource::<RoadWidths>()
+        .init_resource::<RoadLayers>()
+        .add_systems(Startup, (spawn_player_camera, spawn_ui_text))
         .add_systems(
             Update,
             (
-                camera_update,
+                camera_update.run_if(not(filter::editing_filter)),
+                // Tiles are drawn with the style, so wait for it before fetching.
+                request_tiles.run_if(resource_added::<ActiveStyle>),
                 on_tile_response,
                 on_tile_error,
-                adjust_light,
                 mouse_track,
                 grab_mouse,
             ),
@@ -58,35 +154,10 @@ fn main() {
         .run();
 }
 
-fn adjust_light(
-    suns: Query<&mut Transform, With<DirectionalLight>>,
-    gamepads: Query<&Gamepad>,
-    keyboard_input: Res<ButtonInput<KeyCode>>,
-    time: Res<Time>,
-) {
-    for mut tf in suns {
-        for gamepad in gamepads {
-            if gamepad.pressed(GamepadButton::RightTrigger)
-                || keyboard_input.pressed(KeyCode::BracketRight)
-            {
-                tf.rotate_x(-time.delta_secs() * std::f32::consts::PI / 10.0);
-            }
-            if gamepad.pressed(GamepadButton::LeftTrigger)
-                || keyboard_input.pressed(KeyCode::BracketLeft)
-            {
-                tf.rotate_x(time.delta_secs() * std::f32::consts::PI / 10.0);
-            }
-            if gamepad.just_pressed(GamepadButton::South) {
-                info!("light tf (rotation) {:?}", tf.rotation);
-            }
-        }
-    }
-}
-
 fn spawn_player_camera(mut commands: Commands) {
     commands.spawn((
         Camera3d { ..default() },
-        Transform::from_xyz(58.50679, 4.5122952, 78.189224).with_rotation(Quat::from_xyzw(
+        Transform::from_xyz(225.4, 4.5122952, 301.3).with_rotation(Quat::from_xyzw(
             0.07673687,
             0.50015175,
             -0.04455679,
@@ -94,12 +165,17 @@ fn spawn_player_camera(mut commands: Commands) {
         )),
         DistanceFog {
             color: Color::srgb(0.50, 0.44, 0.63),
-            falloff: FogFalloff::ExponentialSquared { density: 0.01 },
+            falloff: FogFalloff::ExponentialSquared { density: 0.0026 },
             ..default()
         },
     ));
 }
 
+/// Column in the bottom-right corner; panels such as the legend stack above
+/// the attribution in it.
+#[derive(Component)]
+pub struct BottomRightPanels;
+
 fn spawn_ui_text(mut commands: Commands) {
     commands.spawn((
         Node {
@@ -112,13 +188,30 @@ fn spawn_ui_text(mut commands: Commands) {
         },
         children![
             Text("sticks (or WASD + mouse) to move & look".to_string()),
-            Text("bumpers/brackets to adjust lights".to_string()),
+            Text("right click to look with the mouse, click again to let go".to_string()),
+            Text("click a building, road or park to inspect it".to_string()),
+            Text(
+                "drop a GeoJSON, FlatGeobuf or GeoParquet file on the window to overlay it"
+                    .to_string()
+            ),
+            Text("drop a GPX track, then P to play it back and F to follow it".to_string()),
+            Text("T to route between two clicks, M to walk, cycle or drive".to_string()),
+            Text("I to see how far you can walk or cycle from a click".to_string()),
+            Text("O to see how many hours of sun a square around a click gets".to_string()),
+            Text("G to show the road graph".to_string()),
+            Text("C to colour buildings by height, levels, age or area".to_string()),
+            Text("/ to filter, e.g. height > 50 and kind == \"commercial\"".to_string()),
+            Text(
+                "brackets to move the sun through the day, with shift a day at a time".to_string()
+            ),
         ],
     ));
 
     commands.spawn((
+        BottomRightPanels,
         Node {
             flex_direction: FlexDirection::Column,
+            align_items: AlignItems::End,
             position_type: PositionType::Absolute,
             row_gap: px(6),
             right: px(12),
@@ -137,7 +230,7 @@ fn spawn_ui_text(mut commands: Commands) {
 
 const CAM_SENSITIVITY_X: f32 = 1.1;
 const CAM_SENSITIVITY_Y: f32 = 0.7;
-const SPEED: f32 = 12.0;
+const SPEED: f32 = 45.0;
 
 fn camera_update(
     camera_transform: Query<&mut Transform, With<Camera3d>>,
@@ -215,15 +308,18 @@ fn mouse_track(
     }
 }
 
+/// Right click locks the cursor for mouse look, any click lets it go again.
+/// Left clicks with the cursor free are for inspecting features.
 fn grab_mouse(
     mut cursor_options: Single<&mut CursorOptions>,
     mouse: Res<ButtonInput<MouseButton>>,
 ) {
-    let click = mouse.just_released(MouseButton::Left);
-    if click && cursor_options.grab_mode == CursorGrabMode::None {
+    let left = mouse.just_released(MouseButton::Left);
+    let right = mouse.just_released(MouseButton::Right);
+    if right && cursor_options.grab_mode == CursorGrabMode::None {
         cursor_options.visible = false;
         cursor_options.grab_mode = CursorGrabMode::Locked;
-    } else if click {
+    } else if (left || right) && cursor_options.grab_mode != CursorGrabMode::None {
         cursor_options.visible = true;
         cursor_options.grab_mode = CursorGrabMode::None;
     }
@@ -233,6 +329,84 @@ const TILE_COORD_Z: u32 = 15;
 const TILE_COORD_X: u32 = 6827;
 const TILE_COORD_Y: u32 = 12436;
 
+const MVT_EXTENT: f64 = 4096.0;
+
+/// Ground metres spanned by one tile at the loaded tile's latitude.
+/// World units are metres, so heights, widths and footprints all share a scale.
+fn tile_size_metres() -> f64 {
+    const EARTH_CIRCUMFERENCE_M: f64 = 40_075_016.686;
+    let n = std::f64::consts::PI
+        * (1.0 - 2.0 * (TILE_COORD_Y as f64 + 0.5) / 2f64.powi(TILE_COORD_Z as i32));
+    let lat = n.sinh().atan();
+    EARTH_CIRCUMFERENCE_M * lat.cos() / 2f64.powi(TILE_COORD_Z as i32)
+}
+
+/// MVT tile coordinates to world metres, measured east (X) and south (Z) from the
+/// loaded tile's corner. Kept relative to that tile so `f32` keeps sub-metre precision.
+pub fn tile_to_world(x: f64, y: f64, tile_x: u32, tile_y: u32) -> Vec2 {
+    let size = tile_size_metres();
+    let norm_x = (tile_x as f64 - TILE_COORD_X as f64 + x / MVT_EXTENT) * size;
+    let norm_y = (tile_y as f64 - TILE_COORD_Y as f64 + y / MVT_EXTENT) * size;
+    Vec2::new(norm_x as f32, norm_y as f32)
+}
+
+/// World position of the tile's corner before centring; the centre of everything
+/// spawned from the tile ends up at the origin.
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct WorldCenter(pub Vec2);
+
+/// Inverse of `tile_to_world` for a centred world position, as WGS84 (lon, lat) degrees.
+pub fn world_to_lon_lat(p: Vec2, center: Vec2) -> (f64, f64) {
+    let size = tile_size_metres();
+    let n = 2f64.powi(TILE_COORD_Z as i32);
+    let tx = TILE_COORD_X as f64 + (p.x + center.x) as f64 / size;
+    let ty = TILE_COORD_Y as f64 + (p.y + center.y) as f64 / size;
+    let lon = tx / n * 360.0 - 180.0;
+    let lat = (std::f64::consts::PI * (1.0 - 2.0 * ty / n))
+        .sinh()
+        .atan()
+        .to_degrees();
+    (lon, lat)
+}
+
+/// The loaded tile's extent in WGS84 degrees: west, south, east, north.
+pub fn tile_bounds() -> [f64; 4] {
+    let n = 2f64.powi(TILE_COORD_Z as i32);
+    let lon = |x: u32| x as f64 / n * 360.0 - 180.0;
+    let lat = |y: u32| {
+        (std::f64::consts::PI * (1.0 - 2.0 * y as f64 / n))
+            .sinh()
+            .atan()
+            .to_degrees()
+    };
+    [
+        lon(TILE_COORD_X),
+        lat(TILE_COORD_Y + 1),
+        lon(TILE_COORD_X + 1),
+        lat(TILE_COORD_Y),
+    ]
+}
+
+/// WGS84 (lon, lat) degrees to a centred world position; the inverse of `world_to_lon_lat`.
+pub fn lon_lat_to_world(lon: f64, lat: f64, center: Vec2) -> Vec2 {
+    let size = tile_size_metres();
+    let n = 2f64.powi(TILE_COORD_Z as i32);
+    let tx = (lon + 180.0) / 360.0 * n;
+    let ty = (1.0 - lat.to_radians().tan().asinh() / std::f64::consts::PI) / 2.0 * n;
+    Vec2::new(
+        ((tx - TILE_COORD_X as f64) * size) as f32,
+        ((ty - TILE_COORD_Y as f64) * size) as f32,
+    ) - center
+}
+
+/// The value after `flag` on the command line, if the flag is there.
+pub fn arg_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|a| a == flag)
+        .and_then(|i| args.get(i + 1))
+        .map(String::as_str)
+}
+
 fn request_tiles(mut ev_request: MessageWriter<HttpRequest>) {
     let url = format!(
         "{}/{}/{}/{}",
@@ -248,29 +422,90 @@ fn request_tiles(mut ev_request: MessageWriter<HttpRequest>) {
     }
 }
 
+/// Everything that decides how tile features look.
+#[derive(SystemParam)]
+struct TileStyle<'w> {
+    style: Res<'w, ActiveStyle>,
+    style_materials: ResMut<'w, StyleMaterials>,
+    road_widths: Res<'w, RoadWidths>,
+    road_layers: Res<'w, RoadLayers>,
+}
+
 fn on_tile_response(
     mut ev_resp: MessageReader<HttpResponse>,
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
+    tile_style: TileStyle,
 ) {
+    let TileStyle {
+        style,
+        mut style_materials,
+        road_widths,
+        road_layers,
+    } = tile_style;
     for response in ev_resp.read() {
         let bytes = response.bytes.as_slice();
         if let Ok(tile) = Tile::decode(bytes) {
+            let tile_key = TileKey {
+                z: TILE_COORD_Z,
+                x: TILE_COORD_X,
+                y: TILE_COORD_Y,
+            };
             let mut buildings = Vec::new();
             let mut landuse = Vec::new();
             let mut roads = Vec::new();
+            let mut water = Vec::new();
+            let mut waterways = Vec::new();
+            let mut points = Vec::new();
 
             for layer in &tile.layers {
                 if layer.name == "buildings" {
                     info!("Processing buildings layer...");
                     for feature in &layer.features {
+                        let properties = FeatureProperties::decode(feature, layer, tile_key);
+                        let styled = style.0.styled(&properties);
                         let mut processor = BuildingProcessor::new(TILE_COORD_X, TILE_COORD_Y);
-                        let height: Option<f64> =
-                            extract_tag_value_as_f64(&feature.tags, layer, "height".to_string());
+                        let tag = |key: &str| {
+                            extract_tag_value_as_f64(&feature.tags, layer, key.to_string())
+                        };
+                        let height_tags = HeightTags {
+                            height: tag("height"),
+                            min_height: tag("min_height"),
+                            levels: tag("building:levels"),
+                            min_level: tag("building:min_level").or_else(|| tag("min_level")),
+                            roof_height: tag("roof:height"),
+                        };
+                        let roof = extract_tag_value_as_string(
+                            &feature.tags,
+                            layer,
+                            "roof:shape".to_string(),
+                        )
+                        .and_then(|shape| RoofShape::from_tag(&shape))
+                        .map(|shape| Roof {
+                            shape,
+                            height: height_tags.roof_height,
+                            direction: extract_tag_value_as_string(
+                                &feature.tags,
+                                layer,
+                                "roof:direction".to_string(),
+                            )
+                            .and_then(|d| roof::parse_direction(&d))
+                            .or_else(|| tag("roof:direction")),
+                        });
+                        let kind = extract_tag_value_as_string(
+                            &feature.tags,
+                            layer,
+                            "kind_detail".to_string(),
+                        );
                         if geozero::mvt::process_geom(feature, &mut processor).is_ok() {
                             if let Some(mut building) = processor.building {
-                                building.height = height;
+                                building.height_tags = height_tags;
+                                building.roof = roof;
+                                building.kind = kind;
+                                building.properties = properties;
+                                building.styled = styled;
+                                building.id = feature.id;
                                 buildings.push(building);
                             }
                         }
@@ -281,16 +516,50 @@ fn on_tile_response(
                         let kind: String =
                             extract_tag_value_as_string(&feature.tags, layer, "kind".to_string())
                                 .unwrap_or("other".to_string());
-                        let width = match kind.as_str() {
-                            "major_road" => 4.0,
-                            "minor_road" => 0.125,
-                            "path" => 0.06,
-                            _ => 0.02,
-                        };
+                        let bridge = extract_flag(&feature.tags, layer, ["is_bridge", "bridge"]);
+                        let tunnel = extract_flag(&feature.tags, layer, ["is_tunnel", "tunnel"]);
+                        let properties = FeatureProperties::decode(feature, layer, tile_key);
+                        let styled = style.0.styled(&properties);
+                        let width = road_widths.width(&RoadWidthTags {
+                            width: extract_tag_value_as_string(
+                                &feature.tags,
+                                layer,
+                                "width".to_string(),
+                            )
+                            .or_else(|| {
+                                extract_tag_value_as_f64(&feature.tags, layer, "width".to_string())
+                                    .map(|w| w.to_string())
+                            }),
+                            lanes: extract_tag_value_as_f64(
+                                &feature.tags,
+                                layer,
+                                "lanes".to_string(),
+                            ),
+                            class: extract_tag_value_as_string(
+                                &feature.tags,
+                                layer,
+                                "kind_detail".to_string(),
+                            ),
+                            kind: kind.clone(),
+                        });
+                        let road_layer =
+                            extract_tag_value_as_f64(&feature.tags, layer, "layer".to_string())
+                                .map(|l| l.round() as i32)
+                                .unwrap_or(0);
+                        let name =
+                            extract_tag_value_as_string(&feature.tags, layer, "name".to_string());
                         let mut processor =
                             RoadProcessor::new(TILE_COORD_X, TILE_COORD_Y, width, kind);
                         if geozero::mvt::process_geom(feature, &mut processor).is_ok() {
-                            roads.extend(processor.roads);
+                            roads.extend(processor.roads.into_iter().map(|road| Road {
+                                layer: road_layer,
+                                bridge,
+                                tunnel,
+                                name: name.clone(),
+                                properties: properties.clone(),
+                                styled: styled.clone(),
+                                ..road
+                            }));
                         }
                     }
                 } else if layer.name == "landuse" {
@@ -299,73 +568,107 @@ fn on_tile_response(
                         let kind: String =
                             extract_tag_value_as_string(&feature.tags, layer, "kind".to_string())
                                 .unwrap_or("other".to_string());
-                        let height = match kind.as_str() {
-                            "other" => 0.05,
-                            "grass" => 0.08,
-                            "pedestrian" => 0.1,
-                            _ => 0.02,
-                        };
-                        let color = match kind.as_str() {
-                            "other" => Color::srgb(0.9, 0.58, 0.43),
-                            "grass" => Color::srgb(0.25, 0.58, 0.43),
-                            "pedestrian" => Color::srgb(0.62, 0.67, 0.60),
-                            _ => Color::srgb(0.85, 0.04, 0.30),
-                        };
+                        let properties = FeatureProperties::decode(feature, layer, tile_key);
+                        let styled = style.0.styled(&properties);
                         let mut processor = BuildingProcessor::new(TILE_COORD_X, TILE_COORD_Y);
                         if geozero::mvt::process_geom(feature, &mut processor).is_ok() {
                             if let Some(mut building) = processor.building {
-                                building.height = Some(height);
-                                building.color = Some(color);
+                                // Flat unless the style gives it a height.
+                                building.height = Some(0.0);
+                                building.properties = properties;
+                                building.styled = styled;
+                                building.kind = Some(kind);
+                                building.id = feature.id;
                                 landuse.push(building);
                             }
                         }
                     }
+                } else if layer.name == "water" {
+                    info!("Processing water layer...");
+                    for feature in &layer.features {
+                        let kind: String =
+                            extract_tag_value_as_string(&feature.tags, layer, "kind".to_string())
+                                .unwrap_or("water".to_string());
+                        let properties = FeatureProperties::decode(feature, layer, tile_key);
+                        let styled = style.0.styled(&properties);
+                        match feature.r#type() {
+                            GeomType::Polygon => {
+                                let mut processor =
+                                    BuildingProcessor::new(TILE_COORD_X, TILE_COORD_Y);
+                                if geozero::mvt::process_geom(feature, &mut processor).is_ok()
+                                    && let Some(mut building) = processor.building
+                                {
+                                    building.kind = Some(kind);
+                                    building.id = feature.id;
+                                    building.properties = properties;
+                                    building.styled = styled;
+                                    water.push(building);
+                                }
+                            }
+                            // Culverts run underground.
+                            GeomType::Linestring
+                                if extract_flag(&feature.tags, layer, ["is_tunnel", "tunnel"]) => {}
+                            GeomType::Linestring => {
+                                let class = extract_tag_value_as_string(
+                                    &feature.tags,
+                                    layer,
+                                    "kind_detail".to_string(),
+                                )
+                                .unwrap_or(kind.clone());
+                                let width = water::waterway_width(
+                                    extract_tag_value_as_f64(
+                                        &feature.tags,
+                                        layer,
+                                        "width".to_string(),
+                                    ),
+                                    &class,
+                                );
+                                let mut processor =
+                                    RoadProcessor::new(TILE_COORD_X, TILE_COORD_Y, width, kind);
+                                if geozero::mvt::process_geom(feature, &mut processor).is_ok() {
+                                    waterways.extend(processor.roads.into_iter().map(|road| {
+                                        Road {
+                                            properties: properties.clone(),
+                                            styled: styled.clone(),
+                                            ..road
+                                        }
+                                    }));
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                } else if layer.name == "pois" {
+                    info!("Processing pois layer...");
+                    for feature in &layer.features {
+                        let Some(kind) =
+                            extract_tag_value_as_string(&feature.tags, layer, "kind".to_string())
+                        else {
+                            continue;
+                        };
+                        let mut processor = PointProcessor::new(TILE_COORD_X, TILE_COORD_Y);
+                        if geozero::mvt::process_geom(feature, &mut processor).is_ok() {
+                            let properties = FeatureProperties::decode(feature, layer, tile_key);
+                            points.extend(
+                                processor
+                                    .points
+                                    .into_iter()
+                                    .map(|p| (p, kind.clone(), properties.clone())),
+                            );
+                        }
+                    }
                 }
             }
 
             info!("✓ Parsed {} building polygons", buildings.len());
             info!("✓ Parsed {} landuse polygons", landuse.len());
             info!("✓ Parsed {} roads", roads.len());
-
-            // Convert all building points to world coordinates
-            for building in &mut buildings {
-                for ring in &mut building.geometry {
-                    for point in ring.iter_mut() {
-                        *point = BuildingProcessor::tile_to_world_static(
-                            point.x as f64,
-                            point.y as f64,
-                            TILE_COORD_X,
-                            TILE_COORD_Y,
-                            1000.0,
-                        );
-                    }
-                }
-            }
-
-            for landuse_poly in &mut landuse {
-                for ring in &mut landuse_poly.geometry {
-                    for point in ring.iter_mut() {
-                        *point = BuildingProcessor::tile_to_world_static(
-                            point.x as f64,
-                            point.y as f64,
-                            TILE_COORD_X,
-                            TILE_COORD_Y,
-                            1000.0,
-                        );
-                    }
-                }
-            }
-
-            for road in &mut roads {
-                for point in road.points.iter_mut() {
-                    *point = RoadProcessor::tile_to_world_static(
-                        point.x as f64,
-                        point.y as f64,
-                        TILE_COORD_X,
-                        TILE_COORD_Y,
-                    );
-                }
-            }
+            info!(
+                "✓ Parsed {} water polygons and {} waterways",
+                water.len(),
+                waterways.len()
+            );
+            info!("✓ Parsed {} points", points.len());
 
             // Compute center of all objects
             let mut min = Vec2::splat(f32::MAX);
@@ -388,6 +691,7 @@ fn on_tile_response(
 
             let center = (min + max) / 2.0;
             info!("Computed world center: {:?}", center);
+            commands.insert_resource(WorldCenter(center));
 
             // Apply center offset
             for building in &mut buildings {
@@ -404,114 +708,199 @@ fn on_tile_response(
                     }
                 }
             }
-            for road in &mut roads {
+            for water_poly in &mut water {
+                for ring in &mut water_poly.geometry {
+                    for point in ring.iter_mut() {
+                        *point -= center;
+                    }
+                }
+            }
+            for road in roads.iter_mut().chain(waterways.iter_mut()) {
                 for point in &mut road.points {
                     *point -= center;
                 }
             }
+            for (point, ..) in &mut points {
+                *point -= center;
+            }
 
-            let building_material = materials.add(StandardMaterial {
-                base_color: Color::srgb(0.8, 0.64, 0.55),
-                metallic: 0.0,
-                perceptual_roughness: 0.9,
-                ..default()
-            });
+            height::resolve_heights(&mut buildings, &landuse, &style.0.heights);
 
-            let major_road_material = materials.add(StandardMaterial {
-                base_color: Color::srgb(0.98, 0.37, 0.43),
-                metallic: 1.0,
-                perceptual_roughness: 0.0,
-                ..default()
-            });
-            let minor_road_material = materials.add(StandardMaterial {
-                base_color: Color::srgb(0.88, 0.41, 0.63),
-                metallic: 1.0,
-                perceptual_roughness: 0.0,
-                ..default()
-            });
-            let other_road_material = materials.add(StandardMaterial {
-                base_color: Color::srgb(0.78, 0.37, 0.93),
-                metallic: 1.0,
-                perceptual_roughness: 0.0,
-                ..default()
-            });
+            info!(
+                "✓ Estimated heights for {} buildings",
+                buildings.iter().filter(|b| b.height_estimated).count()
+            );
 
             // Spawn building meshes
-            for building in &buildings {
-                if let Some(mesh) = create_building_mesh(building) {
+            for building in buildings {
+                let footprint = building.footprint();
+                let painted = footprint.painted(&building.styled.paint);
+                // Without a height (a chain that leaves out `Default`) there's nothing to draw.
+                if let Some(mesh) = create_building_mesh(&painted)
+                    && let Some(height) = BuildingHeight::of(&painted)
+                {
                     commands.spawn((
                         Mesh3d(meshes.add(mesh)),
-                        MeshMaterial3d(building_material.clone()),
-                        Transform::from_xyz(0.0, 0.0, 0.0),
+                        MeshMaterial3d(style_materials.get(&building.styled.paint, &mut materials)),
+                        Transform::from_xyz(0.0, building.min_height.unwrap_or(0.0) as f32, 0.0),
+                        height,
+                        OnTerrain::Footprint(building.geometry[0].clone()),
+                        building.properties,
+                        building.styled,
+                        StyledShape::Extrusion {
+                            footprint,
+                            draped: false,
+                        },
                     ));
                 }
             }
 
-            // Spawn landuse meshes
-            for landuse_poly in &landuse {
-                if let Some(mesh) = create_building_mesh(landuse_poly) {
-                    let landuse_material = materials.add(StandardMaterial {
-                        base_color: landuse_poly.color.unwrap(),
-                        metallic: 0.0,
-                        perceptual_roughness: 1.0,
-                        ..default()
-                    });
+            // Spawn landuse meshes. Landuse is flat unless the style gives it a
+            // height, so it is spawned hidden with an empty mesh until one does.
+            for landuse_poly in landuse {
+                let shape = StyledShape::Extrusion {
+                    footprint: landuse_poly.footprint(),
+                    draped: true,
+                };
+                let mesh = shape.mesh(&landuse_poly.styled.paint);
+                let visibility = if mesh.is_some() {
+                    Visibility::Inherited
+                } else {
+                    Visibility::Hidden
+                };
+                commands.spawn((
+                    Mesh3d(meshes.add(mesh.unwrap_or_else(empty_mesh))),
+                    MeshMaterial3d(style_materials.get(&landuse_poly.styled.paint, &mut materials)),
+                    Transform::from_xyz(0.0, -0.125, 0.0),
+                    visibility,
+                    OnTerrain::Drape,
+                    landuse_poly.properties,
+                    landuse_poly.styled,
+                    shape,
+                ));
+            }
+
+            // Spawn water surfaces and waterways
+            for water_poly in &water {
+                let Some(ring) = water_poly.geometry.first() else {
+                    continue;
+                };
+                if let Some(mesh) = extrude_polygon_mesh(ring, water::WATER_DEPTH) {
+                    commands.spawn((
+                        Mesh3d(meshes.add(mesh)),
+                        MeshMaterial3d(
+                            style_materials.get(&water_poly.styled.paint, &mut materials),
+                        ),
+                        Transform::from_xyz(
+                            0.0,
+                            water::WATER_SURFACE_HEIGHT - water::WATER_DEPTH,
+                            0.0,
+                        ),
+                        OnTerrain::Drape,
+                        water_poly.properties.clone(),
+                        water_poly.styled.clone(),
+                        StyledShape::Fixed,
+                    ));
+                }
+            }
+            let waterway_nodes = RoadNodes::new(&waterways);
+            for waterway in &waterways {
+                let shape = StyledShape::Ribbon {
+                    points: waterway.points.clone(),
+                    width: waterway.width,
+                    style: waterway_nodes.style_for(waterway, RoadJoin::Round),
+                    deck: None,
+                };
+                if let Some(mesh) = shape.mesh(&waterway.styled.paint) {
                     commands.spawn((
                         Mesh3d(meshes.add(mesh)),
-                        MeshMaterial3d(landuse_material.clone()),
-                        Transform::from_xyz(0.0, -0.125, 0.0),
+                        MeshMaterial3d(style_materials.get(&waterway.styled.paint, &mut materials)),
+                        Transform::from_xyz(0.0, water::WATER_SURFACE_HEIGHT, 0.0),
+                        OnTerrain::Drape,
+                        waterway.properties.clone(),
+                        waterway.styled.clone(),
+                        shape,
                     ));
                 }
             }
 
-            // Spawn road meshes
+            // Spawn points; the points plugin gives them shared, instanced meshes
+            for (point, kind, properties) in points {
+                commands.spawn((
+                    PointFeature { kind },
+                    properties,
+                    Transform::from_xyz(point.x, 0.0, point.y),
+                    OnTerrain::Footprint(vec![point]),
+                ));
+            }
+
+            let support_material = materials.add(StandardMaterial {
+                base_color: Color::srgb(0.62, 0.58, 0.66),
+                perceptual_roughness: 0.9,
+                ..default()
+            });
+            let support_mesh = meshes.add(Cuboid::new(1.0, 1.0, 1.0));
+
+            // Spawn road meshes, lowest layer first
+            roads.sort_by_key(|road| road.layer);
+            let road_nodes = RoadNodes::new(&roads);
             for road in &roads {
-                if road.points.len() < 2 {
+                if road.tunnel && road_layers.tunnels == TunnelDisplay::Hidden {
+                    // Not drawn, but still part of the road graph.
+                    commands.spawn(RoadCenterline::of(road));
                     continue;
                 }
-                let coords: Vec<Coord<f32>> = road
-                    .points
-                    .iter()
-                    .map(|v2| coord! { x: v2.x, y: v2.y })
-                    .collect();
-                let road_vertices_2d = LineString::new(coords);
-                let buff_road: MultiPolygon<f32> = road_vertices_2d.buffer(road.width / 2.0);
-
-                let road_height = 0.15_f32;
-                for polygon in buff_road {
-                    let points: Vec<Vec2> = polygon
-                        .exterior()
-                        .points()
-                        .map(|p| Vec2::new(p.0.x, p.0.y))
-                        .collect();
-                    if let Some(mesh) = extrude_polygon_mesh(&points, road_height) {
-                        commands.spawn((
-                            Mesh3d(meshes.add(mesh)),
-                            MeshMaterial3d(match road.kind.as_str() {
-                                "major_road" => major_road_material.clone(),
-                                "minor_road" => minor_road_material.clone(),
-                                _ => other_road_material.clone(),
-                            }),
-                            Transform::from_xyz(0.0, 0.0, 0.0),
-                        ));
+                // Big roads curve smoothly; small ones keep crisp, cheaper corners.
+                let join = match road.kind.as_str() {
+                    "major_road" => RoadJoin::Round,
+                    _ => RoadJoin::Mitre,
+                };
+                let deck = road_layers.deck_height(road);
+                let shape = StyledShape::Ribbon {
+                    points: road.points.clone(),
+                    width: road.width,
+                    style: road_nodes.style_for(road, join),
+                    deck,
+                };
+                if let Some(mesh) = shape.mesh(&road.styled.paint) {
+                    let material = style_materials.get(&road.styled.paint, &mut materials);
+                    // Stack classes and layers a hair apart so overlapping ribbons don't z-fight.
+                    let road_height = road_layers.surface_height(road);
+                    if let Some(deck) = deck {
+                        for (at, height) in road_layers::support_positions(
+                            &road.points,
+                            deck,
+                            road_layers.support_spacing,
+                        ) {
+                            commands.spawn((
+                                Mesh3d(support_mesh.clone()),
+                                MeshMaterial3d(support_material.clone()),
+                                Transform::from_xyz(at.x, height / 2.0, at.y).with_scale(
+                                    Vec3::new(road.width.min(2.0), height, road.width.min(2.0)),
+                                ),
+                                OnTerrain::Footprint(vec![at]),
+                            ));
+                        }
                     }
+                    commands.spawn((
+                        Mesh3d(meshes.add(mesh)),
+                        MeshMaterial3d(material),
+                        Transform::from_xyz(0.0, road_height, 0.0),
+                        OnTerrain::Drape,
+                        road.properties.clone(),
+                        road.styled.clone(),
+                        shape,
+                        RoadCenterline::of(road),
+                    ));
                 }
             }
 
-            // Light
-            commands.spawn((
-                DirectionalLight {
-                    shadows_enabled: true,
-                    illuminance: 50000.0,
-                    ..default()
-                },
-                Transform::from_xyz(1.0, -0.4, 0.0).with_rotation(Quat::from_xyzw(
-                    -0.6469852,
-                    0.02463232,
-                    -0.70667696,
-                    0.285324,
-                )),
-            ));
+            // Spawn street name labels
+            for (name, label) in labels::road_labels(&roads, |road| {
+                road_layers.surface_height(road) + road_layers.deck_height(road).unwrap_or(0.0)
+            }) {
+                commands.spawn(labels::label_bundle(name, label));
+            }
         } else {
             info!("failed to parse tiles");
         }
@@ -572,6 +961,37 @@ fn extract_tag_value_as_string(
     output
 }
 
+fn extract_tag_value_as_bool(tags: &[u32], layer: &Layer, input_key: String) -> Option<bool> {
+    let mut output = None;
+    for tag_pair in tags.chunks(2) {
+        if tag_pair.len() != 2 {
+            continue;
+        }
+        let key_idx = tag_pair[0] as usize;
+        let val_idx = tag_pair[1] as usize;
+        if let (Some(key), Some(val)) = (layer.keys.get(key_idx), layer.values.get(val_idx))
+            && *key == input_key
+        {
+            output = val
+                .bool_value
+                .or_else(|| val.int_value.map(|v| v != 0))
+                .or_else(|| val.uint_value.map(|v| v != 0))
+                .or_else(|| {
+                    val.string_value
+                        .as_deref()
+                        .map(|s| matches!(s, "yes" | "true" | "1"))
+                });
+        }
+    }
+    output
+}
+
+/// Whether either spelling of a flag is set, e.g. Protomaps' `is_tunnel` or OSM's `tunnel`.
+fn extract_flag(tags: &[u32], layer: &Layer, keys: [&str; 2]) -> bool {
+    keys.iter()
+        .any(|key| extract_tag_value_as_bool(tags, layer, key.to_string()).unwrap_or(false))
+}
+
 /// Triangulate a simple polygon (no holes) using the ear-clipping algorithm.
 /// Input: 2D vertices in order (CW or CCW).
 /// Returns: indices into the input slice, as triangles.
@@ -678,8 +1098,8 @@ fn triangulate_polygon(verts: &[Vec2]) -> Vec<usize> {
 ///   • top    cap at Y = h
 ///   • side walls connecting the two
 ///
-/// The polygon ring is assumed to have no holes (the original code only ever
-/// passes `building.geometry.first()` / the exterior ring of a buffered road).
+/// The polygon ring is assumed to have no holes (callers only ever pass
+/// `building.geometry.first()`).
 pub fn extrude_polygon_mesh(ring: &[Vec2], height: f32) -> Option<Mesh> {
     let n = ring.len();
     if n < 3 {
@@ -780,16 +1200,64 @@ pub fn extrude_polygon_mesh(ring: &[Vec2], height: f32) -> Option<Mesh> {
     Some(mesh)
 }
 
+/// A mesh with nothing in it, for features spawned hidden until the style gives
+/// them a shape.
+fn empty_mesh() -> Mesh {
+    let mut mesh = Mesh::new(
+        PrimitiveTopology::TriangleList,
+        RenderAssetUsages::default(),
+    );
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, Vec::<[f32; 3]>::new());
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, Vec::<[f32; 3]>::new());
+    mesh.insert_indices(Indices::U32(Vec::new()));
+    mesh
+}
+
+impl Building {
+    fn footprint(&self) -> Footprint {
+        Footprint {
+            geometry: self.geometry.clone(),
+            height: self.height,
+            min_height: self.min_height,
+            height_estimated: self.height_estimated,
+            roof: self.roof.clone(),
+        }
+    }
+}
+
+impl Footprint {
+    /// The footprint as `paint` draws it: a style `height` replaces the tagged
+    /// or estimated one.
+    pub fn painted(&self, paint: &Paint) -> Footprint {
+        let mut footprint = self.clone();
+        if let Some(height) = paint.height {
+            footprint.height = Some(self.min_height.unwrap_or(0.0) + height as f64);
+            footprint.height_estimated = false;
+        }
+        footprint
+    }
+}
+
 // ---------------------------------------------------------------------------
 // create_building_mesh — thin wrapper that keeps the call-sites unchanged
 // ---------------------------------------------------------------------------
-fn create_building_mesh(building: &Building) -> Option<Mesh> {
-    let outer_ring = building.geometry.first()?;
+/// The mesh spans `min_height..height` but is built from Y=0;
+/// callers lift it into place with the spawned `Transform`.
+fn create_building_mesh(footprint: &Footprint) -> Option<Mesh> {
+    let outer_ring = footprint.geometry.first()?;
     if outer_ring.len() < 3 {
         return None;
     }
-    let height = building.height.unwrap_or(10.0) as f32;
-    extrude_polygon_mesh(outer_ring, height)
+    let height = (footprint.height? - footprint.min_height.unwrap_or(0.0)) as f32;
+    if height <= 0.0 {
+        return None;
+    }
+    match &footprint.roof {
+        Some(roof) if roof.shape != RoofShape::Flat => {
+            roof::roofed_building_mesh(outer_ring, height, roof)
+        }
+        _ => extrude_polygon_mesh(outer_ring, height),
+    }
 }
 
 // ---------------------------------------------------------------------------
@@ -810,30 +1278,26 @@ impl BuildingProcessor {
             tile_y,
             building: Some(Building {
                 geometry: Vec::new(),
-                color: None,
+                properties: FeatureProperties::default(),
+                styled: Styled::default(),
                 height: None,
+                min_height: None,
+                roof: None,
+                kind: None,
+                id: None,
+                height_tags: HeightTags::default(),
+                height_estimated: false,
             }),
             current_ring: Vec::new(),
             rings: Vec::new(),
         }
     }
-
-    pub fn tile_to_world_static(x: f64, y: f64, tile_x: u32, tile_y: u32, scale: f64) -> Vec2 {
-        let norm_x = (tile_x as f64 + x / 4096.0) * scale;
-        let norm_y = (tile_y as f64 + y / 4096.0) * scale;
-        Vec2::new(norm_x as f32, norm_y as f32)
-    }
 }
 
 impl GeomProcessor for BuildingProcessor {
     fn xy(&mut self, x: f64, y: f64, _idx: usize) -> geozero::error::Result<()> {
-        self.current_ring.push(Self::tile_to_world_static(
-            x,
-            y,
-            self.tile_x,
-            self.tile_y,
-            1000.0,
-        ));
+        self.current_ring
+            .push(tile_to_world(x, y, self.tile_x, self.tile_y));
         Ok(())
     }
 
@@ -876,19 +1340,12 @@ impl RoadProcessor {
             kind,
         }
     }
-
-    pub fn tile_to_world_static(x: f64, y: f64, tile_x: u32, tile_y: u32) -> Vec2 {
-        let scale = 1000.0;
-        let norm_x = (tile_x as f64 + x / 4096.0) * scale;
-        let norm_y = (tile_y as f64 + y / 4096.0) * scale;
-        Vec2::new(norm_x as f32, norm_y as f32)
-    }
 }
 
 impl GeomProcessor for RoadProcessor {
     fn xy(&mut self, x: f64, y: f64, _idx: usize) -> geozero::error::Result<()> {
         self.current_line
-            .push(Self::tile_to_world_static(x, y, self.tile_x, self.tile_y));
+            .push(tile_to_world(x, y, self.tile_x, self.tile_y));
         Ok(())
     }
 
@@ -898,6 +1355,12 @@ impl GeomProcessor for RoadProcessor {
                 points: self.current_line.clone(),
                 width: self.width,
                 kind: self.kind.clone(),
+                layer: 0,
+                bridge: false,
+                tunnel: false,
+                name: None,
+                properties: FeatureProperties::default(),
+                styled: Styled::default(),
             });
             self.current_line.clear();
         }
@@ -0,0 +1,523 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use bevy::{
+    ecs::system::SystemParam,
+    prelude::*,
+    tasks::{AsyncComputeTaskPool, Task, block_on, poll_once},
+};
+use geozero::{
+    ColumnValue, FeatureProcessor, GeomProcessor, GeozeroDatasource, PropertyProcessor,
+    geojson::GeoJson,
+};
+
+use crate::gpx;
+use crate::height::{BuildingHeight, HeightSource, HeightTags};
+use crate::points::PointFeature;
+use crate::properties::{FeatureProperties, PropertyValue};
+use crate::road_mesh::{RoadJoin, RoadNodes};
+use crate::sources;
+use crate::style::{ActiveStyle, StyleMaterials, StyledShape};
+use crate::terrain::OnTerrain;
+use crate::{Footprint, Road, WorldCenter, create_building_mesh, lon_lat_to_world, tile_bounds};
+
+/// Tile layer name overlay features are styled under.
+pub const OVERLAY_LAYER: &str = "overlay";
+/// Polygons without a height are drawn as slabs this thick, draped on the ground.
+const SLAB_HEIGHT: f64 = 0.3;
+/// Line width when a feature has no `width`.
+const LINE_WIDTH: f32 = 3.0;
+/// Lift over the tile features, so overlays draw on top of roads and landuse.
+const OVERLAY_LIFT: f32 = 0.15;
+
+#[derive(Debug, Clone)]
+pub enum OverlayGeometry {
+    /// Outer ring first, then any holes.
+    Polygon(Vec<Vec<Vec2>>),
+    Line(Vec<Vec2>),
+    Point(Vec2),
+}
+
+#[derive(Debug, Clone)]
+pub struct OverlayFeature {
+    pub properties: FeatureProperties,
+    pub geometry: Vec<OverlayGeometry>,
+}
+
+/// Collects features from any geozero source in WGS84 lon/lat, projected into
+/// the world around `center`.
+pub struct OverlayCollector {
+    center: Vec2,
+    name: String,
+    pub features: Vec<OverlayFeature>,
+    tags: HashMap<String, PropertyValue>,
+    geometry: Vec<OverlayGeometry>,
+    coords: Vec<Vec2>,
+    rings: Vec<Vec<Vec2>>,
+    in_polygon: bool,
+}
+
+impl OverlayCollector {
+    pub fn new(name: &str, center: Vec2) -> Self {
+        Self {
+            center,
+            name: name.to_string(),
+            features: Vec::new(),
+            tags: HashMap::new(),
+            geometry: Vec::new(),
+            coords: Vec::new(),
+            rings: Vec::new(),
+            in_polygon: false,
+        }
+    }
+}
+
+impl GeomProcessor for OverlayCollector {
+    fn xy(&mut self, x: f64, y: f64, _idx: usize) -> geozero::error::Result<()> {
+        self.coords.push(lon_lat_to_world(x, y, self.center));
+        Ok(())
+    }
+
+    fn point_end(&mut self, _idx: usize) -> geozero::error::Result<()> {
+        self.geometry
+            .extend(self.coords.drain(..).map(OverlayGeometry::Point));
+        Ok(())
+    }
+
+    fn multipoint_end(&mut self, _idx: usize) -> geozero::error::Result<()> {
+        self.point_end(0)
+    }
+
+    fn linestring_end(&mut self, _tagged: bool, _idx: usize) -> geozero::error::Result<()> {
+        let line = std::mem::take(&mut self.coords);
+        if self.in_polygon {
+            self.rings.push(line);
+        } else if line.len() >= 2 {
+            self.geometry.push(OverlayGeometry::Line(line));
+        }
+        Ok(())
+    }
+
+    fn polygon_begin(
+        &mut self,
+        _tagged: bool,
+        _size: usize,
+        _idx: usize,
+    ) -> geozero::error::Result<()> {
+        self.in_polygon = true;
+        Ok(())
+    }
+
+    fn polygon_end(&mut self, _tagged: bool, _idx: usize) -> geozero::error::Result<()> {
+        self.in_polygon = false;
+        let rings = std::mem::take(&mut self.rings);
+        if rings.first().is_some_and(|ring| ring.len() >= 3) {
+            self.geometry.push(OverlayGeometry::Polygon(rings));
+        }
+        Ok(())
+    }
+}
+
+impl PropertyProcessor for OverlayCollector {
+    fn property(
+        &mut self,
+        _idx: usize,
+        name: &str,
+        value: &ColumnValue,
+    ) -> geozero::error::Result<bool> {
+        let value = match *value {
+            ColumnValue::Bool(v) => PropertyValue::Bool(v),
+            ColumnValue::Byte(v) => PropertyValue::Int(v as i64),
+            ColumnValue::Short(v) => PropertyValue::Int(v as i64),
+            ColumnValue::Int(v) => PropertyValue::Int(v as i64),
+            ColumnValue::Long(v) => PropertyValue::Int(v),
+            ColumnValue::UByte(v) => PropertyValue::Uint(v as u64),
+            ColumnValue::UShort(v) => PropertyValue::Uint(v as u64),
+            ColumnValue::UInt(v) => PropertyValue::Uint(v as u64),
+            ColumnValue::ULong(v) => PropertyValue::Uint(v),
+            ColumnValue::Float(v) => PropertyValue::Float(v),
+            ColumnValue::Double(v) => PropertyValue::Double(v),
+            ColumnValue::String(v) | ColumnValue::Json(v) | ColumnValue::DateTime(v) => {
+                PropertyValue::String(v.to_string())
+            }
+            ColumnValue::Binary(_) => return Ok(false),
+        };
+        self.tags.insert(name.to_string(), value);
+        Ok(false)
+    }
+}
+
+impl FeatureProcessor for OverlayCollector {
+    fn feature_end(&mut self, _idx: u64) -> geozero::error::Result<()> {
+        let mut tags = std::mem::take(&mut self.tags);
+        let id = ["id", "osm_id"]
+            .iter()
+            .find_map(|key| tags.get(*key)?.as_f64())
+            .filter(|id| *id >= 0.0 && id.fract() == 0.0)
+            .map(|id| id as u64);
+        tags.entry(OVERLAY_LAYER.to_string())
+            .or_insert_with(|| PropertyValue::String(self.name.clone()));
+        self.features.push(OverlayFeature {
+            properties: FeatureProperties {
+                id,
+                layer: OVERLAY_LAYER.to_string(),
+                tags,
+                ..default()
+            },
+            geometry: std::mem::take(&mut self.geometry),
+        });
+        Ok(())
+    }
+}
+
+/// Read a GeoJSON document's features.
+pub fn read_geojson(name: &str, text: &str, center: Vec2) -> Result<Vec<OverlayFeature>, String> {
+    let mut collector = OverlayCollector::new(name, center);
+    GeoJson(text)
+        .process(&mut collector)
+        .map_err(|e| e.to_string())?;
+    Ok(collector.features)
+}
+
+// ---------------------------------------------------------------------------
+// Loading and spawning
+// ---------------------------------------------------------------------------
+
+/// Everything spawned from one overlay file, so loading it again replaces it.
+#[derive(Component, Debug, Clone)]
+pub struct Overlay {
+    pub name: String,
+}
+
+/// Where an overlay comes from.
+#[derive(Debug, Clone)]
+pub enum OverlaySource {
+    /// A GeoJSON document already in memory, as dropped on the web page.
+    GeoJson(String),
+    /// A GeoJSON, FlatGeobuf or GeoParquet file on disk.
+    File(PathBuf),
+}
+
+/// Overlays waiting for the style and the world to exist, by name.
+#[derive(Resource, Debug, Default)]
+pub struct OverlayQueue(pub Vec<(String, OverlaySource)>);
+
+type ReadOverlay = Task<Result<Vec<OverlayFeature>, String>>;
+
+/// Files being read in the background.
+#[derive(Resource, Default)]
+struct OverlayTasks(Vec<(String, ReadOverlay)>);
+
+/// Everything that decides how overlay features look.
+#[derive(SystemParam)]
+pub struct OverlayStyle<'w> {
+    style: Res<'w, ActiveStyle>,
+    style_materials: ResMut<'w, StyleMaterials>,
+    materials: ResMut<'w, Assets<StandardMaterial>>,
+    meshes: ResMut<'w, Assets<Mesh>>,
+}
+
+pub struct OverlayPlugin;
+
+impl Plugin for OverlayPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<OverlayQueue>()
+            .init_resource::<OverlayTasks>()
+            .add_systems(Startup, queue_overlay_args)
+            .add_systems(
+                Update,
+                (
+                    receive_dropped_files,
+                    read_overlays.run_if(resource_exists::<WorldCenter>),
+                    spawn_overlays.run_if(resource_exists::<ActiveStyle>),
+                )
+                    .chain(),
+            );
+        #[cfg(target_arch = "wasm32")]
+        web_drop::listen();
+    }
+}
+
+fn file_source(path: &Path) -> (String, OverlaySource) {
+    let name = path.file_stem().map_or_else(
+        || "overlay".to_string(),
+        |s| s.to_string_lossy().to_string(),
+    );
+    (name, OverlaySource::File(path.to_path_buf()))
+}
+
+/// `--geojson <path>`, `--fgb <path>` and `--geoparquet <path>`, as many times as
+/// you like, load files from disk at startup.
+fn queue_overlay_args(mut queue: ResMut<OverlayQueue>) {
+    let args: Vec<String> = std::env::args().collect();
+    for pair in args.windows(2) {
+        if matches!(pair[0].as_str(), "--geojson" | "--fgb" | "--geoparquet") {
+            queue.0.push(file_source(Path::new(&pair[1])));
+        }
+    }
+}
+
+/// Files dropped on the window: from disk natively, or read by the browser on web.
+/// GPX files are left for the track loader.
+fn receive_dropped_files(
+    mut drops: MessageReader<FileDragAndDrop>,
+    mut queue: ResMut<OverlayQueue>,
+) {
+    for drop in drops.read() {
+        if let FileDragAndDrop::DroppedFile { path_buf, .. } = drop
+            && !gpx::is_gpx(path_buf)
+        {
+            queue.0.push(file_source(path_buf));
+        }
+    }
+    #[cfg(target_arch = "wasm32")]
+    queue.0.extend(
+        web_drop::take(|name| !gpx::is_gpx(Path::new(name)))
+            .into_iter()
+            .map(|(name, text)| {
+                let (name, _) = file_source(Path::new(&name));
+                (name, OverlaySource::GeoJson(text))
+            }),
+    );
+}
+
+/// Start reading queued overlays in the background. Files are read only where
+/// they cover the loaded tile.
+fn read_overlays(
+    center: Res<WorldCenter>,
+    mut queue: ResMut<OverlayQueue>,
+    mut tasks: ResMut<OverlayTasks>,
+) {
+    let center = center.0;
+    for (name, source) in queue.0.drain(..) {
+        info!("Reading overlay {}", name);
+        let task_name = name.clone();
+        let task = AsyncComputeTaskPool::get().spawn(async move {
+            match source {
+                OverlaySource::GeoJson(text) => read_geojson(&task_name, &text, center),
+                OverlaySource::File(path) => sources::read_file(&path, center, tile_bounds()),
+            }
+        });
+        tasks.0.push((name, task));
+    }
+}
+
+fn spawn_overlays(
+    mut commands: Commands,
+    mut tasks: ResMut<OverlayTasks>,
+    mut style: OverlayStyle,
+    existing: Query<(Entity, &Overlay)>,
+) {
+    let mut pending = Vec::new();
+    for (name, mut task) in tasks.0.drain(..) {
+        let Some(result) = block_on(poll_once(&mut task)) else {
+            pending.push((name, task));
+            continue;
+        };
+        let features = match result {
+            Ok(features) => features,
+            Err(e) => {
+                warn!("Couldn't read overlay {}: {}", name, e);
+                continue;
+            }
+        };
+        for (entity, overlay) in &existing {
+            if overlay.name == name {
+                commands.entity(entity).despawn();
+            }
+        }
+        info!("✓ Loaded overlay {} with {} features", name, features.len());
+        spawn_overlay(&mut commands, &name, features, &mut style);
+    }
+    tasks.0 = pending;
+}
+
+/// Spawn overlay features with the same meshes as tile features: polygons extruded
+/// to their `height` (or draped slabs without one), lines as ribbons and points as
+/// point features. They're styled by rules for the `overlay` layer; each feature's
+/// `overlay` attribute is the file name, for rules that pick one overlay.
+pub fn spawn_overlay(
+    commands: &mut Commands,
+    name: &str,
+    features: Vec<OverlayFeature>,
+    style: &mut OverlayStyle,
+) {
+    let mut lines = Vec::new();
+    for feature in features {
+        let styled = style.style.0.styled(&feature.properties);
+        let properties = feature.properties;
+        for geometry in feature.geometry {
+            match geometry {
+                OverlayGeometry::Polygon(rings) => {
+                    let number = |key: &str| properties.get(key).and_then(|v| v.as_f64());
+                    let height_tags = HeightTags {
+                        height: number("height"),
+                        min_height: number("min_height"),
+                        levels: number("building:levels").or(number("levels")),
+                        min_level: number("building:min_level"),
+                        roof_height: None,
+                    };
+                    let top = style
+                        .style
+                        .0
+                        .heights
+                        .top(&height_tags, None)
+                        .filter(|(_, source)| {
+                            matches!(source, HeightSource::Tag | HeightSource::Levels)
+                        })
+                        .map(|(top, _)| top);
+                    let footprint = Footprint {
+                        geometry: rings,
+                        height: Some(top.unwrap_or(SLAB_HEIGHT)),
+                        min_height: top.map(|_| style.style.0.heights.base(&height_tags)),
+                        height_estimated: false,
+                        roof: None,
+                    };
+                    let draped = top.is_none();
+                    let painted = footprint.painted(&styled.paint);
+                    let Some(mesh) = create_building_mesh(&painted) else {
+                        continue;
+                    };
+                    let lift = if draped {
+                        OVERLAY_LIFT
+                    } else {
+                        footprint.min_height.unwrap_or(0.0) as f32
+                    };
+                    let mut entity = commands.spawn((
+                        Overlay {
+                            name: name.to_string(),
+                        },
+                        Mesh3d(style.meshes.add(mesh)),
+                        MeshMaterial3d(
+                            style
+                                .style_materials
+                                .get(&styled.paint, &mut style.materials),
+                        ),
+                        Transform::from_xyz(0.0, lift, 0.0),
+                        properties.clone(),
+                        styled.clone(),
+                    ));
+                    if draped {
+                        entity.insert(OnTerrain::Drape);
+                    } else if let Some(height) = BuildingHeight::of(&painted) {
+                        entity
+                            .insert((height, OnTerrain::Footprint(footprint.geometry[0].clone())));
+                    }
+                    entity.insert(StyledShape::Extrusion { footprint, draped });
+                }
+                OverlayGeometry::Line(points) => lines.push(Road {
+                    width: properties
+                        .get("width")
+                        .and_then(|v| v.as_f64())
+                        .map_or(LINE_WIDTH, |w| w as f32),
+                    points,
+                    kind: OVERLAY_LAYER.to_string(),
+                    layer: 0,
+                    bridge: false,
+                    tunnel: false,
+                    name: properties.get("name").map(|v| v.to_string()),
+                    properties: properties.clone(),
+                    styled: styled.clone(),
+                }),
+                OverlayGeometry::Point(point) => {
+                    let kind = properties
+                        .get("kind")
+                        .map_or_else(|| OVERLAY_LAYER.to_string(), |v| v.to_string());
+                    commands.spawn((
+                        Overlay {
+                            name: name.to_string(),
+                        },
+                        PointFeature { kind },
+                        properties.clone(),
+                        Transform::from_xyz(point.x, 0.0, point.y),
+                        OnTerrain::Footprint(vec![point]),
+                    ));
+                }
+            }
+        }
+    }
+
+    let nodes = RoadNodes::new(&lines);
+    for line in &lines {
+        let shape = StyledShape::Ribbon {
+            points: line.points.clone(),
+            width: line.width,
+            style: nodes.style_for(line, RoadJoin::Round),
+            deck: None,
+        };
+        let Some(mesh) = shape.mesh(&line.styled.paint) else {
+            continue;
+        };
+        commands.spawn((
+            Overlay {
+                name: name.to_string(),
+            },
+            Mesh3d(style.meshes.add(mesh)),
+            MeshMaterial3d(
+                style
+                    .style_materials
+                    .get(&line.styled.paint, &mut style.materials),
+            ),
+            Transform::from_xyz(0.0, OVERLAY_LIFT, 0.0),
+            OnTerrain::Drape,
+            line.properties.clone(),
+            line.styled.clone(),
+            shape,
+        ));
+    }
+}
+
+/// The browser doesn't hand files dropped on the canvas to winit, so listen for
+/// drops on the page and read them there.
+#[cfg(target_arch = "wasm32")]
+pub mod web_drop {
+    use std::sync::Mutex;
+
+    use wasm_bindgen::{JsCast, closure::Closure};
+    use wasm_bindgen_futures::JsFuture;
+    use web_sys::DragEvent;
+
+    static DROPPED: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+
+    pub fn listen() {
+        let Some(window) = web_sys::window() else {
+            return;
+        };
+        // The page only accepts drops when dragover is cancelled.
+        let over = Closure::<dyn FnMut(DragEvent)>::new(|event: DragEvent| event.prevent_default());
+        let drop = Closure::<dyn FnMut(DragEvent)>::new(|event: DragEvent| {
+            event.prevent_default();
+            let Some(files) = event.data_transfer().and_then(|d| d.files()) else {
+                return;
+            };
+            for file in (0..files.length()).filter_map(|i| files.get(i)) {
+                wasm_bindgen_futures::spawn_local(async move {
+                    let name = file.name();
+                    let text = JsFuture::from(file.text()).await;
+                    if let Some(text) = text.ok().and_then(|t| t.as_string())
+                        && let Ok(mut dropped) = DROPPED.lock()
+                    {
+                        dropped.push((name, text));
+                    }
+                });
+            }
+        });
+        let _ = window.add_event_listener_with_callback("dragover", over.as_ref().unchecked_ref());
+        let _ = window.add_event_listener_with_callback("drop", drop.as_ref().unchecked_ref());
+        // The listeners live as long as the page.
+        over.forget();
+        drop.forget();
+    }
+
+    /// Dropped files whose names match, as (file name, text).
+    pub fn take(matching: impl Fn(&str) -> bool) -> Vec<(String, String)> {
+        let Ok(mut dropped) = DROPPED.lock() else {
+            return Vec::new();
+        };
+        let (taken, kept) = dropped.drain(..).partition(|(name, _)| matching(name));
+        *dropped = kept;
+        taken
+    }
+}
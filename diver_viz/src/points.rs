@@ -0,0 +1,323 @@
+use std::collections::HashMap;
+
+use bevy::{
+    asset::RenderAssetUsages,
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+};
+use geozero::GeomProcessor;
+
+use crate::tile_to_world;
+
+/// Icons float this far above the ground.
+const ICON_HEIGHT: f32 = 6.0;
+const ICON_SIZE: f32 = 3.0;
+/// Pixels along each side of a generated icon.
+const ICON_PIXELS: u32 = 64;
+
+/// A point from the tile, waiting to be given a mesh. Spawned at its world position
+/// and dressed by [`dress_points`], so every tree or bench shares a mesh and material
+/// and Bevy batches them into instanced draws.
+#[derive(Component, Debug, Clone)]
+pub struct PointFeature {
+    pub kind: String,
+}
+
+/// Turns the entity to face the camera every frame.
+#[derive(Component)]
+pub struct Billboard;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Furniture {
+    Bench,
+    WasteBasket,
+    PostBox,
+    BicycleParking,
+    Pole,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum IconCategory {
+    Food,
+    Health,
+    Transport,
+    Culture,
+    Other,
+}
+
+impl IconCategory {
+    fn color(self) -> [u8; 3] {
+        match self {
+            Self::Food => [238, 140, 82],
+            Self::Health => [214, 62, 92],
+            Self::Transport => [82, 120, 214],
+            Self::Culture => [150, 84, 196],
+            Self::Other => [64, 160, 150],
+        }
+    }
+
+    /// Whether pixel `p` (in -1..1 icon space) is part of the white glyph.
+    fn glyph(self, p: Vec2) -> bool {
+        let a = p.abs();
+        match self {
+            Self::Food => p.length() < 0.4,
+            Self::Health => (a.x < 0.14 && a.y < 0.45) || (a.y < 0.14 && a.x < 0.45),
+            Self::Transport => p.y > -0.35 && a.x < (p.y + 0.35) * 0.6,
+            Self::Culture => a.x + a.y < 0.45,
+            Self::Other => a.x < 0.3 && a.y < 0.3,
+        }
+    }
+
+    fn image(self) -> Image {
+        let [r, g, b] = self.color();
+        let mut data = Vec::with_capacity((ICON_PIXELS * ICON_PIXELS * 4) as usize);
+        for y in 0..ICON_PIXELS {
+            for x in 0..ICON_PIXELS {
+                let p = (Vec2::new(x as f32, y as f32) + 0.5) / ICON_PIXELS as f32 * 2.0 - 1.0;
+                // Image rows run downwards; flip so the glyph's +Y is up.
+                let p = Vec2::new(p.x, -p.y);
+                let px = if p.length() > 0.95 {
+                    [0, 0, 0, 0]
+                } else if self.glyph(p) {
+                    [255, 255, 255, 255]
+                } else {
+                    [r, g, b, 255]
+                };
+                data.extend(px);
+            }
+        }
+        Image::new(
+            Extent3d {
+                width: ICON_PIXELS,
+                height: ICON_PIXELS,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            data,
+            TextureFormat::Rgba8UnormSrgb,
+            RenderAssetUsages::RENDER_WORLD,
+        )
+    }
+}
+
+enum PointStyle {
+    Tree,
+    Furniture(Furniture),
+    Icon(IconCategory),
+}
+
+fn style_for(kind: &str) -> PointStyle {
+    match kind {
+        "tree" => PointStyle::Tree,
+        "bench" | "picnic_table" => PointStyle::Furniture(Furniture::Bench),
+        "waste_basket" | "waste_disposal" | "recycling" => {
+            PointStyle::Furniture(Furniture::WasteBasket)
+        }
+        "post_box" | "vending_machine" | "atm" => PointStyle::Furniture(Furniture::PostBox),
+        "bicycle_parking" => PointStyle::Furniture(Furniture::BicycleParking),
+        "bus_stop" | "street_lamp" | "drinking_water" => PointStyle::Furniture(Furniture::Pole),
+        "restaurant" | "cafe" | "fast_food" | "bar" | "pub" | "ice_cream" | "nightclub"
+        | "biergarten" | "food_court" => PointStyle::Icon(IconCategory::Food),
+        "clinic" | "doctors" | "dentist" | "pharmacy" | "hospital" | "veterinary" => {
+            PointStyle::Icon(IconCategory::Health)
+        }
+        "parking" | "parking_entrance" | "bicycle_rental" | "fuel" | "charging_station"
+        | "station" | "car_sharing" => PointStyle::Icon(IconCategory::Transport),
+        "museum" | "theatre" | "artwork" | "memorial" | "place_of_worship" | "library"
+        | "cinema" | "attraction" | "arts_centre" => PointStyle::Icon(IconCategory::Culture),
+        _ => PointStyle::Icon(IconCategory::Other),
+    }
+}
+
+/// Meshes and materials shared by every point of the same style.
+#[derive(Resource)]
+struct PointAssets {
+    trunk: (Handle<Mesh>, Handle<StandardMaterial>),
+    canopy: (Handle<Mesh>, Handle<StandardMaterial>),
+    furniture: HashMap<Furniture, (Handle<Mesh>, f32)>,
+    furniture_material: Handle<StandardMaterial>,
+    icon_mesh: Handle<Mesh>,
+    icons: HashMap<IconCategory, Handle<StandardMaterial>>,
+}
+
+pub struct PointsPlugin;
+
+impl Plugin for PointsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup_point_assets)
+            .add_systems(Update, (dress_points, face_camera));
+    }
+}
+
+fn setup_point_assets(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    let furniture_material = materials.add(StandardMaterial {
+        base_color: Color::srgb(0.42, 0.38, 0.46),
+        perceptual_roughness: 0.7,
+        ..default()
+    });
+    // Mesh and its height, so it can be stood on the ground.
+    let furniture = [
+        (
+            Furniture::Bench,
+            Cuboid::new(1.8, 0.45, 0.5).mesh().build(),
+            0.45,
+        ),
+        (
+            Furniture::WasteBasket,
+            Cylinder::new(0.3, 0.9).mesh().build(),
+            0.9,
+        ),
+        (
+            Furniture::PostBox,
+            Cuboid::new(0.6, 1.3, 0.5).mesh().build(),
+            1.3,
+        ),
+        (
+            Furniture::BicycleParking,
+            Cuboid::new(2.0, 0.8, 0.1).mesh().build(),
+            0.8,
+        ),
+        (
+            Furniture::Pole,
+            Cylinder::new(0.06, 2.6).mesh().build(),
+            2.6,
+        ),
+    ]
+    .into_iter()
+    .map(|(kind, mesh, height)| (kind, (meshes.add(mesh), height)))
+    .collect();
+
+    let icons = [
+        IconCategory::Food,
+        IconCategory::Health,
+        IconCategory::Transport,
+        IconCategory::Culture,
+        IconCategory::Other,
+    ]
+    .into_iter()
+    .map(|category| {
+        let material = materials.add(StandardMaterial {
+            base_color_texture: Some(images.add(category.image())),
+            alpha_mode: AlphaMode::Mask(0.5),
+            unlit: true,
+            ..default()
+        });
+        (category, material)
+    })
+    .collect();
+
+    commands.insert_resource(PointAssets {
+        trunk: (
+            meshes.add(Cylinder::new(0.2, 2.0).mesh().resolution(6).build()),
+            materials.add(StandardMaterial {
+                base_color: Color::srgb(0.45, 0.33, 0.30),
+                perceptual_roughness: 1.0,
+                ..default()
+            }),
+        ),
+        canopy: (
+            meshes.add(Cone::new(1.8, 4.0).mesh().resolution(7).build()),
+            materials.add(StandardMaterial {
+                base_color: Color::srgb(0.22, 0.52, 0.42),
+                perceptual_roughness: 0.9,
+                ..default()
+            }),
+        ),
+        furniture,
+        furniture_material,
+        icon_mesh: meshes.add(Rectangle::new(ICON_SIZE, ICON_SIZE)),
+        icons,
+    });
+}
+
+/// Give freshly spawned points their shared meshes.
+fn dress_points(
+    mut commands: Commands,
+    assets: Option<Res<PointAssets>>,
+    points: Query<(Entity, &PointFeature), Added<PointFeature>>,
+) {
+    let Some(assets) = assets else {
+        return;
+    };
+    for (entity, point) in &points {
+        let mut entity = commands.entity(entity);
+        entity.insert(Visibility::default());
+        match style_for(&point.kind) {
+            PointStyle::Tree => {
+                entity.with_children(|tree| {
+                    tree.spawn((
+                        Mesh3d(assets.trunk.0.clone()),
+                        MeshMaterial3d(assets.trunk.1.clone()),
+                        Transform::from_xyz(0.0, 1.0, 0.0),
+                    ));
+                    tree.spawn((
+                        Mesh3d(assets.canopy.0.clone()),
+                        MeshMaterial3d(assets.canopy.1.clone()),
+                        Transform::from_xyz(0.0, 3.5, 0.0),
+                    ));
+                });
+            }
+            PointStyle::Furniture(kind) => {
+                let (mesh, height) = &assets.furniture[&kind];
+                entity.with_child((
+                    Mesh3d(mesh.clone()),
+                    MeshMaterial3d(assets.furniture_material.clone()),
+                    Transform::from_xyz(0.0, height / 2.0, 0.0),
+                ));
+            }
+            PointStyle::Icon(category) => {
+                entity.with_child((
+                    Mesh3d(assets.icon_mesh.clone()),
+                    MeshMaterial3d(assets.icons[&category].clone()),
+                    Transform::from_xyz(0.0, ICON_HEIGHT, 0.0),
+                    Billboard,
+                ));
+            }
+        }
+    }
+}
+
+fn face_camera(
+    camera: Query<&GlobalTransform, With<Camera3d>>,
+    mut billboards: Query<&mut Transform, With<Billboard>>,
+) {
+    let Ok(camera) = camera.single() else {
+        return;
+    };
+    let rotation = camera.rotation();
+    for mut tf in &mut billboards {
+        tf.rotation = rotation;
+    }
+}
+
+// ---------------------------------------------------------------------------
+// PointProcessor
+// ---------------------------------------------------------------------------
+pub struct PointProcessor {
+    tile_x: u32,
+    tile_y: u32,
+    pub points: Vec<Vec2>,
+}
+
+impl PointProcessor {
+    pub fn new(tile_x: u32, tile_y: u32) -> Self {
+        Self {
+            tile_x,
+            tile_y,
+            points: Vec::new(),
+        }
+    }
+}
+
+impl GeomProcessor for PointProcessor {
+    fn xy(&mut self, x: f64, y: f64, _idx: usize) -> geozero::error::Result<()> {
+        self.points
+            .push(tile_to_world(x, y, self.tile_x, self.tile_y));
+        Ok(())
+    }
+}
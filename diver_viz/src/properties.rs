@@ -0,0 +1,132 @@
+use std::{borrow::Cow, collections::HashMap, fmt};
+
+use bevy::prelude::*;
+use geozero::mvt::tile::{Feature, Layer, Value};
+
+/// A vector tile address.
+#[derive(Reflect, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct TileKey {
+    pub z: u32,
+    pub x: u32,
+    pub y: u32,
+}
+
+impl fmt::Display for TileKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}/{}", self.z, self.x, self.y)
+    }
+}
+
+/// An attribute value as the tile encoded it.
+#[derive(Reflect, Debug, Clone, PartialEq)]
+pub enum PropertyValue {
+    String(String),
+    Float(f32),
+    Double(f64),
+    Int(i64),
+    Uint(u64),
+    Sint(i64),
+    Bool(bool),
+}
+
+impl PropertyValue {
+    fn from_mvt(value: &Value) -> Option<Self> {
+        // Encoders set exactly one field.
+        value
+            .string_value
+            .clone()
+            .map(Self::String)
+            .or(value.float_value.map(Self::Float))
+            .or(value.double_value.map(Self::Double))
+            .or(value.int_value.map(Self::Int))
+            .or(value.uint_value.map(Self::Uint))
+            .or(value.sint_value.map(Self::Sint))
+            .or(value.bool_value.map(Self::Bool))
+    }
+
+    /// The value as a number: numeric values as they are, strings if they parse.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::String(v) => v.trim().parse().ok(),
+            Self::Float(v) => Some(*v as f64),
+            Self::Double(v) => Some(*v),
+            Self::Int(v) | Self::Sint(v) => Some(*v as f64),
+            Self::Uint(v) => Some(*v as f64),
+            Self::Bool(_) => None,
+        }
+    }
+
+    /// Whether the value reads as "yes": true, non-zero, or a string other than
+    /// `no`/`false`/empty.
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Self::String(v) => !matches!(v.as_str(), "" | "no" | "false" | "0"),
+            Self::Bool(v) => *v,
+            other => other.as_f64().is_some_and(|v| v != 0.0),
+        }
+    }
+}
+
+impl fmt::Display for PropertyValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::String(v) => f.write_str(v),
+            Self::Float(v) => write!(f, "{v}"),
+            Self::Double(v) => write!(f, "{v}"),
+            Self::Int(v) | Self::Sint(v) => write!(f, "{v}"),
+            Self::Uint(v) => write!(f, "{v}"),
+            Self::Bool(v) => write!(f, "{v}"),
+        }
+    }
+}
+
+/// Everything the tile said about a feature, kept on the entities spawned from it
+/// for picking, filtering and restyling.
+#[derive(Component, Reflect, Debug, Clone, Default)]
+#[reflect(Component)]
+pub struct FeatureProperties {
+    pub id: Option<u64>,
+    pub layer: String,
+    pub tile: TileKey,
+    pub tags: HashMap<String, PropertyValue>,
+    /// Columns joined from an external table by id. They win over tile attributes
+    /// of the same name.
+    pub joined: HashMap<String, PropertyValue>,
+}
+
+impl FeatureProperties {
+    pub fn decode(feature: &Feature, layer: &Layer, tile: TileKey) -> Self {
+        let tags = feature
+            .tags
+            .chunks_exact(2)
+            .filter_map(|pair| {
+                let key = layer.keys.get(pair[0] as usize)?;
+                let value = PropertyValue::from_mvt(layer.values.get(pair[1] as usize)?)?;
+                Some((key.clone(), value))
+            })
+            .collect();
+        Self {
+            id: feature.id,
+            layer: layer.name.clone(),
+            tile,
+            tags,
+            joined: HashMap::new(),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&PropertyValue> {
+        self.joined.get(key).or_else(|| self.tags.get(key))
+    }
+
+    /// An attribute as style rules and filters match it: as [`get`](Self::get),
+    /// except that landuse without a `kind` reads as `other`, as it's drawn.
+    pub fn field(&self, key: &str) -> Option<Cow<'_, PropertyValue>> {
+        match self.get(key) {
+            Some(value) => Some(Cow::Borrowed(value)),
+            None if key == "kind" && self.layer == "landuse" => {
+                Some(Cow::Owned(PropertyValue::String("other".to_string())))
+            }
+            None => None,
+        }
+    }
+}
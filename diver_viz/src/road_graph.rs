@@ -0,0 +1,520 @@
+use std::collections::{HashMap, HashSet};
+use std::f32::consts::FRAC_PI_2;
+
+use bevy::{
+    prelude::*,
+    window::{CursorGrabMode, CursorOptions, PrimaryWindow},
+};
+
+use crate::filter;
+use crate::properties::{PropertyValue, TileKey};
+use crate::road_mesh::node_key;
+use crate::terrain::{Heightmap, ground_point};
+use crate::{MVT_EXTENT, Road, WorldCenter, tile_to_world};
+
+/// Edges are bucketed on a grid this many metres across for nearest-edge lookups.
+const GRID_CELL: f32 = 50.0;
+/// Ends cut at a tile edge closer than this are the same junction.
+const EDGE_SNAP: f32 = 0.05;
+/// The graph view draws this far above the ground.
+const VIEW_LIFT: f32 = 1.0;
+/// The graph view picks out the edge within this many metres of the cursor.
+const HOVER_DISTANCE: f32 = 30.0;
+
+/// Which way traffic may drive along a road, relative to its drawing direction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Oneway {
+    No,
+    Forward,
+    Backward,
+}
+
+impl Oneway {
+    /// OSM `oneway`: `yes` (or true/1) with the way, `-1` against it.
+    fn from_tag(value: Option<&PropertyValue>) -> Self {
+        match value.map(|v| v.to_string()).as_deref() {
+            Some("yes" | "true" | "1") => Self::Forward,
+            Some("-1" | "reverse") => Self::Backward,
+            _ => Self::No,
+        }
+    }
+}
+
+/// A road's centreline as the graph sees it. Every decoded road carries one,
+/// including tunnels that aren't drawn, so the graph follows roads as they are
+/// spawned and despawned.
+#[derive(Component, Debug, Clone)]
+pub struct RoadCenterline {
+    pub points: Vec<Vec2>,
+    pub kind: String,
+    /// `kind_detail`, e.g. `residential` or `footway`.
+    pub class: Option<String>,
+    pub oneway: Oneway,
+    pub name: Option<String>,
+    /// The tile it was decoded from; it's cut to that tile's edges.
+    pub tile: TileKey,
+}
+
+impl RoadCenterline {
+    pub fn of(road: &Road) -> Self {
+        Self {
+            points: road.points.clone(),
+            kind: road.kind.clone(),
+            class: road.properties.get("kind_detail").map(|v| v.to_string()),
+            oneway: Oneway::from_tag(road.properties.get("oneway")),
+            name: road.name.clone(),
+            tile: road.properties.tile,
+        }
+    }
+}
+
+pub type NodeId = usize;
+pub type EdgeId = usize;
+
+/// A junction, dead end or tile-edge crossing.
+#[derive(Debug, Clone)]
+pub struct GraphNode {
+    pub position: Vec2,
+    pub edges: Vec<EdgeId>,
+}
+
+/// A stretch of road between two nodes, with no junctions along it.
+#[derive(Debug, Clone)]
+pub struct GraphEdge {
+    pub from: NodeId,
+    pub to: NodeId,
+    /// From `from` to `to`, both ends included.
+    pub points: Vec<Vec2>,
+    /// Metres.
+    pub length: f32,
+    pub kind: String,
+    pub class: Option<String>,
+    pub oneway: Oneway,
+    pub name: Option<String>,
+}
+
+impl GraphEdge {
+    /// Whether the edge may be travelled from `from` to `to` (`forward`), or back.
+    pub fn allows(&self, forward: bool) -> bool {
+        match self.oneway {
+            Oneway::No => true,
+            Oneway::Forward => forward,
+            Oneway::Backward => !forward,
+        }
+    }
+}
+
+/// The nearest point on the graph to somewhere.
+#[derive(Debug, Clone, Copy)]
+pub struct EdgeHit {
+    pub edge: EdgeId,
+    pub point: Vec2,
+    pub distance: f32,
+    /// Metres along the edge from its `from` node.
+    pub along: f32,
+}
+
+/// Where two quantised positions are the same node. Ends cut at a tile edge snap
+/// more loosely, since the neighbouring tile cuts the same road a hair away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum NodeKey {
+    Vertex((i64, i64)),
+    TileEdge((i64, i64)),
+}
+
+/// The road network of the loaded tiles: nodes where roads share a vertex or
+/// cross a tile edge, and edges between them.
+#[derive(Resource, Debug, Default)]
+pub struct RoadGraph {
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+    /// Edges passing through each grid cell.
+    grid: HashMap<(i32, i32), Vec<EdgeId>>,
+}
+
+impl RoadGraph {
+    /// Build the graph from road centrelines. Each road is cut to its own tile so
+    /// roads that run on across the tile buffer don't overlap their neighbours,
+    /// then split wherever another road shares a vertex.
+    pub fn build<'a>(roads: impl IntoIterator<Item = &'a RoadCenterline>, center: Vec2) -> Self {
+        let mut pieces = Vec::new();
+        for road in roads {
+            let min = tile_to_world(0.0, 0.0, road.tile.x, road.tile.y) - center;
+            let max = tile_to_world(MVT_EXTENT, MVT_EXTENT, road.tile.x, road.tile.y) - center;
+            let key = |p: Vec2| {
+                let on_edge = (p.x - min.x).abs() < EDGE_SNAP
+                    || (p.x - max.x).abs() < EDGE_SNAP
+                    || (p.y - min.y).abs() < EDGE_SNAP
+                    || (p.y - max.y).abs() < EDGE_SNAP;
+                if on_edge {
+                    let q = (p / EDGE_SNAP).round();
+                    NodeKey::TileEdge((q.x as i64, q.y as i64))
+                } else {
+                    NodeKey::Vertex(node_key(p))
+                }
+            };
+            for piece in clip_line(&road.points, min, max) {
+                let keys: Vec<NodeKey> = piece.iter().map(|p| key(*p)).collect();
+                pieces.push((road, piece, keys));
+            }
+        }
+
+        let mut counts: HashMap<NodeKey, usize> = HashMap::new();
+        for (.., keys) in &pieces {
+            // A closed way touching its own start isn't a junction.
+            for key in keys.iter().collect::<HashSet<_>>() {
+                *counts.entry(*key).or_insert(0) += 1;
+            }
+        }
+
+        let mut graph = Self::default();
+        let mut node_ids: HashMap<NodeKey, NodeId> = HashMap::new();
+        let mut node = |graph: &mut Self, key: NodeKey, position: Vec2| {
+            *node_ids.entry(key).or_insert_with(|| {
+                graph.nodes.push(GraphNode {
+                    position,
+                    edges: Vec::new(),
+                });
+                graph.nodes.len() - 1
+            })
+        };
+        for (road, piece, keys) in &pieces {
+            let last = piece.len() - 1;
+            let mut from = node(&mut graph, keys[0], piece[0]);
+            let mut points = vec![piece[0]];
+            for (i, (p, key)) in piece.iter().zip(keys).enumerate().skip(1) {
+                if points.last() != Some(p) {
+                    points.push(*p);
+                }
+                if i != last && counts[key] < 2 {
+                    continue;
+                }
+                let to = node(&mut graph, *key, *p);
+                let points = std::mem::replace(&mut points, vec![*p]);
+                if points.len() < 2 {
+                    continue;
+                }
+                graph.add_edge(GraphEdge {
+                    from,
+                    to,
+                    length: points.windows(2).map(|w| w[0].distance(w[1])).sum(),
+                    points,
+                    kind: road.kind.clone(),
+                    class: road.class.clone(),
+                    oneway: road.oneway,
+                    name: road.name.clone(),
+                });
+                from = to;
+            }
+        }
+        graph
+    }
+
+    fn add_edge(&mut self, edge: GraphEdge) {
+        let id = self.edges.len();
+        self.nodes[edge.from].edges.push(id);
+        if edge.to != edge.from {
+            self.nodes[edge.to].edges.push(id);
+        }
+        let mut cells = HashSet::new();
+        for w in edge.points.windows(2) {
+            let (lo, hi) = (cell(w[0].min(w[1])), cell(w[0].max(w[1])));
+            for x in lo.0..=hi.0 {
+                for y in lo.1..=hi.1 {
+                    cells.insert((x, y));
+                }
+            }
+        }
+        for c in cells {
+            self.grid.entry(c).or_default().push(id);
+        }
+        self.edges.push(edge);
+    }
+
+    /// Edges leaving `node` and the node at their other end. With `respect_oneway`,
+    /// one-way edges are only left in their direction of travel.
+    pub fn outgoing(
+        &self,
+        node: NodeId,
+        respect_oneway: bool,
+    ) -> impl Iterator<Item = (EdgeId, NodeId)> + '_ {
+        self.nodes[node].edges.iter().filter_map(move |&id| {
+            let edge = &self.edges[id];
+            let forward = edge.from == node;
+            (!respect_oneway || edge.allows(forward))
+                .then_some((id, if forward { edge.to } else { edge.from }))
+        })
+    }
+
+    /// Total length of road in the graph, in metres.
+    pub fn length(&self) -> f32 {
+        self.edges.iter().map(|e| e.length).sum()
+    }
+
+    /// The edge passing closest to `p`, if any is within `max_distance` metres.
+    pub fn nearest_edge(&self, p: Vec2, max_distance: f32) -> Option<EdgeHit> {
+        let (lo, hi) = (cell(p - max_distance), cell(p + max_distance));
+        let mut candidates: Vec<EdgeId> = (lo.0..=hi.0)
+            .flat_map(|x| (lo.1..=hi.1).map(move |y| (x, y)))
+            .filter_map(|c| self.grid.get(&c))
+            .flatten()
+            .copied()
+            .collect();
+        candidates.sort_unstable();
+        candidates.dedup();
+
+        let mut best: Option<EdgeHit> = None;
+        for id in candidates {
+            let mut along = 0.0;
+            for w in self.edges[id].points.windows(2) {
+                let (a, b) = (w[0], w[1]);
+                let length = a.distance(b);
+                let t = if length > 0.0 {
+                    ((p - a).dot(b - a) / (length * length)).clamp(0.0, 1.0)
+                } else {
+                    0.0
+                };
+                let point = a.lerp(b, t);
+                let distance = point.distance(p);
+                if distance <= max_distance && best.is_none_or(|hit| distance < hit.distance) {
+                    best = Some(EdgeHit {
+                        edge: id,
+                        point,
+                        distance,
+                        along: along + length * t,
+                    });
+                }
+                along += length;
+            }
+        }
+        best
+    }
+}
+
+fn cell(p: Vec2) -> (i32, i32) {
+    let c = (p / GRID_CELL).floor();
+    (c.x as i32, c.y as i32)
+}
+
+/// The parts of a polyline inside the box from `min` to `max`.
+fn clip_line(points: &[Vec2], min: Vec2, max: Vec2) -> Vec<Vec<Vec2>> {
+    let mut pieces = Vec::new();
+    let mut piece: Vec<Vec2> = Vec::new();
+    let mut finish = |piece: &mut Vec<Vec2>| {
+        if piece.len() >= 2 {
+            pieces.push(std::mem::take(piece));
+        }
+        piece.clear();
+    };
+    for w in points.windows(2) {
+        let (a, b) = (w[0], w[1]);
+        let Some((t0, t1)) = clip_segment(a, b, min, max) else {
+            finish(&mut piece);
+            continue;
+        };
+        if t0 > 0.0 {
+            finish(&mut piece);
+        }
+        if piece.is_empty() {
+            piece.push(a.lerp(b, t0));
+        }
+        piece.push(a.lerp(b, t1));
+        if t1 < 1.0 {
+            finish(&mut piece);
+        }
+    }
+    finish(&mut piece);
+    pieces
+}
+
+/// Liang-Barsky: the range of `t` along a to b that lies inside the box.
+fn clip_segment(a: Vec2, b: Vec2, min: Vec2, max: Vec2) -> Option<(f32, f32)> {
+    let d = b - a;
+    let (mut t0, mut t1) = (0.0f32, 1.0f32);
+    for (p, q) in [
+        (-d.x, a.x - min.x),
+        (d.x, max.x - a.x),
+        (-d.y, a.y - min.y),
+        (d.y, max.y - a.y),
+    ] {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None;
+            }
+            continue;
+        }
+        let r = q / p;
+        if p < 0.0 {
+            t0 = t0.max(r);
+        } else {
+            t1 = t1.min(r);
+        }
+        if t0 > t1 {
+            return None;
+        }
+    }
+    Some((t0, t1))
+}
+
+// ---------------------------------------------------------------------------
+// Keeping the graph up to date, and looking at it
+// ---------------------------------------------------------------------------
+
+/// Whether the graph is drawn over the scene.
+#[derive(Resource, Debug, Default)]
+pub struct GraphView {
+    pub shown: bool,
+}
+
+#[derive(Component)]
+struct GraphText;
+
+pub struct RoadGraphPlugin;
+
+impl Plugin for RoadGraphPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<RoadGraph>()
+            .init_resource::<GraphView>()
+            .add_systems(Startup, spawn_graph_text)
+            .add_systems(
+                Update,
+                (
+                    update_road_graph.run_if(resource_exists::<WorldCenter>),
+                    toggle_graph_view.run_if(not(filter::editing_filter)),
+                    draw_graph,
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// Rebuild the graph whenever roads come or go, as tiles load and unload.
+fn update_road_graph(
+    center: Res<WorldCenter>,
+    added: Query<(), Added<RoadCenterline>>,
+    mut removed: RemovedComponents<RoadCenterline>,
+    roads: Query<&RoadCenterline>,
+    mut graph: ResMut<RoadGraph>,
+) {
+    let removed = removed.read().count();
+    if added.is_empty() && removed == 0 {
+        return;
+    }
+    *graph = RoadGraph::build(roads, center.0);
+    info!(
+        "✓ Built road graph: {} nodes, {} edges, {:.1} km of road",
+        graph.nodes.len(),
+        graph.edges.len(),
+        graph.length() / 1000.0
+    );
+}
+
+/// G shows and hides the graph.
+fn toggle_graph_view(keys: Res<ButtonInput<KeyCode>>, mut view: ResMut<GraphView>) {
+    if keys.just_pressed(KeyCode::KeyG) {
+        view.shown = !view.shown;
+    }
+}
+
+fn spawn_graph_text(mut commands: Commands) {
+    commands.spawn((
+        GraphText,
+        Text::default(),
+        TextFont {
+            font_size: 14.0,
+            ..default()
+        },
+        Node {
+            position_type: PositionType::Absolute,
+            left: px(12),
+            bottom: px(92),
+            padding: UiRect::all(px(6)),
+            ..default()
+        },
+        BackgroundColor(Color::srgba(0.12, 0.08, 0.16, 0.85)),
+        Visibility::Hidden,
+    ));
+}
+
+/// Draw edges (one-way ones in orange) and their nodes, and describe the edge
+/// nearest the cursor.
+fn draw_graph(
+    view: Res<GraphView>,
+    graph: Res<RoadGraph>,
+    heightmap: Option<Res<Heightmap>>,
+    window: Single<(&Window, &CursorOptions), With<PrimaryWindow>>,
+    camera: Single<(&Camera, &GlobalTransform), With<Camera3d>>,
+    text: Single<(&mut Text, &mut Visibility), With<GraphText>>,
+    mut gizmos: Gizmos,
+) {
+    let (mut text, mut visibility) = text.into_inner();
+    if !view.shown {
+        *visibility = Visibility::Hidden;
+        return;
+    }
+    let heightmap = heightmap.as_deref();
+    let lift = |p: Vec2| {
+        let ground = heightmap.map_or(0.0, |h| h.sample(p));
+        Vec3::new(p.x, ground + VIEW_LIFT, p.y)
+    };
+    for edge in &graph.edges {
+        let color = match edge.oneway {
+            Oneway::No => Color::srgb(0.3, 0.9, 1.0),
+            _ => Color::srgb(1.0, 0.6, 0.2),
+        };
+        gizmos.linestrip(edge.points.iter().map(|p| lift(*p)), color);
+    }
+    for node in &graph.nodes {
+        let color = match node.edges.len() {
+            1 => Color::srgb(1.0, 0.3, 0.3),
+            _ => Color::WHITE,
+        };
+        gizmos.circle(
+            Isometry3d::new(lift(node.position), Quat::from_rotation_x(-FRAC_PI_2)),
+            1.0,
+            color,
+        );
+    }
+
+    let (window, cursor_options) = *window;
+    let (camera, camera_tf) = *camera;
+    let hit = window
+        .cursor_position()
+        .filter(|_| cursor_options.grab_mode == CursorGrabMode::None)
+        .and_then(|cursor| camera.viewport_to_world(camera_tf, cursor).ok())
+        .and_then(|ray| ground_point(ray, heightmap))
+        .and_then(|p| graph.nearest_edge(p, HOVER_DISTANCE));
+    let Some(hit) = hit else {
+        text.0 = format!(
+            "road graph: {} nodes, {} edges",
+            graph.nodes.len(),
+            graph.edges.len()
+        );
+        *visibility = Visibility::Inherited;
+        return;
+    };
+    let edge = &graph.edges[hit.edge];
+    gizmos.linestrip(
+        edge.points.iter().map(|p| lift(*p) + Vec3::Y * 0.2),
+        Color::srgb(1.0, 1.0, 0.3),
+    );
+    gizmos.sphere(lift(hit.point), 1.5, Color::srgb(1.0, 1.0, 0.3));
+    let mut line = format!("edge {}: {}", hit.edge, edge.kind);
+    if let Some(class) = &edge.class {
+        line.push_str(&format!(" ({class})"));
+    }
+    if let Some(name) = &edge.name {
+        line.push_str(&format!(" {name}"));
+    }
+    line.push_str(&format!(
+        ", {:.0} m from node {} to {}, cursor {:.0} m along",
+        edge.length, edge.from, edge.to, hit.along
+    ));
+    match edge.oneway {
+        Oneway::No => {}
+        Oneway::Forward => line.push_str(", one-way"),
+        Oneway::Backward => line.push_str(", one-way against the way"),
+    }
+    text.0 = line;
+    *visibility = Visibility::Inherited;
+}
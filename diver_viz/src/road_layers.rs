@@ -0,0 +1,111 @@
+use bevy::{mesh::VertexAttributeValues, prelude::*};
+
+use crate::Road;
+
+/// Surface roads sit this high, clear of the landuse slabs.
+const ROAD_SURFACE_HEIGHT: f32 = 0.15;
+/// Gap between stacked road layers at grade, so they sort without z-fighting.
+const LAYER_STEP: f32 = 0.03;
+/// Decks rise to full height over at most this many metres from each end.
+const BRIDGE_RAMP_LENGTH: f32 = 40.0;
+
+/// What to do with roads tagged as tunnels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TunnelDisplay {
+    Hidden,
+    /// Drawn translucent just under the surface roads.
+    Ghost,
+}
+
+/// How `layer`, `bridge` and `tunnel` turn into heights.
+#[derive(Resource, Debug, Clone)]
+pub struct RoadLayers {
+    /// Deck height per `layer` level for bridges, in metres.
+    pub bridge_clearance: f32,
+    /// Distance between bridge supports, in metres.
+    pub support_spacing: f32,
+    pub tunnels: TunnelDisplay,
+}
+
+impl Default for RoadLayers {
+    fn default() -> Self {
+        Self {
+            bridge_clearance: 6.0,
+            support_spacing: 30.0,
+            tunnels: TunnelDisplay::Ghost,
+        }
+    }
+}
+
+impl RoadLayers {
+    /// Height of the road surface where it touches the ground. Higher `layer`s
+    /// and bigger road classes draw on top.
+    pub fn surface_height(&self, road: &Road) -> f32 {
+        let class_rank = match road.kind.as_str() {
+            "highway" => 3.0,
+            "major_road" => 2.0,
+            "minor_road" => 1.0,
+            _ => 0.0,
+        };
+        let layer = if road.tunnel {
+            // Tunnels sit between the landuse and the surface roads.
+            -4.0
+        } else {
+            road.layer.clamp(-3, 3) as f32
+        };
+        ROAD_SURFACE_HEIGHT + class_rank * 0.01 + layer * LAYER_STEP
+    }
+
+    /// Height of a bridge deck over the ground at its middle, `None` for roads at grade.
+    pub fn deck_height(&self, road: &Road) -> Option<f32> {
+        (road.bridge && !road.tunnel).then(|| road.layer.max(1) as f32 * self.bridge_clearance)
+    }
+}
+
+/// Deck height at `along` metres into a bridge of total `length`: level in the
+/// middle and ramping smoothly down to meet the approach roads at either end.
+pub fn deck_profile(deck: f32, along: f32, length: f32) -> f32 {
+    let ramp = BRIDGE_RAMP_LENGTH.min(length / 3.0).max(1e-3);
+    let t = (along.min(length - along) / ramp).clamp(0.0, 1.0);
+    deck * t * t * (3.0 - 2.0 * t)
+}
+
+/// Lift a road ribbon into a bridge deck, using the along-road distance
+/// the ribbon mesher writes into UV `v`.
+pub fn raise_deck(mesh: &mut Mesh, deck: f32, length: f32) {
+    let Some(VertexAttributeValues::Float32x2(uvs)) = mesh.attribute(Mesh::ATTRIBUTE_UV_0) else {
+        return;
+    };
+    let lift: Vec<f32> = uvs
+        .iter()
+        .map(|uv| deck_profile(deck, uv[1], length))
+        .collect();
+    if let Some(VertexAttributeValues::Float32x3(positions)) =
+        mesh.attribute_mut(Mesh::ATTRIBUTE_POSITION)
+    {
+        for (p, y) in positions.iter_mut().zip(lift) {
+            p[1] += y;
+        }
+    }
+}
+
+/// Points along a bridge where supports stand, with the deck height above each.
+/// Low parts near the ramps don't get one.
+pub fn support_positions(points: &[Vec2], deck: f32, spacing: f32) -> Vec<(Vec2, f32)> {
+    let length: f32 = points.windows(2).map(|w| w[0].distance(w[1])).sum();
+    let mut supports = Vec::new();
+    let mut next = spacing / 2.0;
+    let mut walked = 0.0;
+    for w in points.windows(2) {
+        let seg = w[0].distance(w[1]);
+        while next <= walked + seg && seg > 0.0 {
+            let height = deck_profile(deck, next, length);
+            if height > 1.0 {
+                supports.push((w[0].lerp(w[1], (next - walked) / seg), height));
+            }
+            next += spacing;
+        }
+        walked += seg;
+    }
+    supports
+}
@@ -0,0 +1,289 @@
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+use bevy::{
+    asset::RenderAssetUsages,
+    mesh::{Indices, PrimitiveTopology},
+    prelude::*,
+};
+
+use crate::Road;
+
+/// Segments used for a half circle in round joins and caps.
+const ROUND_SEGMENTS: usize = 8;
+/// Mitres longer than this many half-widths are bevelled instead.
+const MITRE_LIMIT: f32 = 2.5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RoadJoin {
+    Mitre,
+    Round,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RoadCap {
+    Butt,
+    Round,
+}
+
+/// How a single polyline is turned into a ribbon.
+#[derive(Debug, Clone, Copy)]
+pub struct RibbonStyle {
+    pub join: RoadJoin,
+    pub start_cap: RoadCap,
+    pub end_cap: RoadCap,
+}
+
+/// Shared nodes between roads, keyed on a quantised position so ways that
+/// meet at the same tile coordinate are recognised as joined.
+pub struct RoadNodes {
+    counts: HashMap<(i64, i64), usize>,
+}
+
+impl RoadNodes {
+    pub fn new(roads: &[Road]) -> Self {
+        let mut counts = HashMap::new();
+        for road in roads {
+            let mut seen = Vec::new();
+            for p in &road.points {
+                let key = node_key(*p);
+                // A closed way touching its own start isn't an intersection.
+                if !seen.contains(&key) {
+                    seen.push(key);
+                    *counts.entry(key).or_insert(0) += 1;
+                }
+            }
+        }
+        Self { counts }
+    }
+
+    /// Whether more than one road passes through `p`.
+    pub fn is_junction(&self, p: Vec2) -> bool {
+        self.counts.get(&node_key(p)).is_some_and(|&n| n > 1)
+    }
+
+    /// Ribbon style for a road: ends that meet another road get round caps so the
+    /// corners of the intersection fill in, loose ends stop square.
+    pub fn style_for(&self, road: &Road, join: RoadJoin) -> RibbonStyle {
+        let cap = |p: Option<&Vec2>| match p {
+            Some(p) if self.is_junction(*p) => RoadCap::Round,
+            _ => RoadCap::Butt,
+        };
+        RibbonStyle {
+            join,
+            start_cap: cap(road.points.first()),
+            end_cap: cap(road.points.last()),
+        }
+    }
+}
+
+/// Quantised position roads are matched up on.
+pub fn node_key(p: Vec2) -> (i64, i64) {
+    const QUANTUM: f32 = 1e-3;
+    (
+        (p.x / QUANTUM).round() as i64,
+        (p.y / QUANTUM).round() as i64,
+    )
+}
+
+/// Flat, upward-facing triangle list with road UVs.
+#[derive(Default)]
+struct RibbonBuilder {
+    positions: Vec<[f32; 3]>,
+    uvs: Vec<[f32; 2]>,
+    indices: Vec<u32>,
+}
+
+impl RibbonBuilder {
+    /// `u` runs across the road (0 on the left edge, 1 on the right),
+    /// `v` is the distance along the centreline in world units.
+    fn vertex(&mut self, p: Vec2, u: f32, v: f32) -> u32 {
+        self.positions.push([p.x, 0.0, p.y]);
+        self.uvs.push([u, v]);
+        self.positions.len() as u32 - 1
+    }
+
+    /// Triangle wound to face +Y whichever order the corners come in.
+    fn tri(&mut self, a: u32, b: u32, c: u32) {
+        let pos = |i: u32| {
+            let p = self.positions[i as usize];
+            Vec2::new(p[0], p[2])
+        };
+        let area = (pos(b) - pos(a)).perp_dot(pos(c) - pos(a));
+        if area.abs() < 1e-12 {
+            return;
+        }
+        // Counter-clockwise in (x, z) faces -Y in Bevy's right-handed space.
+        if area > 0.0 {
+            self.indices.extend([a, c, b]);
+        } else {
+            self.indices.extend([a, b, c]);
+        }
+    }
+
+    fn quad(&mut self, a: u32, b: u32, c: u32, d: u32) {
+        self.tri(a, b, c);
+        self.tri(a, c, d);
+    }
+
+    /// Fan around `origin` starting at offset `from` and turning counter-clockwise by `sweep`
+    /// radians. Returns the centre vertex and the arc's vertices, ends included.
+    fn arc(
+        &mut self,
+        origin: Vec2,
+        from: Vec2,
+        sweep: f32,
+        u: (f32, f32),
+        v: f32,
+    ) -> (u32, Vec<u32>) {
+        let center = self.vertex(origin, (u.0 + u.1) / 2.0, v);
+        let steps = ((sweep.abs() / PI * ROUND_SEGMENTS as f32).ceil() as usize).max(1);
+        let mut ring = Vec::with_capacity(steps + 1);
+        for s in 0..=steps {
+            let t = s as f32 / steps as f32;
+            let offset = Vec2::from_angle(sweep * t).rotate(from);
+            ring.push(self.vertex(origin + offset, u.0 + (u.1 - u.0) * t, v));
+        }
+        for w in ring.windows(2) {
+            self.tri(center, w[0], w[1]);
+        }
+        (center, ring)
+    }
+
+    fn into_mesh(self) -> Option<Mesh> {
+        if self.indices.is_empty() {
+            return None;
+        }
+        let normals = vec![[0.0, 1.0, 0.0]; self.positions.len()];
+        let mut mesh = Mesh::new(
+            PrimitiveTopology::TriangleList,
+            RenderAssetUsages::default(),
+        );
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, self.uvs);
+        mesh.insert_indices(Indices::U32(self.indices));
+        Some(mesh)
+    }
+}
+
+/// Stroke a polyline into a flat ribbon at Y=0, `width` wide.
+///
+/// Unlike buffering the line into polygons, the ribbon keeps its topology: every
+/// vertex knows how far along the road it is, which ends up in UV `v` for lane
+/// markings and dashes.
+pub fn road_ribbon_mesh(points: &[Vec2], width: f32, style: RibbonStyle) -> Option<Mesh> {
+    let mut pts: Vec<Vec2> = Vec::with_capacity(points.len());
+    for p in points {
+        if pts.last().is_none_or(|last| last.distance(*p) > 1e-5) {
+            pts.push(*p);
+        }
+    }
+    if pts.len() < 2 || width <= 0.0 {
+        return None;
+    }
+
+    let hw = width / 2.0;
+    let dirs: Vec<Vec2> = pts.windows(2).map(|w| (w[1] - w[0]).normalize()).collect();
+    // Left of the direction of travel, in (x, z).
+    let left = |d: Vec2| d.perp();
+
+    let mut out = RibbonBuilder::default();
+    let mut along = 0.0_f32;
+
+    // Start of the ribbon.
+    let n0 = left(dirs[0]);
+    let mut prev_l = out.vertex(pts[0] + n0 * hw, 0.0, 0.0);
+    let mut prev_r = out.vertex(pts[0] - n0 * hw, 1.0, 0.0);
+    if style.start_cap == RoadCap::Round {
+        // Left edge round through the back of the road to the right edge.
+        out.arc(pts[0], n0 * hw, PI, (0.0, 1.0), 0.0);
+    }
+
+    for i in 1..pts.len() {
+        along += pts[i].distance(pts[i - 1]);
+        let p = pts[i];
+        let d_in = dirs[i - 1];
+        let n_in = left(d_in);
+
+        if i == pts.len() - 1 {
+            let l = out.vertex(p + n_in * hw, 0.0, along);
+            let r = out.vertex(p - n_in * hw, 1.0, along);
+            out.quad(prev_l, prev_r, r, l);
+            if style.end_cap == RoadCap::Round {
+                // Right edge round through the front of the road to the left edge.
+                out.arc(p, -n_in * hw, PI, (1.0, 0.0), along);
+            }
+            break;
+        }
+
+        let d_out = dirs[i];
+        let n_out = left(d_out);
+        let turn = d_in.perp_dot(d_out);
+
+        // Nearly straight: one shared cross-section.
+        if turn.abs() < 1e-4 && d_in.dot(d_out) > 0.0 {
+            let l = out.vertex(p + n_in * hw, 0.0, along);
+            let r = out.vertex(p - n_in * hw, 1.0, along);
+            out.quad(prev_l, prev_r, r, l);
+            prev_l = l;
+            prev_r = r;
+            continue;
+        }
+
+        // The inside corner is where the two offset edges cross; the outside
+        // corner gets the join. Turning left (turn > 0) puts the outside on the right.
+        let outer_sign = if turn > 0.0 { -1.0 } else { 1.0 };
+        let mitre = (n_in + n_out).normalize_or_zero();
+        let mitre_len = hw / mitre.dot(n_in).max(1e-3);
+        let inner_len = mitre_len.min(hw * MITRE_LIMIT);
+        let inner_pt = p - mitre * outer_sign * inner_len;
+        let (u_inner, u_outer) = if outer_sign > 0.0 {
+            (1.0, 0.0)
+        } else {
+            (0.0, 1.0)
+        };
+        let inner = out.vertex(inner_pt, u_inner, along);
+
+        let outer_in = p + n_in * outer_sign * hw;
+        let outer_out = p + n_out * outer_sign * hw;
+        let outer_ring: Vec<u32> = match style.join {
+            RoadJoin::Mitre if mitre_len <= hw * MITRE_LIMIT => {
+                vec![out.vertex(p + mitre * outer_sign * mitre_len, u_outer, along)]
+            }
+            RoadJoin::Mitre => {
+                let a = out.vertex(outer_in, u_outer, along);
+                let b = out.vertex(outer_out, u_outer, along);
+                out.tri(inner, a, b);
+                vec![a, b]
+            }
+            RoadJoin::Round => {
+                let (c, ring) = out.arc(
+                    p,
+                    outer_in - p,
+                    (outer_in - p).angle_to(outer_out - p),
+                    (u_outer, u_outer),
+                    along,
+                );
+                out.tri(c, inner, ring[0]);
+                out.tri(c, inner, ring[ring.len() - 1]);
+                ring
+            }
+        };
+        let first = outer_ring[0];
+        let last = outer_ring[outer_ring.len() - 1];
+
+        // Close the incoming segment, then carry the far side of the join forward.
+        if outer_sign > 0.0 {
+            out.quad(prev_l, prev_r, inner, first);
+            prev_l = last;
+            prev_r = inner;
+        } else {
+            out.quad(prev_l, prev_r, first, inner);
+            prev_l = inner;
+            prev_r = last;
+        }
+    }
+
+    out.into_mesh()
+}
@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+/// How road widths are worked out, in metres.
+///
+/// A `width` tag wins, then `lanes` times the lane width, then the table entry for
+/// the highway class (`kind_detail`), then the entry for the broad `kind`.
+#[derive(Resource, Debug, Clone)]
+pub struct RoadWidths {
+    pub lane_width: f32,
+    /// Keyed by highway class: `primary`, `residential`, `footway`, ...
+    pub by_class: HashMap<String, f32>,
+    /// Keyed by road `kind`: `major_road`, `minor_road`, `path`, ...
+    pub by_kind: HashMap<String, f32>,
+    pub default_width: f32,
+}
+
+impl Default for RoadWidths {
+    fn default() -> Self {
+        let table = |entries: &[(&str, f32)]| {
+            entries
+                .iter()
+                .map(|(k, w)| (k.to_string(), *w))
+                .collect::<HashMap<_, _>>()
+        };
+        Self {
+            lane_width: 3.5,
+            by_class: table(&[
+                ("motorway", 14.0),
+                ("trunk", 12.0),
+                ("primary", 12.0),
+                ("secondary", 10.0),
+                ("tertiary", 8.0),
+                ("motorway_link", 6.0),
+                ("trunk_link", 6.0),
+                ("primary_link", 6.0),
+                ("secondary_link", 6.0),
+                ("tertiary_link", 6.0),
+                ("residential", 7.0),
+                ("unclassified", 6.0),
+                ("living_street", 5.0),
+                ("busway", 4.0),
+                ("service", 4.0),
+                ("pedestrian", 5.0),
+                ("track", 3.0),
+                ("crossing", 2.5),
+                ("steps", 2.0),
+                ("cycleway", 2.0),
+                ("footway", 1.5),
+                ("sidewalk", 1.5),
+                ("path", 1.5),
+            ]),
+            by_kind: table(&[
+                ("highway", 14.0),
+                ("major_road", 10.0),
+                ("minor_road", 6.0),
+                ("path", 1.5),
+            ]),
+            default_width: 3.0,
+        }
+    }
+}
+
+/// The width-related attributes of a road feature.
+#[derive(Debug, Clone, Default)]
+pub struct RoadWidthTags {
+    pub width: Option<String>,
+    pub lanes: Option<f64>,
+    pub class: Option<String>,
+    pub kind: String,
+}
+
+impl RoadWidths {
+    pub fn width(&self, tags: &RoadWidthTags) -> f32 {
+        tags.width
+            .as_deref()
+            .and_then(parse_width)
+            .or_else(|| {
+                tags.lanes
+                    .filter(|l| *l > 0.0)
+                    .map(|l| l as f32 * self.lane_width)
+            })
+            .or_else(|| {
+                tags.class
+                    .as_ref()
+                    .and_then(|c| self.by_class.get(c).copied())
+            })
+            .or_else(|| self.by_kind.get(&tags.kind).copied())
+            .unwrap_or(self.default_width)
+    }
+}
+
+/// Parse an OSM `width`: metres by default, or with an `m`, `ft` or `'` suffix.
+fn parse_width(value: &str) -> Option<f32> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f32 = number.parse().ok()?;
+    let metres = match unit.trim() {
+        "" | "m" => number,
+        "ft" | "'" => number * 0.3048,
+        _ => return None,
+    };
+    (metres > 0.0).then_some(metres)
+}
@@ -0,0 +1,395 @@
+use bevy::{
+    asset::RenderAssetUsages,
+    mesh::{Indices, PrimitiveTopology},
+    prelude::*,
+};
+
+use crate::triangulate_polygon;
+
+/// Pitch used to guess a roof height when the feature has a shape but no `roof:height`.
+const DEFAULT_ROOF_PITCH_DEG: f32 = 30.0;
+/// Skillion roofs span the whole footprint, so they get a shallower default.
+const DEFAULT_SKILLION_PITCH_DEG: f32 = 15.0;
+/// Number of rings stacked between the eave and the top of a dome.
+const DOME_SEGMENTS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RoofShape {
+    Flat,
+    Gabled,
+    Hipped,
+    Pyramidal,
+    Skillion,
+    Dome,
+}
+
+impl RoofShape {
+    /// Parse an OSM `roof:shape` value. Unknown shapes return `None` and render flat.
+    pub fn from_tag(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "flat" => Some(Self::Flat),
+            "gabled" => Some(Self::Gabled),
+            "hipped" => Some(Self::Hipped),
+            "pyramidal" => Some(Self::Pyramidal),
+            "skillion" => Some(Self::Skillion),
+            "dome" => Some(Self::Dome),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Roof {
+    pub shape: RoofShape,
+    /// `roof:height`, part of the building's total height.
+    pub height: Option<f64>,
+    /// `roof:direction` in compass degrees (0 = north, clockwise).
+    /// The direction the roof slope faces, i.e. downhill for a skillion.
+    pub direction: Option<f64>,
+}
+
+/// Parse `roof:direction`, which OSM allows as degrees or as a compass point ("N", "SSW", ...).
+pub fn parse_direction(value: &str) -> Option<f64> {
+    let value = value.trim();
+    if let Ok(deg) = value.parse::<f64>() {
+        return Some(deg.rem_euclid(360.0));
+    }
+    const POINTS: [&str; 16] = [
+        "N", "NNE", "NE", "ENE", "E", "ESE", "SE", "SSE", "S", "SSW", "SW", "WSW", "W", "WNW",
+        "NW", "NNW",
+    ];
+    let upper = value.to_ascii_uppercase();
+    POINTS
+        .iter()
+        .position(|p| *p == upper)
+        .map(|i| i as f64 * 22.5)
+}
+
+/// Unit vector in the ground plane for a compass bearing.
+/// World X is east and world Z (ring `.y`) grows southward, like tile Y.
+fn bearing_to_vec(deg: f64) -> Vec2 {
+    let rad = deg.to_radians() as f32;
+    Vec2::new(rad.sin(), -rad.cos())
+}
+
+/// A footprint-aligned frame: `u` runs along the ridge, `v` across it.
+/// `a` and `b` are the half extents along `u` and `v`.
+struct RoofFrame {
+    center: Vec2,
+    u: Vec2,
+    v: Vec2,
+    a: f32,
+    b: f32,
+}
+
+impl RoofFrame {
+    /// Frame with the given `u` axis, sized to fit the ring.
+    fn with_axis(ring: &[Vec2], u: Vec2) -> Self {
+        let v = u.perp();
+        let (mut min, mut max) = (Vec2::splat(f32::MAX), Vec2::splat(f32::MIN));
+        for p in ring {
+            let l = Vec2::new(p.dot(u), p.dot(v));
+            min = min.min(l);
+            max = max.max(l);
+        }
+        let mid = (min + max) / 2.0;
+        let half = (max - min) / 2.0;
+        Self {
+            center: u * mid.x + v * mid.y,
+            u,
+            v,
+            a: half.x,
+            b: half.y,
+        }
+    }
+
+    /// Minimum-area bounding rectangle over the ring's edge directions,
+    /// with `u` along its longer side.
+    fn fit(ring: &[Vec2]) -> Self {
+        let mut best: Option<Self> = None;
+        for i in 0..ring.len() {
+            let edge = ring[(i + 1) % ring.len()] - ring[i];
+            if edge.length_squared() < 1e-12 {
+                continue;
+            }
+            let frame = Self::with_axis(ring, edge.normalize());
+            if best.as_ref().is_none_or(|b| frame.a * frame.b < b.a * b.b) {
+                best = Some(frame);
+            }
+        }
+        let frame = best.unwrap_or_else(|| Self::with_axis(ring, Vec2::X));
+        if frame.a < frame.b {
+            Self::with_axis(ring, frame.v)
+        } else {
+            frame
+        }
+    }
+}
+
+/// A plane over the footprint: roof fraction (0 at the eave, 1 at the top)
+/// as `k + p.dot(grad)` for a ground point `p`.
+#[derive(Clone, Copy)]
+struct RoofPlane {
+    k: f32,
+    grad: Vec2,
+}
+
+impl RoofPlane {
+    /// Plane from a function of the frame's local (u, v) coordinates: `k + ku * u + kv * v`.
+    fn from_local(frame: &RoofFrame, k: f32, ku: f32, kv: f32) -> Self {
+        let grad = frame.u * ku + frame.v * kv;
+        Self {
+            k: k - frame.center.dot(grad),
+            grad,
+        }
+    }
+
+    fn eval(&self, p: Vec2) -> f32 {
+        self.k + p.dot(self.grad)
+    }
+}
+
+/// Gabled, hipped, pyramidal and skillion roofs are the lower envelope of a few planes,
+/// so every facet is the part of the footprint where its plane is the minimum.
+fn roof_planes(ring: &[Vec2], roof: &Roof) -> (RoofFrame, Vec<RoofPlane>) {
+    let frame = match (roof.shape, roof.direction) {
+        // The slope faces `direction`, so the ridge runs perpendicular to it.
+        (_, Some(dir)) => RoofFrame::with_axis(ring, -bearing_to_vec(dir).perp()),
+        _ => RoofFrame::fit(ring),
+    };
+    let (a, b) = (frame.a.max(1e-3), frame.b.max(1e-3));
+    let m = a.min(b);
+    let planes = match roof.shape {
+        RoofShape::Gabled => vec![
+            RoofPlane::from_local(&frame, 1.0, 0.0, -1.0 / b),
+            RoofPlane::from_local(&frame, 1.0, 0.0, 1.0 / b),
+        ],
+        RoofShape::Hipped => vec![
+            RoofPlane::from_local(&frame, b / m, 0.0, -1.0 / m),
+            RoofPlane::from_local(&frame, b / m, 0.0, 1.0 / m),
+            RoofPlane::from_local(&frame, a / m, -1.0 / m, 0.0),
+            RoofPlane::from_local(&frame, a / m, 1.0 / m, 0.0),
+        ],
+        RoofShape::Pyramidal => vec![
+            RoofPlane::from_local(&frame, 1.0, 0.0, -1.0 / b),
+            RoofPlane::from_local(&frame, 1.0, 0.0, 1.0 / b),
+            RoofPlane::from_local(&frame, 1.0, -1.0 / a, 0.0),
+            RoofPlane::from_local(&frame, 1.0, 1.0 / a, 0.0),
+        ],
+        // Highest on the side opposite the direction the roof faces.
+        RoofShape::Skillion => vec![RoofPlane::from_local(&frame, 0.5, 0.0, -0.5 / b)],
+        RoofShape::Flat | RoofShape::Dome => vec![RoofPlane::from_local(&frame, 0.0, 0.0, 0.0)],
+    };
+    (frame, planes)
+}
+
+/// Keep the part of `poly` where `f(p) <= 0`, for a linear `f` (Sutherland–Hodgman, one edge).
+fn clip_half_plane(poly: &[Vec2], f: impl Fn(Vec2) -> f32) -> Vec<Vec2> {
+    let mut out = Vec::new();
+    for i in 0..poly.len() {
+        let p0 = poly[i];
+        let p1 = poly[(i + 1) % poly.len()];
+        let (f0, f1) = (f(p0), f(p1));
+        if f0 <= 0.0 {
+            out.push(p0);
+        }
+        if (f0 <= 0.0) != (f1 <= 0.0) {
+            out.push(p0 + (p1 - p0) * (f0 / (f0 - f1)));
+        }
+    }
+    out
+}
+
+/// Triangle list with one normal per face, wound so the face points along `outward`.
+#[derive(Default)]
+struct FlatMesh {
+    positions: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+}
+
+impl FlatMesh {
+    fn tri(&mut self, a: Vec3, b: Vec3, c: Vec3, outward: Vec3) {
+        let n = (b - a).cross(c - a);
+        if n.length_squared() < 1e-12 {
+            return;
+        }
+        let (b, c, n) = if n.dot(outward) < 0.0 {
+            (c, b, -n)
+        } else {
+            (b, c, n)
+        };
+        let n = n.normalize().to_array();
+        self.positions
+            .extend([a.to_array(), b.to_array(), c.to_array()]);
+        self.normals.extend([n, n, n]);
+    }
+
+    fn quad(&mut self, a: Vec3, b: Vec3, c: Vec3, d: Vec3, outward: Vec3) {
+        self.tri(a, b, c, outward);
+        self.tri(a, c, d, outward);
+    }
+
+    /// Triangulated 2-D polygon lifted to 3-D by `y`, facing `outward`.
+    fn cap(&mut self, poly: &[Vec2], y: impl Fn(Vec2) -> f32, outward: Vec3) {
+        for chunk in triangulate_polygon(poly).chunks(3) {
+            let [a, b, c] = [chunk[0], chunk[1], chunk[2]].map(|i| {
+                let p = poly[i];
+                Vec3::new(p.x, y(p), p.y)
+            });
+            self.tri(a, b, c, outward);
+        }
+    }
+
+    fn into_mesh(self) -> Mesh {
+        let count = self.positions.len() as u32;
+        let mut mesh = Mesh::new(
+            PrimitiveTopology::TriangleList,
+            RenderAssetUsages::default(),
+        );
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals);
+        mesh.insert_indices(Indices::U32((0..count).collect()));
+        mesh
+    }
+}
+
+/// Build a building prism topped with a shaped roof.
+///
+/// `height` is the total height, as in OSM: the walls stop at `height - roof height`
+/// (the eave) and the roof rises from there to `height`.
+pub fn roofed_building_mesh(ring: &[Vec2], height: f32, roof: &Roof) -> Option<Mesh> {
+    let mut ring: Vec<Vec2> = ring.to_vec();
+    if ring.len() > 3 && ring.first() == ring.last() {
+        ring.pop();
+    }
+    if ring.len() < 3 {
+        return None;
+    }
+    // Work on a CCW ring so "outward" is always to the right of an edge.
+    let signed_area: f32 = (0..ring.len())
+        .map(|i| ring[i].perp_dot(ring[(i + 1) % ring.len()]))
+        .sum();
+    if signed_area < 0.0 {
+        ring.reverse();
+    }
+
+    let (frame, planes) = roof_planes(&ring, roof);
+    let half_short = frame.a.min(frame.b);
+    let default_height = match roof.shape {
+        RoofShape::Flat => 0.0,
+        RoofShape::Dome => half_short,
+        RoofShape::Skillion => 2.0 * frame.b * DEFAULT_SKILLION_PITCH_DEG.to_radians().tan(),
+        _ => half_short * DEFAULT_ROOF_PITCH_DEG.to_radians().tan(),
+    };
+    let roof_height = match roof.height {
+        Some(h) => (h as f32).clamp(0.0, height),
+        None => default_height.min(height * 0.5),
+    };
+    let eave = height - roof_height;
+
+    let mut out = FlatMesh::default();
+    out.cap(&ring, |_| 0.0, Vec3::NEG_Y);
+
+    if roof.shape == RoofShape::Dome {
+        dome(&mut out, &ring, eave, roof_height);
+        return Some(out.into_mesh());
+    }
+
+    let roof_y = |p: Vec2| {
+        let t = planes
+            .iter()
+            .map(|pl| pl.eval(p))
+            .fold(f32::MAX, f32::min)
+            .clamp(0.0, 1.0);
+        eave + roof_height * t
+    };
+
+    // Roof facets: clip the footprint to where each plane is the lowest.
+    for (i, plane) in planes.iter().enumerate() {
+        let mut facet = ring.clone();
+        for (j, other) in planes.iter().enumerate() {
+            if i != j {
+                facet = clip_half_plane(&facet, |p| plane.eval(p) - other.eval(p));
+            }
+        }
+        if facet.len() >= 3 {
+            out.cap(&facet, roof_y, Vec3::Y);
+        }
+    }
+
+    // Walls run up to the roof line, split wherever the lowest plane changes
+    // so gable ends come out as proper triangles.
+    for i in 0..ring.len() {
+        let p0 = ring[i];
+        let p1 = ring[(i + 1) % ring.len()];
+        let edge = p1 - p0;
+        let outward = Vec3::new(edge.y, 0.0, -edge.x);
+
+        let mut cuts = vec![0.0, 1.0];
+        for (j, pa) in planes.iter().enumerate() {
+            for pb in &planes[j + 1..] {
+                let (d0, d1) = (pa.eval(p0) - pb.eval(p0), pa.eval(p1) - pb.eval(p1));
+                if (d0 < 0.0) != (d1 < 0.0) {
+                    cuts.push(d0 / (d0 - d1));
+                }
+            }
+        }
+        cuts.sort_by(f32::total_cmp);
+
+        for w in cuts.windows(2) {
+            let (a, b) = (p0 + edge * w[0], p0 + edge * w[1]);
+            out.quad(
+                Vec3::new(a.x, 0.0, a.y),
+                Vec3::new(b.x, 0.0, b.y),
+                Vec3::new(b.x, roof_y(b), b.y),
+                Vec3::new(a.x, roof_y(a), a.y),
+                outward,
+            );
+        }
+    }
+
+    Some(out.into_mesh())
+}
+
+/// Walls to the eave, then rings shrinking towards the footprint centroid.
+fn dome(out: &mut FlatMesh, ring: &[Vec2], eave: f32, roof_height: f32) {
+    let n = ring.len();
+    let centroid = ring.iter().copied().sum::<Vec2>() / n as f32;
+
+    for i in 0..n {
+        let (p0, p1) = (ring[i], ring[(i + 1) % n]);
+        let edge = p1 - p0;
+        out.quad(
+            Vec3::new(p0.x, 0.0, p0.y),
+            Vec3::new(p1.x, 0.0, p1.y),
+            Vec3::new(p1.x, eave, p1.y),
+            Vec3::new(p0.x, eave, p0.y),
+            Vec3::new(edge.y, 0.0, -edge.x),
+        );
+    }
+
+    let ring_at = |step: usize| -> Vec<Vec3> {
+        let phi = step as f32 / DOME_SEGMENTS as f32 * std::f32::consts::FRAC_PI_2;
+        let y = eave + roof_height * phi.sin();
+        ring.iter()
+            .map(|p| {
+                let q = centroid + (*p - centroid) * phi.cos();
+                Vec3::new(q.x, y, q.y)
+            })
+            .collect()
+    };
+    let apex = Vec3::new(centroid.x, eave + roof_height, centroid.y);
+
+    let mut lower = ring_at(0);
+    for step in 1..DOME_SEGMENTS {
+        let upper = ring_at(step);
+        for i in 0..n {
+            let j = (i + 1) % n;
+            out.quad(lower[i], lower[j], upper[j], upper[i], Vec3::Y);
+        }
+        lower = upper;
+    }
+    for i in 0..n {
+        out.tri(lower[i], lower[(i + 1) % n], apex, Vec3::Y);
+    }
+}
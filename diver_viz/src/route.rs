@@ -0,0 +1,714 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+use bevy::{
+    ecs::system::SystemParam,
+    prelude::*,
+    window::{CursorGrabMode, CursorOptions, PrimaryWindow},
+};
+
+use crate::filter;
+use crate::isochrone::{self, IsochroneRequest};
+use crate::properties::{FeatureProperties, PropertyValue};
+use crate::road_graph::{EdgeHit, EdgeId, GraphEdge, NodeId, RoadGraph};
+use crate::road_mesh::{RibbonStyle, RoadCap, RoadJoin};
+use crate::shadow::{self, ShadowStudy};
+use crate::style::{ActiveStyle, StyleMaterials, StyledShape};
+use crate::terrain::{Heightmap, OnTerrain, ground_point};
+
+/// Tile layer name routes are styled under.
+pub const ROUTE_LAYER: &str = "route";
+/// Clicks further than this from any road don't place a route end.
+const SNAP_DISTANCE: f32 = 100.0;
+/// Ribbon width when the style doesn't set one.
+const ROUTE_WIDTH: f32 = 4.0;
+/// Lift over roads, tracks and overlays.
+const ROUTE_LIFT: f32 = 0.4;
+const PIN_RADIUS: f32 = 2.5;
+/// The start and end pins float this far above the ground.
+const PIN_LIFT: f32 = 3.0;
+
+/// How the route is travelled: which roads are allowed and how fast they go.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Profile {
+    #[default]
+    Walking,
+    Cycling,
+    Driving,
+}
+
+impl Profile {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Walking => Self::Cycling,
+            Self::Cycling => Self::Driving,
+            Self::Driving => Self::Walking,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Walking => "walking",
+            Self::Cycling => "cycling",
+            Self::Driving => "driving",
+        }
+    }
+
+    /// Metres per second along an edge, from its `kind` and `kind_detail`, or
+    /// `None` where this profile can't go.
+    pub fn speed(self, edge: &GraphEdge) -> Option<f32> {
+        let kind = edge.kind.as_str();
+        let class = edge.class.as_deref().unwrap_or_default();
+        let motorway = matches!(class, "motorway" | "motorway_link" | "trunk" | "trunk_link");
+        let not_a_street = matches!(kind, "rail" | "aerialway" | "ferry" | "piste");
+        match self {
+            Self::Walking => match class {
+                _ if kind == "highway" || motorway || not_a_street => None,
+                "steps" => Some(0.7),
+                _ => Some(1.4),
+            },
+            Self::Cycling => match class {
+                _ if kind == "highway" || motorway || not_a_street => None,
+                "steps" => None,
+                "cycleway" => Some(5.5),
+                // Shared with people walking, or rough.
+                "footway" | "pedestrian" | "path" | "track" | "bridleway" => Some(3.0),
+                _ if kind == "major_road" => Some(5.0),
+                _ => Some(4.5),
+            },
+            Self::Driving => match kind {
+                "highway" => Some(25.0),
+                "major_road" => Some(13.0),
+                "minor_road" if matches!(class, "service" | "living_street") => Some(4.0),
+                "minor_road" => Some(8.0),
+                _ => None,
+            },
+        }
+    }
+
+    /// The fastest any edge goes, which keeps the A* estimate from overshooting.
+    pub fn max_speed(self) -> f32 {
+        match self {
+            Self::Walking => 1.4,
+            Self::Cycling => 5.5,
+            Self::Driving => 25.0,
+        }
+    }
+
+    /// People on foot may walk either way down a one-way street.
+    pub fn respects_oneway(self) -> bool {
+        self != Self::Walking
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Route {
+    pub points: Vec<Vec2>,
+    /// Metres.
+    pub length: f32,
+    pub seconds: f32,
+}
+
+/// How the search reached a node.
+#[derive(Debug, Clone, Copy)]
+enum Step {
+    /// Along part of the start edge, towards its `to` node if `forward`.
+    Start { forward: bool },
+    Edge {
+        edge: EdgeId,
+        forward: bool,
+        prev: NodeId,
+    },
+}
+
+/// A node waiting in a search's open set.
+#[derive(Debug, PartialEq)]
+pub struct Open {
+    pub estimate: f32,
+    pub node: NodeId,
+}
+
+impl Eq for Open {}
+
+impl Ord for Open {
+    // Reversed, so the heap pops the lowest estimate first.
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.estimate.total_cmp(&self.estimate)
+    }
+}
+
+impl PartialOrd for Open {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// The quickest way from a point on one edge to a point on another, by A* over
+/// the graph. Start and end are mid-edge, so the search starts from both ends of
+/// the start edge and finishes through either end of the end edge.
+pub fn shortest_path(
+    graph: &RoadGraph,
+    start: EdgeHit,
+    end: EdgeHit,
+    profile: Profile,
+) -> Option<Route> {
+    let oneway = profile.respects_oneway();
+    let allowed = |edge: &GraphEdge, forward: bool| {
+        let speed = profile.speed(edge)?;
+        (!oneway || edge.allows(forward)).then_some(speed)
+    };
+    let start_edge = &graph.edges[start.edge];
+    let end_edge = &graph.edges[end.edge];
+
+    // Both on one edge: straight along it, if that's allowed.
+    let mut best: Option<(f32, Option<(NodeId, bool)>)> = None;
+    if start.edge == end.edge {
+        let forward = end.along >= start.along;
+        if let Some(speed) = allowed(start_edge, forward) {
+            best = Some(((end.along - start.along).abs() / speed, None));
+        }
+    }
+
+    // Into the end edge from its `from` node runs forward to `along`, from `to` back.
+    let mut finish: HashMap<NodeId, (f32, bool)> = HashMap::new();
+    for (node, forward, distance) in [
+        (end_edge.from, true, end.along),
+        (end_edge.to, false, end_edge.length - end.along),
+    ] {
+        if let Some(speed) = allowed(end_edge, forward) {
+            let seconds = distance / speed;
+            if finish.get(&node).is_none_or(|(s, _)| seconds < *s) {
+                finish.insert(node, (seconds, forward));
+            }
+        }
+    }
+
+    let estimate =
+        |node: NodeId| graph.nodes[node].position.distance(end.point) / profile.max_speed();
+    let mut cost = vec![f32::INFINITY; graph.nodes.len()];
+    let mut came_from: Vec<Option<Step>> = vec![None; graph.nodes.len()];
+    let mut open = BinaryHeap::new();
+    for (node, forward, distance) in [
+        (start_edge.to, true, start_edge.length - start.along),
+        (start_edge.from, false, start.along),
+    ] {
+        if let Some(speed) = allowed(start_edge, forward) {
+            let seconds = distance / speed;
+            if seconds < cost[node] {
+                cost[node] = seconds;
+                came_from[node] = Some(Step::Start { forward });
+                open.push(Open {
+                    estimate: seconds + estimate(node),
+                    node,
+                });
+            }
+        }
+    }
+
+    while let Some(Open { estimate: f, node }) = open.pop() {
+        if best.is_some_and(|(total, _)| f >= total) {
+            break;
+        }
+        if f > cost[node] + estimate(node) {
+            // A stale entry; the node was reached more cheaply since.
+            continue;
+        }
+        if let Some((seconds, forward)) = finish.get(&node) {
+            let total = cost[node] + seconds;
+            if best.is_none_or(|(b, _)| total < b) {
+                best = Some((total, Some((node, *forward))));
+            }
+        }
+        for (edge, next) in graph.outgoing(node, oneway) {
+            let Some(speed) = profile.speed(&graph.edges[edge]) else {
+                continue;
+            };
+            let seconds = cost[node] + graph.edges[edge].length / speed;
+            if seconds < cost[next] {
+                cost[next] = seconds;
+                came_from[next] = Some(Step::Edge {
+                    edge,
+                    forward: graph.edges[edge].from == node,
+                    prev: node,
+                });
+                open.push(Open {
+                    estimate: seconds + estimate(next),
+                    node: next,
+                });
+            }
+        }
+    }
+
+    let (seconds, last) = best?;
+    let mut points = match last {
+        None => slice(&start_edge.points, start.along, end.along),
+        Some((last, end_forward)) => {
+            let mut legs = Vec::new();
+            let mut node = last;
+            let start_forward = loop {
+                match came_from[node]? {
+                    Step::Start { forward } => break forward,
+                    Step::Edge {
+                        edge,
+                        forward,
+                        prev,
+                    } => {
+                        legs.push((edge, forward));
+                        node = prev;
+                    }
+                }
+            };
+            let mut points = slice(
+                &start_edge.points,
+                start.along,
+                if start_forward {
+                    start_edge.length
+                } else {
+                    0.0
+                },
+            );
+            for (edge, forward) in legs.into_iter().rev() {
+                let edge = &graph.edges[edge].points;
+                if forward {
+                    points.extend(edge);
+                } else {
+                    points.extend(edge.iter().rev());
+                }
+            }
+            points.extend(slice(
+                &end_edge.points,
+                if end_forward { 0.0 } else { end_edge.length },
+                end.along,
+            ));
+            points
+        }
+    };
+    points.dedup();
+    Some(Route {
+        length: points.windows(2).map(|w| w[0].distance(w[1])).sum(),
+        points,
+        seconds,
+    })
+}
+
+/// The part of a polyline from `a` to `b` metres along it, reversed if `b` comes first.
+fn slice(points: &[Vec2], a: f32, b: f32) -> Vec<Vec2> {
+    if a > b {
+        let mut part = slice(points, b, a);
+        part.reverse();
+        return part;
+    }
+    let mut part = vec![point_along(points, a)];
+    let mut along = 0.0;
+    for w in points.windows(2) {
+        along += w[0].distance(w[1]);
+        if along > a && along < b {
+            part.push(w[1]);
+        }
+    }
+    part.push(point_along(points, b));
+    part
+}
+
+/// The point `distance` metres along a polyline.
+pub fn point_along(points: &[Vec2], distance: f32) -> Vec2 {
+    let mut along = 0.0;
+    for w in points.windows(2) {
+        let length = w[0].distance(w[1]);
+        if along + length >= distance {
+            let t = if length > 0.0 {
+                (distance - along) / length
+            } else {
+                0.0
+            };
+            return w[0].lerp(w[1], t);
+        }
+        along += length;
+    }
+    points.last().copied().unwrap_or_default()
+}
+
+/// `850 m` or `1.8 km`.
+pub fn format_length(metres: f32) -> String {
+    if metres < 1000.0 {
+        format!("{metres:.0} m")
+    } else {
+        format!("{:.1} km", metres / 1000.0)
+    }
+}
+
+/// `12 min` or `1 h 05 min`.
+fn format_minutes(seconds: f32) -> String {
+    let minutes = (seconds / 60.0).round() as u32;
+    match minutes / 60 {
+        0 => format!("{} min", minutes.max(1)),
+        h => format!("{} h {:02} min", h, minutes % 60),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Picking and drawing
+// ---------------------------------------------------------------------------
+
+/// The route being asked for: while `active`, clicks place the start, then the end.
+#[derive(Resource, Debug, Default)]
+pub struct RouteRequest {
+    pub active: bool,
+    pub profile: Profile,
+    pub start: Option<Vec2>,
+    pub end: Option<Vec2>,
+}
+
+/// The last route found, or why there isn't one.
+#[derive(Resource, Debug, Default)]
+pub struct RouteResult(pub Option<Result<Route, String>>);
+
+/// Leave routing when a walkshed is being placed, so a click only does one thing.
+pub fn stop_routing(request: &mut RouteRequest) {
+    if request.active {
+        *request = RouteRequest {
+            profile: request.profile,
+            ..default()
+        };
+    }
+}
+
+/// Run condition: clicks place route ends instead of inspecting features.
+pub fn routing(request: Res<RouteRequest>) -> bool {
+    request.active
+}
+
+/// The ribbon and pins of the drawn route.
+#[derive(Component)]
+struct RoutePart;
+
+#[derive(Component)]
+struct RouteText;
+
+/// Everything that decides how the route looks.
+#[derive(SystemParam)]
+struct RouteStyle<'w> {
+    style: Res<'w, ActiveStyle>,
+    style_materials: ResMut<'w, StyleMaterials>,
+    materials: ResMut<'w, Assets<StandardMaterial>>,
+    meshes: ResMut<'w, Assets<Mesh>>,
+}
+
+pub struct RoutePlugin;
+
+impl Plugin for RoutePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<RouteRequest>()
+            .init_resource::<RouteResult>()
+            .add_systems(Startup, spawn_route_text)
+            .add_systems(
+                Update,
+                (
+                    route_controls.run_if(not(filter::editing_filter)),
+                    pick_route_ends.run_if(routing),
+                    find_route
+                        .run_if(resource_changed::<RouteRequest>.or(resource_changed::<RoadGraph>)),
+                    draw_route.run_if(
+                        resource_changed::<RouteResult>.and(resource_exists::<ActiveStyle>),
+                    ),
+                    show_route_text.run_if(
+                        resource_changed::<RouteRequest>.or(resource_changed::<RouteResult>),
+                    ),
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// T starts routing, or stops and clears the route; M switches between walking,
+/// cycling and driving.
+fn route_controls(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut request: ResMut<RouteRequest>,
+    mut isochrone: ResMut<IsochroneRequest>,
+    mut shadow: ResMut<ShadowStudy>,
+) {
+    if keys.just_pressed(KeyCode::KeyT) {
+        *request = RouteRequest {
+            active: !request.active,
+            profile: request.profile,
+            ..default()
+        };
+        if request.active {
+            isochrone::stop_placing(&mut isochrone);
+            shadow::stop_placing(&mut shadow);
+        }
+    }
+    if keys.just_pressed(KeyCode::KeyM) && request.active {
+        request.profile = request.profile.next();
+    }
+}
+
+/// With the cursor free, a left click sets the start, the next one the end, and
+/// the one after that starts over.
+fn pick_route_ends(
+    mouse: Res<ButtonInput<MouseButton>>,
+    window: Single<(&Window, &CursorOptions), With<PrimaryWindow>>,
+    camera: Single<(&Camera, &GlobalTransform), With<Camera3d>>,
+    heightmap: Option<Res<Heightmap>>,
+    mut request: ResMut<RouteRequest>,
+) {
+    let (window, cursor_options) = *window;
+    if !mouse.just_pressed(MouseButton::Left) || cursor_options.grab_mode != CursorGrabMode::None {
+        return;
+    }
+    let (camera, camera_tf) = *camera;
+    let Some(point) = window
+        .cursor_position()
+        .and_then(|cursor| camera.viewport_to_world(camera_tf, cursor).ok())
+        .and_then(|ray| ground_point(ray, heightmap.as_deref()))
+    else {
+        return;
+    };
+    match (request.start, request.end) {
+        (Some(_), None) => request.end = Some(point),
+        _ => {
+            request.start = Some(point);
+            request.end = None;
+        }
+    }
+}
+
+fn find_route(request: Res<RouteRequest>, graph: Res<RoadGraph>, mut result: ResMut<RouteResult>) {
+    let (Some(start), Some(end)) = (request.start, request.end) else {
+        result.0 = None;
+        return;
+    };
+    let snap = |p: Vec2| graph.nearest_edge(p, SNAP_DISTANCE);
+    result.0 = Some(match (snap(start), snap(end)) {
+        (Some(start), Some(end)) => shortest_path(&graph, start, end, request.profile)
+            .ok_or_else(|| format!("no {} route between those points", request.profile.label())),
+        _ => Err(format!("no road within {SNAP_DISTANCE:.0} m of that point")),
+    });
+}
+
+/// Replace the drawn route: a ribbon styled by rules for the `route` layer, and
+/// pins at its ends.
+fn draw_route(
+    mut commands: Commands,
+    result: Res<RouteResult>,
+    request: Res<RouteRequest>,
+    parts: Query<Entity, With<RoutePart>>,
+    mut style: RouteStyle,
+) {
+    for entity in &parts {
+        commands.entity(entity).despawn();
+    }
+    let Some(Ok(route)) = &result.0 else {
+        return;
+    };
+
+    let mut tags = HashMap::new();
+    tags.insert(
+        "profile".to_string(),
+        PropertyValue::String(request.profile.label().to_string()),
+    );
+    tags.insert(
+        "length".to_string(),
+        PropertyValue::Double(route.length.round() as f64),
+    );
+    tags.insert(
+        "minutes".to_string(),
+        PropertyValue::Double((route.seconds / 6.0).round() as f64 / 10.0),
+    );
+    let properties = FeatureProperties {
+        layer: ROUTE_LAYER.to_string(),
+        tags,
+        ..default()
+    };
+    let styled = style.style.0.styled(&properties);
+    let shape = StyledShape::Ribbon {
+        points: route.points.clone(),
+        width: ROUTE_WIDTH,
+        style: RibbonStyle {
+            join: RoadJoin::Round,
+            start_cap: RoadCap::Round,
+            end_cap: RoadCap::Round,
+        },
+        deck: None,
+    };
+    if let Some(mesh) = shape.mesh(&styled.paint) {
+        commands.spawn((
+            RoutePart,
+            Mesh3d(style.meshes.add(mesh)),
+            MeshMaterial3d(
+                style
+                    .style_materials
+                    .get(&styled.paint, &mut style.materials),
+            ),
+            Transform::from_xyz(0.0, ROUTE_LIFT, 0.0),
+            OnTerrain::Drape,
+            properties,
+            styled,
+            shape,
+        ));
+    }
+
+    let pin = style.meshes.add(Sphere::new(PIN_RADIUS));
+    let ends = [
+        (route.points.first(), Color::srgb(0.2, 0.85, 0.35)),
+        (route.points.last(), Color::srgb(0.95, 0.25, 0.3)),
+    ];
+    for (point, color) in ends {
+        let Some(point) = point.copied() else {
+            continue;
+        };
+        commands.spawn((
+            RoutePart,
+            Mesh3d(pin.clone()),
+            MeshMaterial3d(style.materials.add(StandardMaterial {
+                base_color: color,
+                unlit: true,
+                ..default()
+            })),
+            Transform::from_xyz(point.x, PIN_LIFT, point.y),
+            OnTerrain::Footprint(vec![point]),
+        ));
+    }
+}
+
+fn spawn_route_text(mut commands: Commands) {
+    commands.spawn((
+        RouteText,
+        Text::default(),
+        TextFont {
+            font_size: 14.0,
+            ..default()
+        },
+        Node {
+            position_type: PositionType::Absolute,
+            left: px(12),
+            bottom: px(132),
+            padding: UiRect::all(px(6)),
+            ..default()
+        },
+        BackgroundColor(Color::srgba(0.12, 0.08, 0.16, 0.85)),
+        Visibility::Hidden,
+    ));
+}
+
+fn show_route_text(
+    request: Res<RouteRequest>,
+    result: Res<RouteResult>,
+    text: Single<(&mut Text, &mut Visibility), With<RouteText>>,
+) {
+    let (mut text, mut visibility) = text.into_inner();
+    if !request.active {
+        *visibility = Visibility::Hidden;
+        return;
+    }
+    *visibility = Visibility::Inherited;
+    let profile = request.profile.label();
+    text.0 = match (&result.0, request.start) {
+        (Some(Ok(route)), _) => format!(
+            "{}: {}, about {}  (M to change, click to start again)",
+            profile,
+            format_length(route.length),
+            format_minutes(route.seconds)
+        ),
+        (Some(Err(e)), _) => format!("{e}  (click to start again)"),
+        (None, None) => format!("routing ({profile}): click a start point"),
+        (None, Some(_)) => format!("routing ({profile}): click an end point"),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::road_graph::{GraphNode, Oneway};
+
+    /// A 100 m square block, corners numbered anticlockwise from the origin,
+    /// with the bottom side one-way from 0 to 1.
+    fn block() -> RoadGraph {
+        let corners = [
+            Vec2::new(0.0, 0.0),
+            Vec2::new(100.0, 0.0),
+            Vec2::new(100.0, 100.0),
+            Vec2::new(0.0, 100.0),
+        ];
+        let mut graph = RoadGraph::default();
+        for (i, &position) in corners.iter().enumerate() {
+            graph.nodes.push(GraphNode {
+                position,
+                edges: vec![i, (i + 3) % 4],
+            });
+        }
+        for i in 0..4 {
+            let (from, to) = (i, (i + 1) % 4);
+            graph.edges.push(GraphEdge {
+                from,
+                to,
+                points: vec![corners[from], corners[to]],
+                length: 100.0,
+                kind: "minor_road".to_string(),
+                class: Some("residential".to_string()),
+                oneway: if i == 0 { Oneway::Forward } else { Oneway::No },
+                name: None,
+            });
+        }
+        graph
+    }
+
+    fn hit(graph: &RoadGraph, edge: EdgeId, along: f32) -> EdgeHit {
+        EdgeHit {
+            edge,
+            point: point_along(&graph.edges[edge].points, along),
+            distance: 0.0,
+            along,
+        }
+    }
+
+    fn route(
+        graph: &RoadGraph,
+        start: (EdgeId, f32),
+        end: (EdgeId, f32),
+        profile: Profile,
+    ) -> Route {
+        let (start, end) = (hit(graph, start.0, start.1), hit(graph, end.0, end.1));
+        shortest_path(graph, start, end, profile).expect("a route")
+    }
+
+    fn assert_follows(route: &Route, corners: &[[f32; 2]]) {
+        assert_eq!(route.points.len(), corners.len(), "{:?}", route.points);
+        for (p, &c) in route.points.iter().zip(corners) {
+            assert!(p.abs_diff_eq(Vec2::from(c), 1e-3), "{:?}", route.points);
+        }
+    }
+
+    #[test]
+    fn both_ends_on_one_edge() {
+        let graph = block();
+        let drive = route(&graph, (0, 20.0), (0, 80.0), Profile::Driving);
+        assert_follows(&drive, &[[20.0, 0.0], [80.0, 0.0]]);
+        assert!((drive.seconds - 60.0 / 8.0).abs() < 1e-3);
+        // Against the one-way, driving goes round the block; walking doesn't.
+        let around = route(&graph, (0, 80.0), (0, 20.0), Profile::Driving);
+        assert!((around.length - 340.0).abs() < 1e-2);
+        let walk = route(&graph, (0, 80.0), (0, 20.0), Profile::Walking);
+        assert_follows(&walk, &[[80.0, 0.0], [20.0, 0.0]]);
+    }
+
+    #[test]
+    fn one_way_edges_are_only_driven_forwards() {
+        let graph = block();
+        // From the top side near corner 3 to near corner 1 on the bottom side:
+        // shortest back through 2 and 1, but that drives the bottom backwards.
+        let walk = route(&graph, (2, 80.0), (0, 90.0), Profile::Walking);
+        assert_follows(
+            &walk,
+            &[[20.0, 100.0], [100.0, 100.0], [100.0, 0.0], [90.0, 0.0]],
+        );
+        assert!((walk.length - 190.0).abs() < 1e-2);
+        let drive = route(&graph, (2, 80.0), (0, 90.0), Profile::Driving);
+        assert_follows(
+            &drive,
+            &[[20.0, 100.0], [0.0, 100.0], [0.0, 0.0], [90.0, 0.0]],
+        );
+        assert!((drive.length - 210.0).abs() < 1e-2);
+    }
+}
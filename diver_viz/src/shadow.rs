@@ -0,0 +1,738 @@
+use std::io::Cursor;
+
+use bevy::{
+    asset::RenderAssetUsages,
+    light::{NotShadowCaster, NotShadowReceiver},
+    mesh::{Indices, PrimitiveTopology},
+    prelude::*,
+    window::{CursorGrabMode, CursorOptions, PrimaryWindow},
+};
+use serde_json::json;
+use tiff::encoder::{TiffEncoder, colortype::Gray32Float};
+use tiff::tags::Tag;
+
+use crate::choropleth::Ramp;
+use crate::filter;
+use crate::height::{BuildingHeight, point_in_ring};
+use crate::isochrone::{self, IsochroneRequest};
+use crate::route::{self, RouteRequest};
+use crate::style::StyledShape;
+use crate::sun::{self, SunClock, civil_from_days, sun_position};
+use crate::terrain::{Heightmap, ground_point};
+use crate::{WorldCenter, world_to_lon_lat};
+
+/// Size of a grid cell in metres.
+const CELL: f32 = 2.0;
+/// Side of the square studied, centred on the click.
+const STUDY_SIZE: f32 = 300.0;
+const STUDY_SIDE: usize = (STUDY_SIZE / CELL) as usize;
+/// Buildings up to this far outside the square still cast shadows into it.
+const MARGIN: f32 = 300.0;
+const MARGIN_CELLS: usize = (MARGIN / CELL) as usize;
+/// Sun positions over a day are sampled this often.
+const SAMPLE_MINUTES: f64 = 15.0;
+/// How far above a surface its ray to the sun starts, so gentle slopes don't
+/// shade themselves.
+const CLEARANCE: f32 = 0.25;
+/// Lift of the overlay over the ground and roofs.
+const OVERLAY_LIFT: f32 = 0.5;
+/// Cells with less sun than this over the day count as shaded in the summary.
+const SHADED_HOURS: f32 = 2.0;
+
+// ---------------------------------------------------------------------------
+// Surface grid
+// ---------------------------------------------------------------------------
+
+/// Heights of the ground, or of the roof where there's a building, on a square
+/// grid covering the study area and the margin around it.
+pub struct SurfaceGrid {
+    /// World position of the north-west corner.
+    origin: Vec2,
+    /// Cells along each side.
+    side: usize,
+    heights: Vec<f32>,
+    roof: Vec<bool>,
+    highest: f32,
+    /// Middle of the study area.
+    centre: Vec2,
+}
+
+impl SurfaceGrid {
+    /// Terrain where there is some, with each building's footprint raised to
+    /// its top. Roofs are taken as flat.
+    pub fn build<'a>(
+        centre: Vec2,
+        heightmap: Option<&Heightmap>,
+        footprints: impl IntoIterator<Item = (&'a [Vec2], f32)>,
+    ) -> Self {
+        let side = STUDY_SIDE + 2 * MARGIN_CELLS;
+        let origin = centre - Vec2::splat(STUDY_SIZE / 2.0 + MARGIN);
+        let mut grid = Self {
+            origin,
+            side,
+            heights: vec![0.0; side * side],
+            roof: vec![false; side * side],
+            highest: f32::MIN,
+            centre,
+        };
+        if let Some(heightmap) = heightmap {
+            for cell in 0..side * side {
+                grid.heights[cell] = heightmap.sample(grid.cell_centre(cell));
+            }
+        }
+        for (ring, top) in footprints {
+            let (min, max) = ring.iter().fold(
+                (Vec2::splat(f32::MAX), Vec2::splat(f32::MIN)),
+                |(min, max), p| (min.min(*p), max.max(*p)),
+            );
+            let first = ((min - origin) / CELL).floor().max(Vec2::ZERO);
+            let last = ((max - origin) / CELL).ceil().min(Vec2::splat(side as f32));
+            for y in first.y as usize..last.y as usize {
+                for x in first.x as usize..last.x as usize {
+                    let cell = y * side + x;
+                    if point_in_ring(grid.cell_centre(cell), ring) && top > grid.heights[cell] {
+                        grid.heights[cell] = top;
+                        grid.roof[cell] = true;
+                    }
+                }
+            }
+        }
+        grid.highest = grid.heights.iter().copied().fold(f32::MIN, f32::max);
+        grid
+    }
+
+    fn cell_centre(&self, cell: usize) -> Vec2 {
+        let (x, y) = (cell % self.side, cell / self.side);
+        self.origin + (Vec2::new(x as f32, y as f32) + 0.5) * CELL
+    }
+
+    fn cell_at(&self, p: Vec2) -> Option<usize> {
+        let g = (p - self.origin) / CELL;
+        if g.x < 0.0 || g.y < 0.0 {
+            return None;
+        }
+        let (x, y) = (g.x as usize, g.y as usize);
+        (x < self.side && y < self.side).then_some(y * self.side + x)
+    }
+
+    /// Grid cells of the study area, row by row from the north-west corner.
+    fn study_cells(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..STUDY_SIDE).flat_map(move |y| {
+            (0..STUDY_SIDE).map(move |x| (y + MARGIN_CELLS) * self.side + x + MARGIN_CELLS)
+        })
+    }
+
+    /// World position of the study area's north-west corner.
+    fn study_origin(&self) -> Vec2 {
+        self.centre - Vec2::splat(STUDY_SIZE / 2.0)
+    }
+
+    /// Whether anything stands between the surface of `cell` and a sun in the
+    /// direction `towards`, walking the grid a cell at a time until the ray
+    /// clears the highest surface or leaves the grid.
+    fn shaded(&self, cell: usize, towards: Vec3) -> bool {
+        if towards.y <= 0.0 {
+            return true;
+        }
+        let flat = Vec2::new(towards.x, towards.z);
+        let run = flat.length();
+        if run < 1e-4 {
+            return false;
+        }
+        let step = flat / run * CELL;
+        let rise = towards.y / run * CELL;
+        let mut p = self.cell_centre(cell);
+        let mut h = self.heights[cell] + CLEARANCE;
+        while h < self.highest {
+            p += step;
+            h += rise;
+            let Some(i) = self.cell_at(p) else {
+                return false;
+            };
+            if self.heights[i] > h {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Study
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StudyMode {
+    /// Hours of direct sun over the clock's day.
+    #[default]
+    Day,
+    /// Sun and shade at the clock's moment.
+    Now,
+}
+
+/// The shadow study being asked for: while `active`, a click places it.
+#[derive(Resource, Debug, Default)]
+pub struct ShadowStudy {
+    pub active: bool,
+    pub mode: StudyMode,
+    pub centre: Option<Vec2>,
+}
+
+/// The study's results so far; sun samples are worked through a frame at a time.
+#[derive(Resource, Default)]
+pub struct SunHours {
+    pub grid: Option<SurfaceGrid>,
+    pub mode: StudyMode,
+    /// Start of the day studied, or the moment, in seconds since the Unix epoch.
+    pub moment: f64,
+    /// Hours of sun on each study cell; for a moment, 1 where it's sunlit.
+    pub hours: Vec<f32>,
+    /// Sun times still to do.
+    pub pending: Vec<f64>,
+    /// Sun times with the sun up.
+    pub samples: usize,
+    /// Hours each sample stands for.
+    pub sample_hours: f32,
+    /// Bumped whenever the grid is rebuilt, so the overlay is too.
+    pub generation: u32,
+    /// Result of the last export.
+    pub saved: Option<String>,
+}
+
+impl SunHours {
+    fn done(&self) -> f32 {
+        1.0 - self.pending.len() as f32 / self.samples.max(1) as f32
+    }
+
+    /// Hours the sun is up over the day studied.
+    fn daylight(&self) -> f32 {
+        self.samples as f32 * self.sample_hours
+    }
+
+    fn color(&self, hours: f32) -> Color {
+        match self.mode {
+            StudyMode::Day => Ramp::Heat
+                .sample(hours / self.daylight().max(f32::EPSILON))
+                .with_alpha(0.75),
+            StudyMode::Now if hours > 0.0 => Color::srgba(1.0, 0.85, 0.3, 0.25),
+            StudyMode::Now => Color::srgba(0.1, 0.1, 0.35, 0.6),
+        }
+    }
+}
+
+/// Leave shadow study mode when routing or a walkshed starts, so a click only
+/// does one thing.
+pub fn stop_placing(study: &mut ShadowStudy) {
+    if study.active {
+        *study = ShadowStudy {
+            active: false,
+            centre: None,
+            ..*study
+        };
+    }
+}
+
+/// Run condition: clicks place the shadow study instead of inspecting features.
+pub fn placing_study(study: Res<ShadowStudy>) -> bool {
+    study.active
+}
+
+fn working(hours: Res<SunHours>) -> bool {
+    !hours.pending.is_empty()
+}
+
+/// Run condition: a building was added or settled on the terrain.
+fn buildings_moved(moved: Query<(), (With<BuildingHeight>, Changed<Transform>)>) -> bool {
+    !moved.is_empty()
+}
+
+/// The heat-coloured cells.
+#[derive(Component)]
+struct StudyOverlay;
+
+#[derive(Component)]
+struct StudyText;
+
+pub struct ShadowPlugin;
+
+impl Plugin for ShadowPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ShadowStudy>()
+            .init_resource::<SunHours>()
+            .add_systems(Startup, spawn_study_text)
+            .add_systems(
+                Update,
+                (
+                    shadow_controls.run_if(not(filter::editing_filter)),
+                    pick_study_centre.run_if(placing_study),
+                    start_study.run_if(
+                        resource_changed::<ShadowStudy>
+                            .or(resource_changed::<SunClock>)
+                            .or(buildings_moved),
+                    ),
+                    run_study.run_if(working),
+                    draw_study.run_if(resource_changed::<SunHours>),
+                    export_study.run_if(not(filter::editing_filter)),
+                    show_study_text
+                        .run_if(resource_changed::<ShadowStudy>.or(resource_changed::<SunHours>)),
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// O starts placing a shadow study, or stops and clears it; L switches between
+/// sun hours over the day and shade at the moment.
+fn shadow_controls(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut study: ResMut<ShadowStudy>,
+    mut route: ResMut<RouteRequest>,
+    mut isochrone: ResMut<IsochroneRequest>,
+) {
+    if keys.just_pressed(KeyCode::KeyO) {
+        *study = ShadowStudy {
+            active: !study.active,
+            centre: None,
+            ..*study
+        };
+        if study.active {
+            route::stop_routing(&mut route);
+            isochrone::stop_placing(&mut isochrone);
+        }
+    }
+    if keys.just_pressed(KeyCode::KeyL) && study.active {
+        study.mode = match study.mode {
+            StudyMode::Day => StudyMode::Now,
+            StudyMode::Now => StudyMode::Day,
+        };
+    }
+}
+
+fn pick_study_centre(
+    mouse: Res<ButtonInput<MouseButton>>,
+    window: Single<(&Window, &CursorOptions), With<PrimaryWindow>>,
+    camera: Single<(&Camera, &GlobalTransform), With<Camera3d>>,
+    heightmap: Option<Res<Heightmap>>,
+    mut study: ResMut<ShadowStudy>,
+) {
+    let (window, cursor_options) = *window;
+    if !mouse.just_pressed(MouseButton::Left) || cursor_options.grab_mode != CursorGrabMode::None {
+        return;
+    }
+    let (camera, camera_tf) = *camera;
+    let centre = window
+        .cursor_position()
+        .and_then(|cursor| camera.viewport_to_world(camera_tf, cursor).ok())
+        .and_then(|ray| ground_point(ray, heightmap.as_deref()));
+    if centre.is_some() {
+        study.centre = centre;
+    }
+}
+
+/// Rebuild the grid when the study moves or the buildings do, and start the sun
+/// samples over when the day or moment changes.
+fn start_study(
+    study: Res<ShadowStudy>,
+    clock: Res<SunClock>,
+    heightmap: Option<Res<Heightmap>>,
+    buildings: Query<(&StyledShape, &BuildingHeight, &Transform)>,
+    moved: Query<(), (With<BuildingHeight>, Changed<Transform>)>,
+    mut hours: ResMut<SunHours>,
+) {
+    let Some(centre) = study.centre else {
+        if hours.grid.is_some() {
+            *hours = SunHours {
+                generation: hours.generation + 1,
+                ..default()
+            };
+        }
+        return;
+    };
+    let (_, seconds) = clock.local();
+    let moment = match study.mode {
+        StudyMode::Day => clock.unix - seconds,
+        StudyMode::Now => clock.unix,
+    };
+    let rebuild = hours.grid.as_ref().is_none_or(|g| g.centre != centre) || !moved.is_empty();
+    if !rebuild && hours.mode == study.mode && hours.moment == moment {
+        return;
+    }
+
+    let grid = match hours.grid.take() {
+        Some(grid) if !rebuild => grid,
+        _ => {
+            hours.generation += 1;
+            let footprints = buildings.iter().filter_map(|(shape, height, transform)| {
+                let StyledShape::Extrusion {
+                    footprint,
+                    draped: false,
+                } = shape
+                else {
+                    return None;
+                };
+                let top = transform.translation.y + height.height - height.min_height;
+                Some((footprint.geometry.first()?.as_slice(), top))
+            });
+            SurfaceGrid::build(centre, heightmap.as_deref(), footprints)
+        }
+    };
+
+    let (lat, lon) = sun::location();
+    let (times, sample_hours) = match study.mode {
+        StudyMode::Day => {
+            let count = (24.0 * 60.0 / SAMPLE_MINUTES) as usize;
+            let times = (0..count)
+                .map(|k| moment + (k as f64 + 0.5) * SAMPLE_MINUTES * 60.0)
+                .collect::<Vec<_>>();
+            (times, (SAMPLE_MINUTES / 60.0) as f32)
+        }
+        StudyMode::Now => (vec![moment], 1.0),
+    };
+    let mut pending: Vec<f64> = times
+        .into_iter()
+        .filter(|&t| sun_position(t, lat, lon).is_up())
+        .collect();
+    // Worked through from the back.
+    pending.reverse();
+    *hours = SunHours {
+        grid: Some(grid),
+        mode: study.mode,
+        moment,
+        hours: vec![0.0; STUDY_SIDE * STUDY_SIDE],
+        samples: pending.len(),
+        pending,
+        sample_hours,
+        generation: hours.generation,
+        saved: None,
+    };
+}
+
+/// One sun position per frame, adding its time to every cell it reaches.
+fn run_study(mut hours: ResMut<SunHours>) {
+    let hours = &mut *hours;
+    let (Some(grid), Some(time)) = (&hours.grid, hours.pending.pop()) else {
+        return;
+    };
+    let (lat, lon) = sun::location();
+    let towards = sun_position(time, lat, lon).direction();
+    for (i, cell) in grid.study_cells().enumerate() {
+        if !grid.shaded(cell, towards) {
+            hours.hours[i] += hours.sample_hours;
+        }
+    }
+}
+
+/// Builds the overlay when the grid changes and recolours it as samples come in.
+fn draw_study(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    overlay: Query<(Entity, &Mesh3d), With<StudyOverlay>>,
+    hours: Res<SunHours>,
+    mut drawn: Local<u32>,
+) {
+    let colors: Vec<[f32; 4]> = hours
+        .hours
+        .iter()
+        .flat_map(|h| [hours.color(*h).to_linear().to_f32_array(); 4])
+        .collect();
+    if *drawn == hours.generation {
+        for (_, mesh) in &overlay {
+            if let Some(mesh) = meshes.get_mut(&mesh.0) {
+                mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors.clone());
+            }
+        }
+        return;
+    }
+    *drawn = hours.generation;
+    for (entity, _) in &overlay {
+        commands.entity(entity).despawn();
+    }
+    let Some(grid) = &hours.grid else {
+        return;
+    };
+
+    let mut positions = Vec::with_capacity(colors.len());
+    let mut indices = Vec::with_capacity(colors.len() / 4 * 6);
+    for cell in grid.study_cells() {
+        let c = grid.cell_centre(cell);
+        let y = grid.heights[cell] + OVERLAY_LIFT;
+        let base = positions.len() as u32;
+        for (dx, dz) in [(-1.0, -1.0), (-1.0, 1.0), (1.0, 1.0), (1.0, -1.0)] {
+            positions.push([c.x + dx * CELL / 2.0, y, c.y + dz * CELL / 2.0]);
+        }
+        indices.extend([base, base + 1, base + 2, base, base + 2, base + 3]);
+    }
+    let mut mesh = Mesh::new(
+        PrimitiveTopology::TriangleList,
+        RenderAssetUsages::default(),
+    );
+    mesh.insert_attribute(
+        Mesh::ATTRIBUTE_NORMAL,
+        vec![[0.0, 1.0, 0.0]; positions.len()],
+    );
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+    mesh.insert_indices(Indices::U32(indices));
+    commands.spawn((
+        StudyOverlay,
+        Mesh3d(meshes.add(mesh)),
+        MeshMaterial3d(materials.add(StandardMaterial {
+            base_color: Color::WHITE,
+            unlit: true,
+            alpha_mode: AlphaMode::Blend,
+            ..default()
+        })),
+        Transform::default(),
+        NotShadowCaster,
+        NotShadowReceiver,
+    ));
+}
+
+// ---------------------------------------------------------------------------
+// Export
+// ---------------------------------------------------------------------------
+
+/// X saves the finished study as GeoJSON cells and a GeoTIFF, next to where the
+/// app was started (or as downloads on the web).
+fn export_study(
+    keys: Res<ButtonInput<KeyCode>>,
+    study: Res<ShadowStudy>,
+    clock: Res<SunClock>,
+    center: Option<Res<WorldCenter>>,
+    mut hours: ResMut<SunHours>,
+) {
+    if !keys.just_pressed(KeyCode::KeyX) || !study.active {
+        return;
+    }
+    let (Some(grid), Some(center)) = (&hours.grid, center) else {
+        return;
+    };
+    if !hours.pending.is_empty() {
+        hours.saved = Some("still working, export when done".to_string());
+        return;
+    }
+    let stem = export_stem(&clock, hours.mode);
+    let geojson = study_geojson(grid, &hours, center.0);
+    let saved = study_geotiff(grid, &hours, center.0)
+        .map_err(|e| e.to_string())
+        .and_then(|tiff| {
+            save(&format!("{stem}.geojson"), geojson.as_bytes())?;
+            save(&format!("{stem}.tif"), &tiff)
+        });
+    hours.saved = Some(match saved {
+        Ok(()) => format!("saved {stem}.geojson and .tif"),
+        Err(e) => format!("couldn't export: {e}"),
+    });
+    info!(
+        "shadow study: {}",
+        hours.saved.as_deref().unwrap_or_default()
+    );
+}
+
+/// `shadow-2024-06-21`, or `shadow-2024-06-21-1530` for a moment.
+fn export_stem(clock: &SunClock, mode: StudyMode) -> String {
+    let (day, seconds) = clock.local();
+    let (year, month, date) = civil_from_days(day);
+    let minutes = (seconds / 60.0) as i64;
+    match mode {
+        StudyMode::Day => format!("shadow-{year}-{month:02}-{date:02}"),
+        StudyMode::Now => format!(
+            "shadow-{year}-{month:02}-{date:02}-{:02}{:02}",
+            minutes / 60,
+            minutes % 60
+        ),
+    }
+}
+
+/// The study cells as WGS84 polygons, with runs of equal cells along each row
+/// joined into one rectangle to keep the file small.
+fn study_geojson(grid: &SurfaceGrid, hours: &SunHours, center: Vec2) -> String {
+    let roofs: Vec<bool> = grid.study_cells().map(|cell| grid.roof[cell]).collect();
+    let origin = grid.study_origin();
+    let corner = |x: usize, y: usize| {
+        let (lon, lat) = world_to_lon_lat(origin + Vec2::new(x as f32, y as f32) * CELL, center);
+        [lon, lat]
+    };
+    let mut features = Vec::new();
+    for y in 0..STUDY_SIDE {
+        let mut start = 0;
+        for x in 1..=STUDY_SIDE {
+            let (a, b) = (y * STUDY_SIDE + start, y * STUDY_SIDE + x);
+            if x < STUDY_SIDE && hours.hours[a] == hours.hours[b] && roofs[a] == roofs[b] {
+                continue;
+            }
+            let value = match hours.mode {
+                StudyMode::Day => json!({ "sun_hours": hours.hours[a] }),
+                StudyMode::Now => json!({ "sunlit": hours.hours[a] > 0.0 }),
+            };
+            let mut properties = value.as_object().cloned().unwrap_or_default();
+            properties.insert(
+                "surface".to_string(),
+                json!(if roofs[a] { "roof" } else { "ground" }),
+            );
+            features.push(json!({
+                "type": "Feature",
+                "properties": properties,
+                "geometry": {
+                    "type": "Polygon",
+                    "coordinates": [[
+                        corner(start, y),
+                        corner(start, y + 1),
+                        corner(x, y + 1),
+                        corner(x, y),
+                        corner(start, y),
+                    ]],
+                },
+            }));
+            start = x;
+        }
+    }
+    json!({ "type": "FeatureCollection", "features": features }).to_string()
+}
+
+/// Sun hours (or 1 for sunlit, 0 for shade) as a single band float GeoTIFF in
+/// Web Mercator, which the world grid is a scaled copy of.
+fn study_geotiff(
+    grid: &SurfaceGrid,
+    hours: &SunHours,
+    center: Vec2,
+) -> Result<Vec<u8>, tiff::TiffError> {
+    const EARTH_RADIUS_M: f64 = 6_378_137.0;
+    let mercator = |p: Vec2| {
+        let (lon, lat) = world_to_lon_lat(p, center);
+        let lat = lat.to_radians();
+        (
+            EARTH_RADIUS_M * lon.to_radians(),
+            EARTH_RADIUS_M * (std::f64::consts::FRAC_PI_4 + lat / 2.0).tan().ln(),
+        )
+    };
+    let (west, north) = mercator(grid.study_origin());
+    let (east, south) = mercator(grid.study_origin() + Vec2::splat(STUDY_SIZE));
+    let scale = [
+        (east - west) / STUDY_SIDE as f64,
+        (north - south) / STUDY_SIDE as f64,
+        0.0,
+    ];
+
+    let mut bytes = Cursor::new(Vec::new());
+    let mut encoder = TiffEncoder::new(&mut bytes)?;
+    let mut image = encoder.new_image::<Gray32Float>(STUDY_SIDE as u32, STUDY_SIDE as u32)?;
+    image
+        .encoder()
+        .write_tag(Tag::ModelPixelScaleTag, &scale[..])?;
+    image.encoder().write_tag(
+        Tag::ModelTiepointTag,
+        &[0.0, 0.0, 0.0, west, north, 0.0][..],
+    )?;
+    // Projected, pixel is area, EPSG:3857.
+    image.encoder().write_tag(
+        Tag::GeoKeyDirectoryTag,
+        &[
+            1u16, 1, 0, 3, 1024, 0, 1, 1, 1025, 0, 1, 1, 3072, 0, 1, 3857,
+        ][..],
+    )?;
+    image.write_data(&hours.hours)?;
+    Ok(bytes.into_inner())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn save(name: &str, bytes: &[u8]) -> Result<(), String> {
+    std::fs::write(name, bytes).map_err(|e| e.to_string())
+}
+
+/// Hand the file to the browser as a download.
+#[cfg(target_arch = "wasm32")]
+fn save(name: &str, bytes: &[u8]) -> Result<(), String> {
+    use wasm_bindgen::JsCast;
+
+    let fail = |e: wasm_bindgen::JsValue| format!("{e:?}");
+    let document = web_sys::window()
+        .and_then(|w| w.document())
+        .ok_or("no document")?;
+    let parts = js_sys::Array::of1(&js_sys::Uint8Array::from(bytes));
+    let blob = web_sys::Blob::new_with_u8_array_sequence(&parts).map_err(fail)?;
+    let url = web_sys::Url::create_object_url_with_blob(&blob).map_err(fail)?;
+    let link: web_sys::HtmlAnchorElement = document
+        .create_element("a")
+        .map_err(fail)?
+        .dyn_into()
+        .map_err(|_| "not a link")?;
+    link.set_href(&url);
+    link.set_download(name);
+    link.click();
+    web_sys::Url::revoke_object_url(&url).map_err(fail)
+}
+
+// ---------------------------------------------------------------------------
+// Text
+// ---------------------------------------------------------------------------
+
+fn spawn_study_text(mut commands: Commands) {
+    commands.spawn((
+        StudyText,
+        Text::default(),
+        TextFont {
+            font_size: 14.0,
+            ..default()
+        },
+        Node {
+            position_type: PositionType::Absolute,
+            left: px(12),
+            bottom: px(172),
+            padding: UiRect::all(px(6)),
+            ..default()
+        },
+        BackgroundColor(Color::srgba(0.12, 0.08, 0.16, 0.85)),
+        Visibility::Hidden,
+    ));
+}
+
+fn show_study_text(
+    study: Res<ShadowStudy>,
+    hours: Res<SunHours>,
+    clock: Res<SunClock>,
+    text: Single<(&mut Text, &mut Visibility), With<StudyText>>,
+) {
+    let (mut text, mut visibility) = text.into_inner();
+    if !study.active {
+        *visibility = Visibility::Hidden;
+        return;
+    }
+    *visibility = Visibility::Inherited;
+    let size = STUDY_SIZE;
+    let cells = hours.hours.len().max(1) as f32;
+    let summary = match (study.centre, hours.mode) {
+        (None, _) => format!("shadow study: click the middle of a {size:.0} m square"),
+        _ if !hours.pending.is_empty() => {
+            format!("working out the shadows: {:.0}%", hours.done() * 100.0)
+        }
+        (Some(_), StudyMode::Day) => {
+            let mean = hours.hours.iter().sum::<f32>() / cells;
+            let shaded = hours.hours.iter().filter(|h| **h < SHADED_HOURS).count() as f32;
+            format!(
+                "sun hours on {}: {:.1} h on average of {:.1} h of daylight, {:.0}% gets under {:.0} h",
+                clock.label().split(' ').next().unwrap_or_default(),
+                mean,
+                hours.daylight(),
+                shaded / cells * 100.0,
+                SHADED_HOURS,
+            )
+        }
+        (Some(_), StudyMode::Now) if hours.samples == 0 => {
+            format!("shade at {}: the sun is down", clock.label())
+        }
+        (Some(_), StudyMode::Now) => {
+            let shaded = hours.hours.iter().filter(|h| **h == 0.0).count() as f32;
+            format!(
+                "shade at {}: {:.0}% of the ground and roofs",
+                clock.label(),
+                shaded / cells * 100.0
+            )
+        }
+    };
+    text.0 = match &hours.saved {
+        Some(saved) => format!("{summary}  ({saved})"),
+        None => format!("{summary}  (L for day or moment, X to export)"),
+    };
+}
@@ -0,0 +1,355 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek},
+    path::Path,
+};
+
+use bevy::prelude::*;
+use flatgeobuf::{FallibleStreamingIterator, FeatureAccess, FgbReader, Geometry};
+use geozero::{ColumnValue, FeatureProcessor, GeozeroGeometry, wkb::Wkb};
+use parquet::{
+    file::{
+        reader::{FileReader, SerializedFileReader},
+        statistics::Statistics,
+    },
+    record::{Field, Row},
+};
+
+use crate::overlay::{OverlayCollector, OverlayFeature, OverlayGeometry, read_geojson};
+
+/// A lon/lat box: west, south, east, north.
+pub type Bounds = [f64; 4];
+
+fn overlaps(a: Bounds, b: Bounds) -> bool {
+    a[0] <= b[2] && b[0] <= a[2] && a[1] <= b[3] && b[1] <= a[3]
+}
+
+/// Read the features of a GeoJSON, FlatGeobuf or GeoParquet file that fall in
+/// `bounds`, projected around `center`. FlatGeobuf's spatial index and GeoParquet
+/// bbox statistics let big files be read only where they overlap.
+pub fn read_file(path: &Path, center: Vec2, bounds: Bounds) -> Result<Vec<OverlayFeature>, String> {
+    let name = path.file_stem().map_or_else(
+        || "overlay".to_string(),
+        |s| s.to_string_lossy().to_string(),
+    );
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let mut collector = OverlayCollector::new(&name, center);
+    match extension.as_str() {
+        "fgb" => {
+            let mut file = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+            read_flatgeobuf(&mut file, bounds, &mut collector)?;
+        }
+        "parquet" | "geoparquet" => {
+            let file = File::open(path).map_err(|e| e.to_string())?;
+            read_geoparquet(file, bounds, &mut collector)?;
+        }
+        _ => {
+            let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+            return read_geojson(&name, &text, center);
+        }
+    }
+    // Indexes and statistics work on boxes; drop what's near but not in the area.
+    let sw = crate::lon_lat_to_world(bounds[0], bounds[1], center);
+    let ne = crate::lon_lat_to_world(bounds[2], bounds[3], center);
+    let area = Rect::from_corners(sw, ne);
+    let mut features = collector.features;
+    features.retain(|feature| {
+        feature.geometry.iter().any(|geometry| {
+            let points = match geometry {
+                OverlayGeometry::Polygon(rings) => rings.first().map_or(&[][..], |r| r),
+                OverlayGeometry::Line(points) => points,
+                OverlayGeometry::Point(point) => std::slice::from_ref(point),
+            };
+            let bounds = points
+                .iter()
+                .fold(Rect::EMPTY, |rect, p| rect.union_point(*p));
+            !bounds.intersect(area).is_empty() || points.iter().any(|p| area.contains(*p))
+        })
+    });
+    Ok(features)
+}
+
+// ---------------------------------------------------------------------------
+// FlatGeobuf
+// ---------------------------------------------------------------------------
+
+/// Read the features of a FlatGeobuf file that overlap `bounds`. With a spatial
+/// index only the matching features are read; without one every feature is
+/// read and checked against its own box.
+pub fn read_flatgeobuf<R: Read + Seek>(
+    reader: &mut R,
+    bounds: Bounds,
+    processor: &mut OverlayCollector,
+) -> Result<usize, String> {
+    let fgb = FgbReader::open(reader).map_err(|e| e.to_string())?;
+    let header = fgb.header();
+    if let Some(code) = header.crs().map(|crs| crs.code())
+        && code != 0
+        && code != 4326
+    {
+        return Err(format!(
+            "EPSG:{code} isn't supported, only WGS84 (EPSG:4326)"
+        ));
+    }
+    let [west, south, east, north] = bounds;
+    let mut features = if header.index_node_size() > 0 && header.features_count() > 0 {
+        fgb.select_bbox(west, south, east, north)
+    } else {
+        fgb.select_all()
+    }
+    .map_err(|e| e.to_string())?;
+
+    let mut read = 0;
+    while let Some(feature) = features.next().map_err(|e| e.to_string())? {
+        let Some(geometry) = feature.geometry() else {
+            continue;
+        };
+        if !overlaps(geometry_bounds(&geometry)?, bounds) {
+            continue;
+        }
+        feature
+            .process(processor, read as u64)
+            .map_err(|e| e.to_string())?;
+        read += 1;
+    }
+    Ok(read)
+}
+
+/// The box around a geometry's coordinates. Ring ends that don't fit the
+/// coordinates are an error here, as the reader would index past them.
+fn geometry_bounds(geometry: &Geometry) -> Result<Bounds, String> {
+    let mut bounds = [
+        f64::INFINITY,
+        f64::INFINITY,
+        f64::NEG_INFINITY,
+        f64::NEG_INFINITY,
+    ];
+    let mut extend = |[west, south, east, north]: Bounds| {
+        bounds = [
+            bounds[0].min(west),
+            bounds[1].min(south),
+            bounds[2].max(east),
+            bounds[3].max(north),
+        ];
+    };
+    for part in geometry.parts().into_iter().flatten() {
+        extend(geometry_bounds(&part)?);
+    }
+    if let Some(xy) = geometry.xy() {
+        let ends: Vec<u32> = geometry
+            .ends()
+            .map(|e| e.iter().collect())
+            .unwrap_or_default();
+        if xy.len() % 2 != 0
+            || ends.windows(2).any(|w| w[0] > w[1])
+            || ends.last().is_some_and(|&end| end as usize > xy.len() / 2)
+        {
+            return Err("ring ends run past the coordinates".to_string());
+        }
+        for i in (0..xy.len()).step_by(2) {
+            let (x, y) = (xy.get(i), xy.get(i + 1));
+            extend([x, y, x, y]);
+        }
+    }
+    Ok(bounds)
+}
+
+// ---------------------------------------------------------------------------
+// GeoParquet
+// ---------------------------------------------------------------------------
+
+/// The column holding WKB geometry, from the file's `geo` metadata.
+fn geometry_column(reader: &SerializedFileReader<File>) -> String {
+    let geo = reader
+        .metadata()
+        .file_metadata()
+        .key_value_metadata()
+        .and_then(|kv| kv.iter().find(|kv| kv.key == "geo"))
+        .and_then(|kv| kv.value.as_deref())
+        .and_then(|json| serde_json::from_str::<serde_json::Value>(json).ok());
+    geo.as_ref()
+        .and_then(|geo| geo.get("primary_column")?.as_str())
+        .unwrap_or("geometry")
+        .to_string()
+}
+
+/// Read the features of a GeoParquet file that overlap `bounds`. Row groups whose
+/// `bbox` column statistics (as in Overture Maps files) miss the area are skipped
+/// without being read, and rows are checked against their `bbox` before their
+/// geometry is decoded.
+pub fn read_geoparquet(
+    file: File,
+    bounds: Bounds,
+    processor: &mut OverlayCollector,
+) -> Result<usize, String> {
+    let reader = SerializedFileReader::new(file).map_err(|e| e.to_string())?;
+    let geometry = geometry_column(&reader);
+    let mut read = 0;
+    let mut idx = 0u64;
+    for group in 0..reader.num_row_groups() {
+        if !row_group_overlaps(&reader, group, bounds) {
+            continue;
+        }
+        let rows = reader.get_row_group(group).map_err(|e| e.to_string())?;
+        for row in rows.get_row_iter(None).map_err(|e| e.to_string())? {
+            let row = row.map_err(|e| e.to_string())?;
+            idx += 1;
+            if row_bbox(&row).is_some_and(|b| !overlaps(b, bounds)) {
+                continue;
+            }
+            let Some(wkb) = row.get_column_iter().find_map(|(name, field)| match field {
+                Field::Bytes(bytes) if *name == geometry => Some(bytes.data()),
+                _ => None,
+            }) else {
+                continue;
+            };
+            processor.feature_begin(idx).map_err(|e| e.to_string())?;
+            let mut column = 0;
+            for (name, field) in row.get_column_iter() {
+                if *name != geometry {
+                    parquet_properties(name, field, &mut column, processor)?;
+                }
+            }
+            Wkb(wkb)
+                .process_geom(processor)
+                .map_err(|e| e.to_string())?;
+            processor.feature_end(idx).map_err(|e| e.to_string())?;
+            read += 1;
+        }
+    }
+    Ok(read)
+}
+
+fn row_group_overlaps(reader: &SerializedFileReader<File>, group: usize, bounds: Bounds) -> bool {
+    let metadata = reader.metadata().row_group(group);
+    let stat = |path: &str, max: bool| {
+        metadata
+            .columns()
+            .iter()
+            .find(|c| c.column_path().string() == path)
+            .and_then(|c| match c.statistics()? {
+                Statistics::Double(s) => if max { s.max_opt() } else { s.min_opt() }.copied(),
+                Statistics::Float(s) => {
+                    if max { s.max_opt() } else { s.min_opt() }.map(|v| *v as f64)
+                }
+                _ => None,
+            })
+    };
+    match (
+        stat("bbox.xmin", false),
+        stat("bbox.ymin", false),
+        stat("bbox.xmax", true),
+        stat("bbox.ymax", true),
+    ) {
+        (Some(west), Some(south), Some(east), Some(north)) => {
+            overlaps([west, south, east, north], bounds)
+        }
+        // No statistics to go on.
+        _ => true,
+    }
+}
+
+/// The row's `bbox` struct column, when it has one.
+fn row_bbox(row: &Row) -> Option<Bounds> {
+    let (_, Field::Group(bbox)) = row.get_column_iter().find(|(name, _)| *name == "bbox")? else {
+        return None;
+    };
+    let get = |key: &str| {
+        bbox.get_column_iter()
+            .find(|(name, _)| *name == key)
+            .and_then(|(_, field)| match field {
+                Field::Double(v) => Some(*v),
+                Field::Float(v) => Some(*v as f64),
+                _ => None,
+            })
+    };
+    Some([get("xmin")?, get("ymin")?, get("xmax")?, get("ymax")?])
+}
+
+/// Pass a column on as properties. Structs are flattened into dotted names, so
+/// Overture's `names.primary` reads as one; lists and maps are kept as text.
+fn parquet_properties(
+    name: &str,
+    field: &Field,
+    column: &mut usize,
+    processor: &mut OverlayCollector,
+) -> Result<(), String> {
+    use geozero::PropertyProcessor;
+
+    let text;
+    let value = match field {
+        Field::Null | Field::Bytes(_) => return Ok(()),
+        Field::Group(group) => {
+            for (inner, field) in group.get_column_iter() {
+                parquet_properties(&format!("{name}.{inner}"), field, column, processor)?;
+            }
+            return Ok(());
+        }
+        Field::Bool(v) => ColumnValue::Bool(*v),
+        Field::Byte(v) => ColumnValue::Byte(*v),
+        Field::Short(v) => ColumnValue::Short(*v),
+        Field::Int(v) => ColumnValue::Int(*v),
+        Field::Long(v) => ColumnValue::Long(*v),
+        Field::UByte(v) => ColumnValue::UByte(*v),
+        Field::UShort(v) => ColumnValue::UShort(*v),
+        Field::UInt(v) => ColumnValue::UInt(*v),
+        Field::ULong(v) => ColumnValue::ULong(*v),
+        Field::Float(v) => ColumnValue::Float(*v),
+        Field::Double(v) => ColumnValue::Double(*v),
+        Field::Str(v) => ColumnValue::String(v),
+        other => {
+            text = other.to_string();
+            ColumnValue::String(&text)
+        }
+    };
+    processor
+        .property(*column, name, &value)
+        .map_err(|e| e.to_string())?;
+    *column += 1;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn read(file: &[u8], bounds: Bounds) -> Result<Vec<String>, String> {
+        let mut collector = OverlayCollector::new("test", Vec2::ZERO);
+        read_flatgeobuf(&mut Cursor::new(file), bounds, &mut collector)?;
+        Ok(collector
+            .features
+            .iter()
+            .filter_map(|f| f.properties.get("name").map(|n| n.to_string()))
+            .collect())
+    }
+
+    /// Written with flatgeobuf's `FgbWriter`: Union Station, Civic Center and
+    /// Boulder as points and a downtown block as a polygon, with a spatial index.
+    #[test]
+    fn flatgeobuf_index_finds_features_in_bounds() {
+        let file = include_bytes!("../tests/data/denver.fgb");
+        // Downtown only: not Union Station just west of it, nor Boulder.
+        let downtown = read(file, [-105.0, 39.7, -104.9, 39.8]).unwrap();
+        assert_eq!(downtown, ["civic center", "block"]);
+        // In the index's Hilbert order, not the order they were written in.
+        let everywhere = read(file, [-180.0, -90.0, 180.0, 90.0]).unwrap();
+        assert_eq!(
+            everywhere,
+            ["civic center", "block", "union station", "boulder"]
+        );
+        assert!(read(file, [0.0, 0.0, 1.0, 1.0]).unwrap().is_empty());
+    }
+
+    /// Written with flatgeobuf's `FgbWriter`, without an index, from a square with
+    /// a courtyard; the courtyard's ring end was then patched from 10 to 40.
+    #[test]
+    fn flatgeobuf_with_bad_ring_ends_is_an_error() {
+        let file = include_bytes!("../tests/data/bad_ends.fgb");
+        assert!(read(file, [-180.0, -90.0, 180.0, 90.0]).is_err());
+    }
+}
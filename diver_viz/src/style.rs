@@ -0,0 +1,455 @@
+use std::collections::HashMap;
+
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadState, io::Reader},
+    ecs::query::QueryItem,
+    prelude::*,
+};
+use ron::extensions::Extensions;
+use serde::Deserialize;
+
+use crate::height::{BuildingHeight, BuildingHeights};
+use crate::properties::FeatureProperties;
+use crate::road_layers::raise_deck;
+use crate::road_mesh::{RibbonStyle, road_ribbon_mesh};
+use crate::terrain::OnTerrain;
+use crate::{Footprint, create_building_mesh, empty_mesh};
+
+/// Flags tiles spell more than one way, as Protomaps' `is_tunnel` and OSM's
+/// `tunnel`. A rule on either spelling also matches `"true"` or `"false"` by
+/// whether any spelling is set, whatever value it's set to (`yes`, `1`, ...).
+const FLAG_SPELLINGS: [[&str; 2]; 2] = [["is_bridge", "bridge"], ["is_tunnel", "tunnel"]];
+
+/// Loaded from the asset folder at startup.
+const STYLE_PATH: &str = "style.ron";
+/// The shipped style, used when `assets/style.ron` can't be loaded.
+const BUILT_IN_STYLE: &str = include_str!("../assets/style.ron");
+
+/// How features look, as a list of rules. Every rule whose `layer` and `filter`
+/// match a feature applies, in order, and later rules override the paint
+/// properties they set, so general rules go first and specific ones after.
+#[derive(Asset, TypePath, Debug, Clone, Deserialize)]
+pub struct Style {
+    pub rules: Vec<StyleRule>,
+    /// Where building heights come from, read as tiles load.
+    #[serde(default)]
+    pub heights: BuildingHeights,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct StyleRule {
+    /// Tile layer name: `buildings`, `landuse`, `roads`, `water`, ...
+    pub layer: String,
+    /// Tag values the feature must have, all of them.
+    #[serde(default)]
+    pub filter: HashMap<String, TagMatch>,
+    pub paint: Paint,
+}
+
+/// A tag value to match, or a list of values any of which match.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum TagMatch {
+    One(String),
+    Any(Vec<String>),
+}
+
+impl TagMatch {
+    fn matches(&self, value: &str) -> bool {
+        match self {
+            Self::One(v) => v == value,
+            Self::Any(values) => values.iter().any(|v| v == value),
+        }
+    }
+}
+
+impl StyleRule {
+    fn matches(&self, feature: &FeatureProperties) -> bool {
+        self.layer == feature.layer
+            && self.filter.iter().all(|(key, m)| {
+                feature
+                    .field(key)
+                    .is_some_and(|v| m.matches(&v.to_string()))
+                    || FLAG_SPELLINGS
+                        .iter()
+                        .find(|spellings| spellings.contains(&key.as_str()))
+                        .and_then(|spellings| {
+                            spellings
+                                .iter()
+                                .filter_map(|k| feature.get(k))
+                                .map(|v| v.is_truthy())
+                                .reduce(|a, b| a || b)
+                        })
+                        .is_some_and(|set| m.matches(if set { "true" } else { "false" }))
+            })
+    }
+}
+
+/// What a rule sets. Anything left out falls through to earlier rules.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+pub struct Paint {
+    pub color: Option<HexColor>,
+    /// Below 1 the material is alpha blended.
+    pub opacity: Option<f32>,
+    pub metallic: Option<f32>,
+    pub roughness: Option<f32>,
+    pub reflectance: Option<f32>,
+    pub unlit: Option<bool>,
+    /// Extrusion height in metres. For buildings this replaces the tagged or
+    /// estimated height.
+    pub height: Option<f32>,
+    /// Width in metres for line features, replacing the width worked out from tags.
+    pub width: Option<f32>,
+}
+
+impl Paint {
+    /// `other`'s properties where it has them, ours otherwise.
+    fn with(self, other: Paint) -> Paint {
+        Paint {
+            color: other.color.or(self.color),
+            opacity: other.opacity.or(self.opacity),
+            metallic: other.metallic.or(self.metallic),
+            roughness: other.roughness.or(self.roughness),
+            reflectance: other.reflectance.or(self.reflectance),
+            unlit: other.unlit.or(self.unlit),
+            height: other.height.or(self.height),
+            width: other.width.or(self.width),
+        }
+    }
+
+    pub fn material(&self) -> StandardMaterial {
+        let opacity = self.opacity.unwrap_or(1.0);
+        let defaults = StandardMaterial::default();
+        StandardMaterial {
+            base_color: self.color.map_or(Color::WHITE, |c| c.0).with_alpha(opacity),
+            alpha_mode: if opacity < 1.0 {
+                AlphaMode::Blend
+            } else {
+                AlphaMode::Opaque
+            },
+            metallic: self.metallic.unwrap_or(defaults.metallic),
+            perceptual_roughness: self.roughness.unwrap_or(defaults.perceptual_roughness),
+            reflectance: self.reflectance.unwrap_or(defaults.reflectance),
+            unlit: self.unlit.unwrap_or(false),
+            ..defaults
+        }
+    }
+
+    /// Everything that goes into [`Paint::material`], for sharing materials.
+    fn material_key(&self) -> [u32; 8] {
+        let color = self.color.map_or(Color::WHITE, |c| c.0).to_srgba();
+        [
+            color.red.to_bits(),
+            color.green.to_bits(),
+            color.blue.to_bits(),
+            self.opacity.unwrap_or(1.0).to_bits(),
+            self.metallic.unwrap_or(f32::NAN).to_bits(),
+            self.roughness.unwrap_or(f32::NAN).to_bits(),
+            self.reflectance.unwrap_or(f32::NAN).to_bits(),
+            self.unlit.unwrap_or(false) as u32,
+        ]
+    }
+}
+
+/// A colour written as a CSS-style hex string: `"#rgb"`, `"#rrggbb"` or `"#rrggbbaa"`.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct HexColor(pub Color);
+
+impl TryFrom<String> for HexColor {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Srgba::hex(&value)
+            .map(|c| HexColor(c.into()))
+            .map_err(|e| format!("bad colour {value:?}: {e}"))
+    }
+}
+
+impl Style {
+    fn parse(bytes: &[u8]) -> Result<Self, ron::error::SpannedError> {
+        ron::Options::default()
+            .with_default_extension(Extensions::IMPLICIT_SOME)
+            .from_bytes(bytes)
+    }
+
+    /// The combined paint of every rule matching a feature.
+    pub fn paint(&self, feature: &FeatureProperties) -> Paint {
+        self.rules
+            .iter()
+            .filter(|rule| rule.matches(feature))
+            .fold(Paint::default(), |paint, rule| paint.with(rule.paint))
+    }
+
+    pub fn styled(&self, feature: &FeatureProperties) -> Styled {
+        Styled {
+            paint: self.paint(feature),
+        }
+    }
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Style::parse(BUILT_IN_STYLE.as_bytes()).expect("built-in style should parse")
+    }
+}
+
+/// One material per distinct paint, so features styled alike share a handle.
+#[derive(Resource, Default)]
+pub struct StyleMaterials {
+    handles: HashMap<[u32; 8], Handle<StandardMaterial>>,
+}
+
+impl StyleMaterials {
+    pub fn get(
+        &mut self,
+        paint: &Paint,
+        materials: &mut Assets<StandardMaterial>,
+    ) -> Handle<StandardMaterial> {
+        self.handles
+            .entry(paint.material_key())
+            .or_insert_with(|| materials.add(paint.material()))
+            .clone()
+    }
+}
+
+/// The paint a feature was last drawn with, so it can be restyled in place
+/// when the style changes.
+#[derive(Component, Debug, Clone, Default)]
+pub struct Styled {
+    pub paint: Paint,
+}
+
+/// What a styled mesh was built from, for rebuilding it when the style changes
+/// a height or width.
+#[derive(Component, Debug, Clone)]
+pub enum StyledShape {
+    /// An extruded footprint: a building or landuse slab.
+    Extrusion {
+        footprint: Footprint,
+        /// Whether the mesh follows the terrain, rather than the entity sitting on it.
+        draped: bool,
+    },
+    /// A road or waterway ribbon. `width` is the one worked out from its tags.
+    Ribbon {
+        points: Vec<Vec2>,
+        width: f32,
+        style: RibbonStyle,
+        /// Bridge deck height at mid-span.
+        deck: Option<f32>,
+    },
+    /// Geometry the style doesn't change.
+    Fixed,
+}
+
+impl StyledShape {
+    /// The mesh as `paint` draws it, flat (before any draping).
+    pub fn mesh(&self, paint: &Paint) -> Option<Mesh> {
+        match self {
+            Self::Extrusion { footprint, .. } => create_building_mesh(&footprint.painted(paint)),
+            Self::Ribbon {
+                points,
+                width,
+                style,
+                deck,
+            } => {
+                let mut mesh = road_ribbon_mesh(points, paint.width.unwrap_or(*width), *style)?;
+                if let Some(deck) = deck {
+                    let length = points.windows(2).map(|w| w[0].distance(w[1])).sum();
+                    raise_deck(&mut mesh, *deck, length);
+                }
+                Some(mesh)
+            }
+            Self::Fixed => None,
+        }
+    }
+
+    fn draped(&self) -> bool {
+        match self {
+            Self::Extrusion { draped, .. } => *draped,
+            Self::Ribbon { .. } => true,
+            Self::Fixed => false,
+        }
+    }
+}
+
+/// The style tiles are drawn with, once it has loaded.
+#[derive(Resource, Debug, Clone, Default)]
+pub struct ActiveStyle(pub Style);
+
+#[derive(Resource)]
+struct StyleHandle(Handle<Style>);
+
+#[derive(Default, TypePath)]
+pub struct StyleLoader;
+
+impl AssetLoader for StyleLoader {
+    type Asset = Style;
+    type Settings = ();
+    type Error = std::io::Error;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Style, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        Style::parse(&bytes).map_err(std::io::Error::other)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["ron"]
+    }
+}
+
+pub struct StylePlugin;
+
+impl Plugin for StylePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<Style>()
+            .init_asset_loader::<StyleLoader>()
+            .init_resource::<StyleMaterials>()
+            .add_systems(Startup, load_style)
+            .add_systems(
+                Update,
+                (
+                    activate_style,
+                    restyle.run_if(
+                        resource_exists::<ActiveStyle>.and(not(resource_added::<ActiveStyle>)),
+                    ),
+                )
+                    .chain(),
+            );
+    }
+}
+
+fn load_style(mut commands: Commands, assets: Res<AssetServer>) {
+    info!("Loading style from {}", STYLE_PATH);
+    commands.insert_resource(StyleHandle(assets.load(STYLE_PATH)));
+}
+
+/// Make the style active once it loads, and again whenever the file changes,
+/// or fall back to the built-in one if it can't be loaded at all.
+fn activate_style(
+    mut commands: Commands,
+    handle: Res<StyleHandle>,
+    styles: Res<Assets<Style>>,
+    assets: Res<AssetServer>,
+    active: Option<ResMut<ActiveStyle>>,
+    mut events: MessageReader<AssetEvent<Style>>,
+) {
+    let loaded = events.read().any(|event| match event {
+        AssetEvent::LoadedWithDependencies { id } | AssetEvent::Modified { id } => {
+            *id == handle.0.id()
+        }
+        _ => false,
+    });
+    match (active, styles.get(&handle.0)) {
+        (Some(mut active), Some(style)) if loaded => {
+            info!("✓ Reloaded style with {} rules", style.rules.len());
+            active.0 = style.clone();
+        }
+        (None, Some(style)) if loaded => {
+            info!("✓ Loaded style with {} rules", style.rules.len());
+            commands.insert_resource(ActiveStyle(style.clone()));
+        }
+        (None, _) => {
+            if let Some(LoadState::Failed(err)) = assets.get_load_state(&handle.0) {
+                warn!(
+                    "Couldn't load {}, using the built-in style: {}",
+                    STYLE_PATH, err
+                );
+                commands.insert_resource(ActiveStyle::default());
+            }
+        }
+        _ => {}
+    }
+}
+
+type StyledMesh<'a> = (
+    Entity,
+    &'a FeatureProperties,
+    &'a mut Styled,
+    &'a StyledShape,
+    &'a Mesh3d,
+    &'a mut MeshMaterial3d<StandardMaterial>,
+    Option<&'a mut BuildingHeight>,
+);
+
+/// Re-apply a changed style to everything already spawned, or the style to
+/// features whose properties changed after they were spawned. Materials are
+/// swapped for the new shared ones; meshes are only rebuilt where a height or
+/// width changed.
+pub fn restyle(
+    mut commands: Commands,
+    style: Res<ActiveStyle>,
+    mut style_materials: ResMut<StyleMaterials>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut styled: Query<StyledMesh>,
+    changed: Query<Entity, Changed<FeatureProperties>>,
+) {
+    let all = style.is_changed();
+    if all {
+        // Start a fresh set so materials no paint uses any more are dropped.
+        *style_materials = StyleMaterials::default();
+    } else if changed.is_empty() {
+        return;
+    }
+    let mut rebuilt = 0;
+    let mut apply =
+        |(entity, feature, mut styled, shape, mesh, mut material, building_height): QueryItem<
+            StyledMesh,
+        >| {
+            let paint = style.0.paint(feature);
+            let handle = style_materials.get(&paint, &mut materials);
+            if material.0 != handle {
+                material.0 = handle;
+            }
+            let reshaped = paint.height != styled.paint.height || paint.width != styled.paint.width;
+            if styled.paint != paint {
+                styled.paint = paint;
+            }
+            if !reshaped || matches!(shape, StyledShape::Fixed) {
+                return;
+            }
+            let mut entity = commands.entity(entity);
+            match shape.mesh(&paint) {
+                Some(new_mesh) => {
+                    if let Some(old) = meshes.get_mut(&mesh.0) {
+                        *old = new_mesh;
+                    }
+                    if shape.draped() {
+                        entity.insert(OnTerrain::Drape);
+                    }
+                    entity.insert(Visibility::Inherited);
+                }
+                // Styled down to nothing, e.g. a height of 0. The mesh is emptied
+                // too, since the filter shows again whatever it doesn't hide.
+                None => {
+                    if let Some(old) = meshes.get_mut(&mesh.0) {
+                        *old = empty_mesh();
+                    }
+                    entity.insert(Visibility::Hidden);
+                }
+            }
+            if let (StyledShape::Extrusion { footprint, .. }, Some(mut height)) =
+                (shape, building_height)
+                && let Some(new_height) = BuildingHeight::of(&footprint.painted(&paint))
+            {
+                *height = new_height;
+            }
+            rebuilt += 1;
+        };
+    if all {
+        styled.iter_mut().for_each(&mut apply);
+        info!("✓ Restyled, rebuilt {} meshes", rebuilt);
+    } else {
+        let mut features = styled.iter_many_mut(&changed);
+        while let Some(item) = features.fetch_next() {
+            apply(item);
+        }
+    }
+}
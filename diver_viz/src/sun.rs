@@ -0,0 +1,431 @@
+use bevy::prelude::*;
+
+use crate::{arg_value, filter};
+
+/// Illuminance with the sun straight overhead, before the atmosphere is taken
+/// into account.
+const FULL_SUN: f32 = 50_000.0;
+
+/// How fast the brackets move the clock, in hours per second held.
+const HOURS_PER_SECOND: f64 = 2.0;
+
+/// Sky light on the ground by day and once the sun is well below the horizon.
+const DAY_AMBIENT: f32 = 80.0;
+const NIGHT_AMBIENT: f32 = 8.0;
+
+// ---------------------------------------------------------------------------
+// Solar position
+// ---------------------------------------------------------------------------
+
+/// Where the sun is in the sky, in degrees: azimuth clockwise from north,
+/// elevation above the horizon (negative at night).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SunPosition {
+    pub azimuth: f64,
+    pub elevation: f64,
+}
+
+impl SunPosition {
+    /// Unit vector from the ground towards the sun, in world space (x east,
+    /// y up, z south).
+    pub fn direction(&self) -> Vec3 {
+        let (azimuth, elevation) = (self.azimuth.to_radians(), self.elevation.to_radians());
+        Vec3::new(
+            (azimuth.sin() * elevation.cos()) as f32,
+            elevation.sin() as f32,
+            (-azimuth.cos() * elevation.cos()) as f32,
+        )
+    }
+
+    pub fn is_up(&self) -> bool {
+        self.elevation > 0.0
+    }
+}
+
+/// The sun's position seen from `lat`, `lon` (WGS84 degrees) at `unix` seconds
+/// UTC, using NOAA's solar calculator equations, which are good to well under a
+/// degree for the next few centuries. Elevation includes atmospheric refraction.
+pub fn sun_position(unix: f64, lat: f64, lon: f64) -> SunPosition {
+    let julian_day = unix / 86_400.0 + 2_440_587.5;
+    let t = (julian_day - 2_451_545.0) / 36_525.0;
+
+    // Mean longitude and anomaly, and the eccentricity of the Earth's orbit.
+    let l0 = (280.46646 + t * (36000.76983 + t * 0.0003032))
+        .rem_euclid(360.0)
+        .to_radians();
+    let m = (357.52911 + t * (35999.05029 - t * 0.0001537)).to_radians();
+    let e = 0.016708634 - t * (0.000042037 + t * 0.0000001267);
+
+    let centre = m.sin() * (1.914602 - t * (0.004817 + t * 0.000014))
+        + (2.0 * m).sin() * (0.019993 - t * 0.000101)
+        + (3.0 * m).sin() * 0.000289;
+    let omega = (125.04 - 1934.136 * t).to_radians();
+    let apparent_longitude =
+        (l0.to_degrees() + centre - 0.00569 - 0.00478 * omega.sin()).to_radians();
+    let mean_obliquity =
+        23.0 + (26.0 + (21.448 - t * (46.815 + t * (0.00059 - t * 0.001813))) / 60.0) / 60.0;
+    let obliquity = (mean_obliquity + 0.00256 * omega.cos()).to_radians();
+    let declination = (obliquity.sin() * apparent_longitude.sin()).asin();
+
+    // Equation of time, in minutes.
+    let y = (obliquity / 2.0).tan().powi(2);
+    let equation_of_time = 4.0
+        * (y * (2.0 * l0).sin() - 2.0 * e * m.sin() + 4.0 * e * y * m.sin() * (2.0 * l0).cos()
+            - 0.5 * y * y * (4.0 * l0).sin()
+            - 1.25 * e * e * (2.0 * m).sin())
+        .to_degrees();
+
+    let minutes = unix.rem_euclid(86_400.0) / 60.0;
+    let solar_time = (minutes + equation_of_time + 4.0 * lon).rem_euclid(1440.0);
+    let hour_angle = (solar_time / 4.0 - 180.0).to_radians();
+
+    let lat = lat.to_radians();
+    let zenith = (lat.sin() * declination.sin() + lat.cos() * declination.cos() * hour_angle.cos())
+        .clamp(-1.0, 1.0)
+        .acos();
+    let azimuth = hour_angle
+        .sin()
+        .atan2(hour_angle.cos() * lat.sin() - declination.tan() * lat.cos())
+        .to_degrees()
+        + 180.0;
+
+    let elevation = 90.0 - zenith.to_degrees();
+    SunPosition {
+        azimuth: azimuth.rem_euclid(360.0),
+        elevation: elevation + refraction(elevation),
+    }
+}
+
+/// Bennett's formula for how far the atmosphere lifts the sun, in degrees.
+fn refraction(elevation: f64) -> f64 {
+    if elevation < -1.0 {
+        return 0.0;
+    }
+    (elevation + 7.31 / (elevation + 4.4))
+        .to_radians()
+        .tan()
+        .recip()
+        / 60.0
+}
+
+/// Centre of the loaded tile as (lat, lon), which is where the sun is worked out for.
+pub fn location() -> (f64, f64) {
+    let [west, south, east, north] = crate::tile_bounds();
+    ((south + north) / 2.0, (west + east) / 2.0)
+}
+
+// ---------------------------------------------------------------------------
+// Calendar
+// ---------------------------------------------------------------------------
+
+/// Days since 1970-01-01 in the proleptic Gregorian calendar.
+pub fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Inverse of `days_from_civil`: (year, month, day).
+pub fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Seconds since the Unix epoch, from the system clock.
+fn now() -> f64 {
+    #[cfg(target_arch = "wasm32")]
+    {
+        js_sys::Date::now() / 1000.0
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0.0, |d| d.as_secs_f64())
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Clock
+// ---------------------------------------------------------------------------
+
+/// The moment the sun is shown for.
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct SunClock {
+    /// Seconds since the Unix epoch, UTC.
+    pub unix: f64,
+    /// Hours ahead of UTC for the date and time shown and typed in. Defaults to
+    /// the solar time zone of the loaded area (no daylight saving).
+    pub utc_offset: f64,
+}
+
+impl SunClock {
+    /// `--date 2024-06-21`, `--time 15:30` (local) and `--utc-offset -6`; the
+    /// date and time default to now.
+    pub fn from_args(args: &[String]) -> Self {
+        let value = |flag| arg_value(args, flag);
+        let utc_offset = value("--utc-offset")
+            .and_then(|o| o.parse().ok())
+            .unwrap_or_else(|| (location().1 / 15.0).round());
+        let mut clock = Self {
+            unix: now(),
+            utc_offset,
+        };
+        let (today, time_of_day) = clock.local();
+        let day = value("--date")
+            .and_then(|d| {
+                let mut parts = d.splitn(3, '-').map(|p| p.parse::<i64>().ok());
+                Some(days_from_civil(
+                    parts.next()??,
+                    parts.next()??,
+                    parts.next()??,
+                ))
+            })
+            .unwrap_or(today);
+        let time_of_day = value("--time")
+            .and_then(|t| {
+                let (hour, minute) = t.split_once(':')?;
+                Some(hour.parse::<f64>().ok()? * 3600.0 + minute.parse::<f64>().ok()? * 60.0)
+            })
+            .unwrap_or(time_of_day);
+        clock.set_local(day, time_of_day);
+        clock
+    }
+
+    /// Local day (since 1970-01-01) and seconds into it.
+    pub fn local(&self) -> (i64, f64) {
+        let local = self.unix + self.utc_offset * 3600.0;
+        let day = local.div_euclid(86_400.0);
+        (day as i64, local - day * 86_400.0)
+    }
+
+    pub fn set_local(&mut self, day: i64, seconds: f64) {
+        self.unix = day as f64 * 86_400.0 + seconds - self.utc_offset * 3600.0;
+    }
+
+    pub fn position(&self) -> SunPosition {
+        let (lat, lon) = location();
+        sun_position(self.unix, lat, lon)
+    }
+
+    /// `2024-06-21 15:30 UTC-6`.
+    pub fn label(&self) -> String {
+        let (day, seconds) = self.local();
+        let (year, month, date) = civil_from_days(day);
+        let minutes = (seconds / 60.0) as i64;
+        let offset = (self.utc_offset * 60.0).round() as i64;
+        let zone = match (offset.abs() / 60, offset.abs() % 60) {
+            (0, 0) => "UTC".to_string(),
+            (h, 0) => format!("UTC{}{h}", if offset < 0 { '-' } else { '+' }),
+            (h, m) => format!("UTC{}{h}:{m:02}", if offset < 0 { '-' } else { '+' }),
+        };
+        format!(
+            "{year}-{month:02}-{date:02} {:02}:{:02} {zone}",
+            minutes / 60,
+            minutes % 60
+        )
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Plugin
+// ---------------------------------------------------------------------------
+
+/// The directional light standing in for the sun.
+#[derive(Component)]
+pub struct Sun;
+
+#[derive(Component)]
+struct SunText;
+
+pub struct SunPlugin;
+
+impl Plugin for SunPlugin {
+    fn build(&self, app: &mut App) {
+        let args: Vec<String> = std::env::args().collect();
+        app.insert_resource(SunClock::from_args(&args))
+            .add_systems(Startup, (spawn_sun, spawn_sun_text))
+            .add_systems(
+                Update,
+                (
+                    sun_controls.run_if(not(filter::editing_filter)),
+                    (place_sun, show_sun_text).run_if(resource_changed::<SunClock>),
+                )
+                    .chain(),
+            );
+    }
+}
+
+fn spawn_sun(mut commands: Commands) {
+    commands.spawn((
+        Sun,
+        DirectionalLight {
+            shadows_enabled: true,
+            illuminance: FULL_SUN,
+            ..default()
+        },
+        Transform::default(),
+    ));
+}
+
+/// Brackets (or the bumpers) run the clock back and forward through the day;
+/// with shift (or the triggers) they step a day at a time.
+fn sun_controls(
+    keys: Res<ButtonInput<KeyCode>>,
+    gamepads: Query<&Gamepad>,
+    time: Res<Time>,
+    mut clock: ResMut<SunClock>,
+) {
+    let shift = keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    let mut hours = 0.0;
+    let mut days = 0;
+    if shift {
+        days += i64::from(keys.just_pressed(KeyCode::BracketRight));
+        days -= i64::from(keys.just_pressed(KeyCode::BracketLeft));
+    } else {
+        hours += f64::from(u8::from(keys.pressed(KeyCode::BracketRight)));
+        hours -= f64::from(u8::from(keys.pressed(KeyCode::BracketLeft)));
+    }
+    for gamepad in gamepads {
+        hours += f64::from(u8::from(gamepad.pressed(GamepadButton::RightTrigger)));
+        hours -= f64::from(u8::from(gamepad.pressed(GamepadButton::LeftTrigger)));
+        days += i64::from(gamepad.just_pressed(GamepadButton::RightTrigger2));
+        days -= i64::from(gamepad.just_pressed(GamepadButton::LeftTrigger2));
+    }
+    if hours != 0.0 || days != 0 {
+        clock.unix +=
+            hours * HOURS_PER_SECOND * 3600.0 * time.delta_secs_f64() + days as f64 * 86_400.0;
+    }
+}
+
+/// Points the light along the sun's rays and sets how bright and warm it is
+/// for its height in the sky.
+fn place_sun(
+    clock: Res<SunClock>,
+    sun: Single<(&mut DirectionalLight, &mut Transform), With<Sun>>,
+    mut ambient: ResMut<GlobalAmbientLight>,
+) {
+    let position = clock.position();
+    let (mut light, mut transform) = sun.into_inner();
+    let towards = position.direction();
+    // Light below the horizon would shine up through the ground, so keep it
+    // skimming the horizon and let it fade out instead.
+    let towards = Vec3::new(towards.x, towards.y.max(0.01), towards.z).normalize();
+    let up = if towards.y > 0.99 {
+        Vec3::NEG_Z
+    } else {
+        Vec3::Y
+    };
+    *transform = Transform::default().looking_to(-towards, up);
+
+    let elevation = position.elevation as f32;
+    light.illuminance = if position.is_up() {
+        // Kasten and Young's air mass, and Meinel's attenuation through it,
+        // relative to the sun overhead.
+        let zenith = 90.0 - elevation;
+        let air_mass =
+            1.0 / (zenith.to_radians().cos() + 0.50572 * (96.07995 - zenith).powf(-1.6364));
+        let attenuation = 0.7f32.powf(air_mass.powf(0.678)) / 0.7;
+        FULL_SUN * attenuation * (elevation / 2.0).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let warmth = 1.0 - (elevation / 20.0).clamp(0.0, 1.0);
+    light.color = Color::srgb(1.0, 1.0 - 0.3 * warmth, 1.0 - 0.55 * warmth);
+    ambient.brightness =
+        NIGHT_AMBIENT + (DAY_AMBIENT - NIGHT_AMBIENT) * ((elevation + 6.0) / 12.0).clamp(0.0, 1.0);
+}
+
+fn spawn_sun_text(mut commands: Commands) {
+    commands
+        .spawn(Node {
+            position_type: PositionType::Absolute,
+            top: px(12),
+            width: percent(100),
+            justify_content: JustifyContent::Center,
+            ..default()
+        })
+        .with_child((
+            SunText,
+            Text::default(),
+            TextFont {
+                font_size: 14.0,
+                ..default()
+            },
+            Node {
+                padding: UiRect::all(px(6)),
+                ..default()
+            },
+            BackgroundColor(Color::srgba(0.12, 0.08, 0.16, 0.85)),
+        ));
+}
+
+fn show_sun_text(clock: Res<SunClock>, mut text: Single<&mut Text, With<SunText>>) {
+    let position = clock.position();
+    text.0 = if position.is_up() {
+        format!(
+            "{}  sun {:.0}° high, bearing {:.0}°",
+            clock.label(),
+            position.elevation,
+            position.azimuth
+        )
+    } else {
+        format!("{}  sun below the horizon", clock.label())
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Civic Center, Denver.
+    const DENVER: (f64, f64) = (39.7392, -104.9903);
+
+    fn at(date: (i64, i64, i64), hour: f64) -> SunPosition {
+        let unix = days_from_civil(date.0, date.1, date.2) as f64 * 86_400.0 + hour * 3600.0;
+        sun_position(unix, DENVER.0, DENVER.1)
+    }
+
+    #[test]
+    fn sun_matches_noaa_for_denver() {
+        // Solar noon on the 2024 summer solstice is 13:01:39 MDT, 19:01:39 UTC.
+        let noon = at((2024, 6, 21), 19.0 + 1.0 / 60.0 + 39.0 / 3600.0);
+        assert!((noon.elevation - 73.70).abs() < 0.05, "{noon:?}");
+        assert!((noon.azimuth - 180.0).abs() < 0.5, "{noon:?}");
+        // Sunrise at 05:32 MDT, in the north-east.
+        let sunrise = at((2024, 6, 21), 11.0 + 32.0 / 60.0);
+        assert!(sunrise.elevation.abs() < 0.5, "{sunrise:?}");
+        assert!((sunrise.azimuth - 58.0).abs() < 1.0, "{sunrise:?}");
+        // Winter solstice noon, 11:58 MST: 90 - 39.74 - 23.44, plus refraction.
+        let winter = at((2024, 12, 21), 18.0 + 58.0 / 60.0);
+        assert!((winter.elevation - 26.85).abs() < 0.05, "{winter:?}");
+        assert!(!at((2024, 12, 21), 6.0).is_up());
+    }
+
+    #[test]
+    fn calendar_round_trips() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(days_from_civil(1969, 12, 31), -1);
+        assert_eq!(days_from_civil(2000, 3, 1), 11_017);
+        assert_eq!(civil_from_days(11_017), (2000, 3, 1));
+        // 2000 is a leap year: the 29th of February comes before March.
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        for days in [-1, 0, 11_016, 11_017, 19_895] {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+}
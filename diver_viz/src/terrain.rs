@@ -0,0 +1,462 @@
+use std::io::Cursor;
+
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadState, RenderAssetUsages, io::Reader},
+    image::ImageLoaderSettings,
+    math::DVec2,
+    mesh::{Indices, PrimitiveTopology, VertexAttributeValues},
+    prelude::*,
+};
+use tiff::{decoder::DecodingResult, tags::Tag};
+
+use crate::{
+    TILE_COORD_X, TILE_COORD_Y, TILE_COORD_Z, WorldCenter, arg_value, tile_to_world,
+    world_to_lon_lat,
+};
+
+/// Grid cells along each side of the ground mesh.
+const GROUND_RESOLUTION: usize = 256;
+/// The ground sits just under the landuse slabs.
+const GROUND_OFFSET: f32 = -0.2;
+
+/// How elevation is encoded in the DEM files.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DemFormat {
+    /// Terrarium RGB PNG tiles: `(R * 256 + G + B / 256) - 32768` metres.
+    Terrarium,
+    /// Mapbox Terrain-RGB PNG tiles: `-10000 + (R * 65536 + G * 256 + B) * 0.1` metres.
+    MapboxRgb,
+    /// A single-band GeoTIFF in WGS84 lon/lat, covering at least the loaded tile.
+    GeoTiff,
+}
+
+/// Where the elevation data comes from.
+#[derive(Resource, Debug, Clone)]
+pub struct TerrainSource {
+    pub format: DemFormat,
+    /// Asset path. For PNG tiles `{z}`, `{x}` and `{y}` are filled in with the
+    /// same tile key as the vector tiles.
+    pub path: String,
+    pub exaggeration: f32,
+}
+
+impl Default for TerrainSource {
+    fn default() -> Self {
+        Self {
+            format: DemFormat::Terrarium,
+            path: "terrain/{z}/{x}/{y}.png".to_string(),
+            exaggeration: 1.0,
+        }
+    }
+}
+
+impl TerrainSource {
+    /// `--terrain <path>` picks the elevation data. `.tif`/`.tiff` files are read as
+    /// GeoTIFF, anything else as PNG tiles, Terrarium-encoded unless
+    /// `--terrain-format mapbox` is given. `--terrain-exaggeration <factor>` scales heights.
+    pub fn from_args(args: &[String]) -> Self {
+        let value = |flag| arg_value(args, flag);
+        let mut source = Self::default();
+        if let Some(path) = value("--terrain") {
+            source.path = path.to_string();
+            if path.ends_with(".tif") || path.ends_with(".tiff") {
+                source.format = DemFormat::GeoTiff;
+            }
+        }
+        if value("--terrain-format").is_some_and(|f| f == "mapbox") {
+            source.format = DemFormat::MapboxRgb;
+        }
+        if let Some(factor) = value("--terrain-exaggeration").and_then(|f| f.parse().ok()) {
+            source.exaggeration = factor;
+        }
+        source
+    }
+}
+
+/// Elevation over the loaded tile on a regular grid, in world metres above the
+/// lowest point of the tile (so the scene stays near Y=0).
+#[derive(Resource, Debug, Clone)]
+pub struct Heightmap {
+    /// Samples along each side; the grid covers the whole tile edge to edge.
+    samples: usize,
+    /// World position of sample (0, 0), the tile's north-west corner.
+    origin: Vec2,
+    /// World metres between samples.
+    spacing: f32,
+    heights: Vec<f32>,
+    /// Elevation above sea level of height 0.
+    pub base_elevation: f32,
+}
+
+impl Heightmap {
+    /// Build a grid by sampling `elevation` at each grid point's world position.
+    fn from_fn(
+        samples: usize,
+        center: Vec2,
+        exaggeration: f32,
+        elevation: impl Fn(Vec2) -> Option<f32>,
+    ) -> Option<Self> {
+        let origin = tile_to_world(0.0, 0.0, TILE_COORD_X, TILE_COORD_Y) - center;
+        let far = tile_to_world(4096.0, 4096.0, TILE_COORD_X, TILE_COORD_Y) - center;
+        let spacing = (far.x - origin.x) / (samples - 1) as f32;
+        let raw: Vec<Option<f32>> = (0..samples * samples)
+            .map(|i| {
+                let (col, row) = (i % samples, i / samples);
+                elevation(origin + Vec2::new(col as f32, row as f32) * spacing)
+            })
+            .collect();
+        let base_elevation = raw.iter().flatten().copied().reduce(f32::min)?;
+        let heights = raw
+            .into_iter()
+            .map(|e| (e.unwrap_or(base_elevation) - base_elevation) * exaggeration)
+            .collect();
+        Some(Self {
+            samples,
+            origin,
+            spacing,
+            heights,
+            base_elevation,
+        })
+    }
+
+    /// Height at a world position, bilinear between samples and clamped at the tile edge.
+    pub fn sample(&self, p: Vec2) -> f32 {
+        let max = (self.samples - 1) as f32;
+        let g = ((p - self.origin) / self.spacing).clamp(Vec2::ZERO, Vec2::splat(max));
+        let (c0, r0) = (g.x.floor() as usize, g.y.floor() as usize);
+        let (c1, r1) = (
+            (c0 + 1).min(self.samples - 1),
+            (r0 + 1).min(self.samples - 1),
+        );
+        let f = g - Vec2::new(c0 as f32, r0 as f32);
+        let h = |c: usize, r: usize| self.heights[r * self.samples + c];
+        let top = h(c0, r0) + (h(c1, r0) - h(c0, r0)) * f.x;
+        let bottom = h(c0, r1) + (h(c1, r1) - h(c0, r1)) * f.x;
+        top + (bottom - top) * f.y
+    }
+
+    /// Lowest ground under any of the points.
+    pub fn lowest(&self, points: &[Vec2]) -> f32 {
+        points
+            .iter()
+            .map(|p| self.sample(*p))
+            .reduce(f32::min)
+            .unwrap_or(0.0)
+    }
+
+    fn ground_mesh(&self) -> Mesh {
+        let n = GROUND_RESOLUTION.min(self.samples);
+        let step = (self.samples - 1) as f32 / (n - 1) as f32;
+        let mut positions = Vec::with_capacity(n * n);
+        let mut uvs = Vec::with_capacity(n * n);
+        for row in 0..n {
+            for col in 0..n {
+                let p = self.origin + Vec2::new(col as f32, row as f32) * step * self.spacing;
+                positions.push([p.x, self.sample(p), p.y]);
+                uvs.push([col as f32 / (n - 1) as f32, row as f32 / (n - 1) as f32]);
+            }
+        }
+        let mut indices = Vec::with_capacity((n - 1) * (n - 1) * 6);
+        for row in 0..n - 1 {
+            for col in 0..n - 1 {
+                let i = (row * n + col) as u32;
+                let (right, down) = (i + 1, i + n as u32);
+                // Rows run south (+Z), so this winding faces +Y.
+                indices.extend([i, down, right, right, down, down + 1]);
+            }
+        }
+        let mut mesh = Mesh::new(
+            PrimitiveTopology::TriangleList,
+            RenderAssetUsages::default(),
+        );
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+        mesh.insert_indices(Indices::U32(indices));
+        mesh.compute_normals();
+        mesh
+    }
+}
+
+/// Where a ray (say, from the cursor) meets the ground: the flat plane at Y=0, or
+/// the terrain once it has loaded, found by re-intersecting at the ground height a
+/// few times.
+pub fn ground_point(ray: Ray3d, heightmap: Option<&Heightmap>) -> Option<Vec2> {
+    let mut ground = 0.0;
+    let mut hit = None;
+    for _ in 0..3 {
+        let distance = ray.intersect_plane(Vec3::Y * ground, InfinitePlane3d::new(Vec3::Y))?;
+        let p = ray.get_point(distance).xz();
+        hit = Some(p);
+        let Some(heightmap) = heightmap else {
+            break;
+        };
+        ground = heightmap.sample(p);
+    }
+    hit
+}
+
+/// How a spawned feature is placed once the heightmap is ready.
+#[derive(Component, Debug, Clone)]
+pub enum OnTerrain {
+    /// Lift the whole entity to the lowest ground under these world points,
+    /// so buildings and supports never float.
+    Footprint(Vec<Vec2>),
+    /// Add the ground height to every vertex, so flat features follow the slope.
+    Drape,
+}
+
+/// A raster of elevations in WGS84 lon/lat, as read from a GeoTIFF.
+#[derive(Asset, TypePath, Debug)]
+pub struct ElevationRaster {
+    width: usize,
+    height: usize,
+    data: Vec<f32>,
+    /// Lon/lat of the top-left corner of the top-left pixel.
+    origin: DVec2,
+    /// Degrees per pixel; `y` is positive going south.
+    pixel_size: DVec2,
+    nodata: Option<f32>,
+}
+
+impl ElevationRaster {
+    fn sample(&self, lon: f64, lat: f64) -> Option<f32> {
+        let px = (lon - self.origin.x) / self.pixel_size.x - 0.5;
+        let py = (self.origin.y - lat) / self.pixel_size.y - 0.5;
+        if px < 0.0 || py < 0.0 || px > (self.width - 1) as f64 || py > (self.height - 1) as f64 {
+            return None;
+        }
+        let (x0, y0) = (px.floor() as usize, py.floor() as usize);
+        let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+        let (fx, fy) = ((px - x0 as f64) as f32, (py - y0 as f64) as f32);
+        let v = |x: usize, y: usize| {
+            let e = self.data[y * self.width + x];
+            (Some(e) != self.nodata && e.is_finite()).then_some(e)
+        };
+        let top = v(x0, y0)? + (v(x1, y0)? - v(x0, y0)?) * fx;
+        let bottom = v(x0, y1)? + (v(x1, y1)? - v(x0, y1)?) * fx;
+        Some(top + (bottom - top) * fy)
+    }
+}
+
+#[derive(Default, TypePath)]
+pub struct GeoTiffLoader;
+
+impl AssetLoader for GeoTiffLoader {
+    type Asset = ElevationRaster;
+    type Settings = ();
+    type Error = std::io::Error;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<ElevationRaster, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let mut decoder = tiff::decoder::Decoder::new(Cursor::new(bytes)).map_err(tiff_error)?;
+        let (width, height) = decoder.dimensions().map_err(tiff_error)?;
+        let scale = decoder
+            .get_tag_f64_vec(Tag::ModelPixelScaleTag)
+            .map_err(tiff_error)?;
+        let tiepoint = decoder
+            .get_tag_f64_vec(Tag::ModelTiepointTag)
+            .map_err(tiff_error)?;
+        let nodata = decoder
+            .get_tag_ascii_string(Tag::GdalNodata)
+            .ok()
+            .and_then(|s| s.trim_matches(char::from(0)).trim().parse().ok());
+        if scale.len() < 2 || tiepoint.len() < 6 {
+            return Err(std::io::Error::other(
+                "GeoTIFF is missing its georeferencing",
+            ));
+        }
+        let data: Vec<f32> = match decoder.read_image().map_err(tiff_error)? {
+            DecodingResult::F32(v) => v,
+            DecodingResult::F64(v) => v.into_iter().map(|e| e as f32).collect(),
+            DecodingResult::I16(v) => v.into_iter().map(f32::from).collect(),
+            DecodingResult::U16(v) => v.into_iter().map(f32::from).collect(),
+            DecodingResult::I32(v) => v.into_iter().map(|e| e as f32).collect(),
+            _ => return Err(std::io::Error::other("unsupported GeoTIFF sample type")),
+        };
+        // Tiepoint maps raster (i, j) to model (x, y); shift it back to pixel (0, 0).
+        let origin = DVec2::new(
+            tiepoint[3] - tiepoint[0] * scale[0],
+            tiepoint[4] + tiepoint[1] * scale[1],
+        );
+        Ok(ElevationRaster {
+            width: width as usize,
+            height: height as usize,
+            data,
+            origin,
+            pixel_size: DVec2::new(scale[0], scale[1]),
+            nodata,
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["tif", "tiff"]
+    }
+}
+
+fn tiff_error(e: tiff::TiffError) -> std::io::Error {
+    std::io::Error::other(e)
+}
+
+#[derive(Resource)]
+enum PendingTerrain {
+    Png(Handle<Image>),
+    GeoTiff(Handle<ElevationRaster>),
+}
+
+pub struct TerrainPlugin;
+
+impl Plugin for TerrainPlugin {
+    fn build(&self, app: &mut App) {
+        let args: Vec<String> = std::env::args().collect();
+        app.insert_resource(TerrainSource::from_args(&args))
+            .init_asset::<ElevationRaster>()
+            .init_asset_loader::<GeoTiffLoader>()
+            .add_systems(Startup, request_terrain)
+            .add_systems(Update, (build_terrain, settle_on_terrain).chain());
+    }
+}
+
+fn request_terrain(mut commands: Commands, source: Res<TerrainSource>, assets: Res<AssetServer>) {
+    let path = source
+        .path
+        .replace("{z}", &TILE_COORD_Z.to_string())
+        .replace("{x}", &TILE_COORD_X.to_string())
+        .replace("{y}", &TILE_COORD_Y.to_string());
+    info!("Loading elevation from {}", path);
+    commands.insert_resource(match source.format {
+        DemFormat::GeoTiff => PendingTerrain::GeoTiff(assets.load(path)),
+        DemFormat::Terrarium | DemFormat::MapboxRgb => PendingTerrain::Png(
+            assets.load_with_settings(path, |s: &mut ImageLoaderSettings| {
+                s.is_srgb = false;
+                s.asset_usage = RenderAssetUsages::MAIN_WORLD;
+            }),
+        ),
+    });
+}
+
+/// Decode one RGB-encoded elevation pixel.
+fn decode_rgb(format: DemFormat, rgb: &[u8]) -> f32 {
+    let (r, g, b) = (rgb[0] as f32, rgb[1] as f32, rgb[2] as f32);
+    match format {
+        DemFormat::MapboxRgb => -10000.0 + (r * 65536.0 + g * 256.0 + b) * 0.1,
+        _ => r * 256.0 + g + b / 256.0 - 32768.0,
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn build_terrain(
+    mut commands: Commands,
+    pending: Option<Res<PendingTerrain>>,
+    center: Option<Res<WorldCenter>>,
+    source: Res<TerrainSource>,
+    assets: Res<AssetServer>,
+    images: Res<Assets<Image>>,
+    rasters: Res<Assets<ElevationRaster>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut camera: Query<&mut Transform, With<Camera3d>>,
+) {
+    let (Some(pending), Some(center)) = (pending, center) else {
+        return;
+    };
+    let id = match &*pending {
+        PendingTerrain::Png(h) => h.id().untyped(),
+        PendingTerrain::GeoTiff(h) => h.id().untyped(),
+    };
+    if let Some(LoadState::Failed(err)) = assets.get_load_state(id) {
+        warn!("No elevation data ({}), keeping the ground flat", err);
+        commands.remove_resource::<PendingTerrain>();
+        return;
+    }
+
+    let heightmap = match &*pending {
+        PendingTerrain::Png(handle) => {
+            let Some(image) = images.get(handle) else {
+                return;
+            };
+            let size = image.width() as usize;
+            Heightmap::from_fn(size, center.0, source.exaggeration, |p| {
+                // The PNG covers the same tile as the vector data, pixel centres inset half a pixel.
+                let origin = tile_to_world(0.0, 0.0, TILE_COORD_X, TILE_COORD_Y) - center.0;
+                let far = tile_to_world(4096.0, 4096.0, TILE_COORD_X, TILE_COORD_Y) - center.0;
+                let f = (p - origin) / (far - origin) * size as f32;
+                let px = f
+                    .as_uvec2()
+                    .min(UVec2::new(image.width() - 1, image.height() - 1));
+                image
+                    .pixel_bytes(px.extend(0))
+                    .map(|rgb| decode_rgb(source.format, rgb))
+            })
+        }
+        PendingTerrain::GeoTiff(handle) => {
+            let Some(raster) = rasters.get(handle) else {
+                return;
+            };
+            Heightmap::from_fn(GROUND_RESOLUTION, center.0, source.exaggeration, |p| {
+                let (lon, lat) = world_to_lon_lat(p, center.0);
+                raster.sample(lon, lat)
+            })
+        }
+    };
+    commands.remove_resource::<PendingTerrain>();
+    let Some(heightmap) = heightmap else {
+        warn!("Elevation data didn't cover the loaded tile, keeping the ground flat");
+        return;
+    };
+    info!(
+        "✓ Terrain loaded, base elevation {:.0} m",
+        heightmap.base_elevation
+    );
+
+    commands.spawn((
+        Mesh3d(meshes.add(heightmap.ground_mesh())),
+        MeshMaterial3d(materials.add(StandardMaterial {
+            base_color: Color::srgb(0.55, 0.47, 0.50),
+            perceptual_roughness: 1.0,
+            ..default()
+        })),
+        Transform::from_xyz(0.0, GROUND_OFFSET, 0.0),
+    ));
+    for mut cam in &mut camera {
+        cam.translation.y += heightmap.sample(cam.translation.xz());
+    }
+    commands.insert_resource(heightmap);
+}
+
+/// Put features spawned flat onto the ground, once there is ground to put them on.
+fn settle_on_terrain(
+    mut commands: Commands,
+    heightmap: Option<Res<Heightmap>>,
+    mut placed: Query<(Entity, &OnTerrain, &mut Transform, Option<&Mesh3d>)>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    let Some(heightmap) = heightmap else {
+        return;
+    };
+    for (entity, on_terrain, mut transform, mesh) in &mut placed {
+        match on_terrain {
+            OnTerrain::Footprint(points) => {
+                transform.translation.y += heightmap.lowest(points);
+            }
+            OnTerrain::Drape => {
+                let Some(mesh) = mesh.and_then(|m| meshes.get_mut(&m.0)) else {
+                    continue;
+                };
+                if let Some(VertexAttributeValues::Float32x3(positions)) =
+                    mesh.attribute_mut(Mesh::ATTRIBUTE_POSITION)
+                {
+                    for p in positions.iter_mut() {
+                        p[1] += heightmap.sample(Vec2::new(p[0], p[2]));
+                    }
+                }
+            }
+        }
+        commands.entity(entity).remove::<OnTerrain>();
+    }
+}
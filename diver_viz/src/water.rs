@@ -0,0 +1,20 @@
+/// Top of water surfaces: above the landuse slabs, so ponds inside parks show,
+/// and a little under the ground and roads around them.
+pub const WATER_SURFACE_HEIGHT: f32 = -0.01;
+/// Water polygons are thin slabs, so their edges read as banks from street level.
+pub const WATER_DEPTH: f32 = 0.05;
+
+/// Width of a waterway line in metres: the `width` tag if it has one, otherwise a
+/// typical width for its class (`kind_detail`, falling back to `kind`).
+pub fn waterway_width(width: Option<f64>, class: &str) -> f32 {
+    if let Some(w) = width.filter(|w| *w > 0.0) {
+        return w as f32;
+    }
+    match class {
+        "river" => 20.0,
+        "canal" => 10.0,
+        "stream" => 4.0,
+        "ditch" | "drain" => 2.0,
+        _ => 3.0,
+    }
+}